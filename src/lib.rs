@@ -1,7 +1,247 @@
-use rs6502::Disassembler;
+pub mod machine;
 
-pub fn disassemble(code: &Vec<u8>) -> String {
-    let dasm = Disassembler::new();
-    let asm = dasm.disassemble(&code);
-    asm
+use std::fmt;
+
+use machine::{
+    bus::Bus,
+    instruction::{AddressingMode, OPCODE_TABLE},
+};
+
+/* One decoded instruction, as the disassembler shows it */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub operand: String,
+    pub illegal: bool,
+}
+
+impl Disassembly {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.len())
+    }
+
+    /* The instruction alone, with undocumented opcodes marked by a '*' */
+    pub fn text(&self) -> String {
+        let marker = if self.illegal { "*" } else { "" };
+        if self.operand.is_empty() {
+            format!("{}{}", marker, self.mnemonic)
+        } else {
+            format!("{}{} {}", marker, self.mnemonic, self.operand)
+        }
+    }
+}
+
+/* "C000  B1 12     LDA ($12),Y" */
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "{:04X}  {:<8}  {}",
+            self.addr,
+            bytes.join(" "),
+            self.text()
+        )
+    }
+}
+
+/* Decodes the instruction at addr through the same table the CPU uses, with peeks only */
+pub fn disassemble_one(bus: &dyn Bus, addr: u16) -> Disassembly {
+    let opcode = bus.peek(addr);
+    let (decode, mode) = OPCODE_TABLE[opcode as usize];
+    let size = mode.operand_size();
+    let bytes: Vec<u8> = (0..=size as u16)
+        .map(|offset| bus.peek(addr.wrapping_add(offset)))
+        .collect();
+    let operand = match size {
+        0 => 0,
+        1 => bytes[1] as u16,
+        _ => u16::from_le_bytes([bytes[1], bytes[2]]),
+    };
+    let inst = decode(opcode, operand, size, mode.operand_type());
+
+    Disassembly {
+        addr,
+        mnemonic: inst.mnemonic(),
+        operand: format_operand(mode, operand, addr.wrapping_add(bytes.len() as u16)),
+        illegal: inst.is_illegal(),
+        bytes,
+    }
+}
+
+fn format_operand(mode: AddressingMode, operand: u16, next_addr: u16) -> String {
+    match mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02X}", operand),
+        AddressingMode::ZeroPage => format!("${:02X}", operand),
+        AddressingMode::ZeroPageX => format!("${:02X},X", operand),
+        AddressingMode::ZeroPageY => format!("${:02X},Y", operand),
+        AddressingMode::Absolute => format!("${:04X}", operand),
+        AddressingMode::AbsoluteX => format!("${:04X},X", operand),
+        AddressingMode::AbsoluteY => format!("${:04X},Y", operand),
+        AddressingMode::Indirect => format!("(${:04X})", operand),
+        AddressingMode::IndexedIndirect => format!("(${:02X},X)", operand),
+        AddressingMode::IndirectIndexed => format!("(${:02X}),Y", operand),
+        /* Branches show where they go rather than the offset */
+        AddressingMode::Relative => {
+            format!(
+                "${:04X}",
+                next_addr.wrapping_add(operand as u8 as i8 as u16)
+            )
+        }
+    }
+}
+
+/* `count` instructions in a row starting at addr, wrapping at $FFFF */
+pub fn disassemble_range(bus: &dyn Bus, start: u16, count: usize) -> Vec<Disassembly> {
+    let mut addr = start;
+    (0..count)
+        .map(|_| {
+            let line = disassemble_one(bus, addr);
+            addr = line.next_addr();
+            line
+        })
+        .collect()
+}
+
+/* Code loaded at `origin`, for disassembling a byte slice without a bus */
+struct SliceBus<'a> {
+    origin: u16,
+    code: &'a [u8],
+}
+
+impl Bus for SliceBus<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn write(&mut self, _addr: u16, _data: u8) {}
+
+    fn peek(&self, addr: u16) -> u8 {
+        let offset = addr.wrapping_sub(self.origin) as usize;
+        self.code.get(offset).copied().unwrap_or(0)
+    }
+}
+
+/*
+ * Every instruction in the slice, one per line, as if it were loaded at
+ * `origin`. An instruction cut off by the end of the slice is shown as
+ * plain bytes.
+ */
+pub fn disassemble_at(code: &[u8], origin: u16) -> String {
+    let bus = SliceBus { origin, code };
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let mut line = disassemble_one(&bus, origin.wrapping_add(offset as u16));
+        if offset + line.bytes.len() > code.len() {
+            line.bytes.truncate(code.len() - offset);
+            line.mnemonic = ".byte";
+            line.operand = line
+                .bytes
+                .iter()
+                .map(|b| format!("${:02X}", b))
+                .collect::<Vec<_>>()
+                .join(",");
+            line.illegal = false;
+        }
+        offset += line.bytes.len();
+        lines.push(line.to_string());
+    }
+    lines.join("\n")
+}
+
+pub fn disassemble(code: &[u8]) -> String {
+    disassemble_at(code, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uses_canonical_syntax() {
+        let code = [
+            0xA9, 0x01, /* LDA #$01 */
+            0xB1, 0x12, /* LDA ($12),Y */
+            0xA1, 0x34, /* LDA ($34,X) */
+            0xB6, 0x56, /* LDX $56,Y */
+            0x9D, 0x00, 0x02, /* STA $0200,X */
+            0x6C, 0xFC, 0xFF, /* JMP ($FFFC) */
+            0x0A, /* ASL A */
+            0x60, /* RTS */
+        ];
+        let texts: Vec<String> = disassemble_range(
+            &SliceBus {
+                origin: 0x8000,
+                code: &code,
+            },
+            0x8000,
+            8,
+        )
+        .iter()
+        .map(|line| line.text())
+        .collect();
+        assert_eq!(
+            texts,
+            vec![
+                "LDA #$01",
+                "LDA ($12),Y",
+                "LDA ($34,X)",
+                "LDX $56,Y",
+                "STA $0200,X",
+                "JMP ($FFFC)",
+                "ASL A",
+                "RTS"
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_branch_targets() {
+        /* BNE back to itself, BEQ forward over a NOP */
+        let listing = disassemble_at(&[0xD0, 0xFE, 0xF0, 0x01, 0xEA], 0xC000);
+        assert_eq!(
+            listing,
+            "C000  D0 FE     BNE $C000\n\
+             C002  F0 01     BEQ $C005\n\
+             C004  EA        NOP"
+        );
+    }
+
+    #[test]
+    fn marks_illegal_opcodes_and_cut_off_bytes() {
+        let listing = disassemble(&[0x07, 0x12, 0x80, 0x00, 0xEB, 0x05, 0xAD, 0x00]);
+        assert_eq!(
+            listing,
+            "0000  07 12     *SLO $12\n\
+             0002  80 00     *NOP #$00\n\
+             0004  EB 05     *SBC #$05\n\
+             0006  AD 00     .byte $AD,$00"
+        );
+    }
+
+    #[test]
+    fn disassembles_a_live_bus() {
+        use machine::bus::NesBus;
+
+        let mut bus = NesBus::new();
+        bus.write(0x0000, 0x2C);
+        bus.write(0x0001, 0x02);
+        bus.write(0x0002, 0x20);
+        let line = disassemble_one(&bus, 0x0000);
+        assert_eq!(line.to_string(), "0000  2C 02 20  BIT $2002");
+        assert_eq!(line.next_addr(), 0x0003);
+    }
 }
@@ -1,53 +1,225 @@
-mod cpu;
-mod instruction;
-mod memory;
-mod monitor;
-use std::{io::Write, process::exit};
-
-use cpu::CPU;
-use memory::Memory;
+pub mod apu;
+pub mod battery;
+pub mod bus;
+pub mod capture;
+pub mod cartridge;
+pub mod controller;
+pub mod cpu;
+pub mod debugger;
+pub mod instruction;
+pub mod mapper;
+pub mod memory;
+pub mod monitor;
+pub mod movie;
+pub mod ppu;
+pub mod rewind;
+pub mod state;
+pub mod wav;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use crate::{disassemble_one, disassemble_range};
+use battery::{battery_path, load_battery, save_battery, BATTERY_FLUSH_FRAMES};
+use bus::{Bus, NesBus};
+use capture::{save_frame, CaptureOptions, ImageFormat};
+use cartridge::Cartridge;
+use controller::ButtonState;
+use cpu::{IrqSource, OperandType, CPU};
+use debugger::{
+    hex_dump, listing_line, listing_start, parse_assignment, parse_expr, Breakpoint, DebugTarget,
+    Debugger, Place, Register, RunUntil, WatchBus, WatchKind,
+};
+use instruction::Instruction;
+use mapper::new_mapper;
 use monitor::{Monitor, MonitorState};
+use movie::{Movie, MovieFrame};
+use rewind::{RewindBuffer, RewindMark, DEFAULT_REWIND_BUDGET};
+use state::{fingerprint, Snapshot, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+use wav::{save_wav, SampleEncoding};
 
 pub struct Machine {
     cpu: CPU,
-    memory: Memory,
-    reset: bool,
+    bus: NesBus,
     stop: bool,
     debug: bool,
+    debugger: Debugger,
+    running: Option<RunUntil>,
+    frame_limit: Option<u64>,
+    capture: CaptureOptions,
+    audio: Option<Vec<f32>>,
+    wav_path: Option<String>,
+    wav_encoding: SampleEncoding,
+    recording: Option<Movie>,
+    playback: Option<(Movie, usize)>,
+    movie_path: Option<String>,
+    pending_commands: u8,
+    input_frame: Option<u64>,
+    rom_fingerprint: u64,
+    load_state_path: Option<String>,
+    save_state_path: Option<String>,
+    rewind: Option<RewindBuffer>,
+    battery: bool,
+    battery_path: Option<PathBuf>,
+    battery_written: Vec<u8>,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Machine {
     pub fn new() -> Self {
         Machine {
             cpu: CPU::new(),
-            memory: Memory::new(),
-            reset: false,
+            bus: NesBus::new(),
             stop: false,
 
             debug: true, /* TODO: Don't go to debug mode by defalt */
+            debugger: Debugger::new(),
+            running: None,
+            frame_limit: None,
+            capture: CaptureOptions::default(),
+            audio: None,
+            wav_path: None,
+            wav_encoding: SampleEncoding::Pcm16,
+            recording: None,
+            playback: None,
+            movie_path: None,
+            pending_commands: 0,
+            input_frame: None,
+            rom_fingerprint: 0,
+            load_state_path: None,
+            save_state_path: None,
+            rewind: None,
+            battery: false,
+            battery_path: None,
+            battery_written: Vec::new(),
         }
     }
 
     fn print_help() {
-        println!("Usage: nesemu [options]");
+        println!("Usage: nesemu [options] <rom.nes>");
         println!("Options:");
         println!("\t-d\t\tEnable debug mode");
         println!("\t-h\t\tPrint this help message");
+        println!("\t--frames <n>\tRun headless for n frames, then exit");
+        println!("\t--capture <list>\tSave the given frames, e.g. 1,60,120");
+        println!("\t--capture-every <n>\tSave every nth frame");
+        println!("\t--capture-dir <dir>\tWhere to save frames (default: .)");
+        println!("\t--capture-format <fmt>\tppm or png (default: png)");
+        println!("\t--wav <file>\t\tRecord the audio to a WAV file");
+        println!("\t--wav-format <fmt>\ts16 or f32 (default: s16)");
+        println!("\t--sample-rate <hz>\tAudio sample rate (default: 44100)");
+        println!("\t--record-movie <file>\tRecord input and resets to an FM2 movie");
+        println!("\t--play-movie <file>\tPlay back an FM2 movie, for its length unless --frames");
+        println!("\t--load-state <file>\tStart from a save state");
+        println!("\t--save-state <file>\tWrite a save state when the run ends");
+        println!("\t--save-dir <dir>\tWhere battery saves go (default: next to the ROM)");
+        println!("\t--rewind <n>\t\tKeep a rewind snapshot every n frames");
+        println!("\t--rewind-budget <MiB>\tMemory for rewind snapshots (default: 32)");
         exit(0);
     }
 
-    pub fn new_from_args(args: &Vec<String>) -> Self {
+    pub fn new_from_args(args: &[String]) -> Result<Self, String> {
         let mut machine = Machine::new();
+        let mut rom_path = None;
+        let mut rewind_interval = None;
+        let mut rewind_budget = DEFAULT_REWIND_BUDGET;
+        let mut save_dir = None;
 
-        for arg in args {
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
             match arg.as_str() {
                 "-d" => machine.set_debug(true),
                 "-h" => Machine::print_help(),
-                _ => (),
+                "--frames" => {
+                    machine.frame_limit = Some(parse_count(arg, option_value(&mut args, arg)?)?)
+                }
+                "--capture" => {
+                    machine.capture.frames = option_value(&mut args, arg)?
+                        .split(',')
+                        .map(|frame| parse_count(arg, frame))
+                        .collect::<Result<_, _>>()?
+                }
+                "--capture-every" => {
+                    machine.capture.every = Some(parse_count(arg, option_value(&mut args, arg)?)?)
+                }
+                "--capture-dir" => machine.capture.dir = option_value(&mut args, arg)?.to_string(),
+                "--capture-format" => {
+                    machine.capture.format = ImageFormat::parse(option_value(&mut args, arg)?)?
+                }
+                "--wav" => machine.wav_path = Some(option_value(&mut args, arg)?.to_string()),
+                "--wav-format" => {
+                    machine.wav_encoding = SampleEncoding::parse(option_value(&mut args, arg)?)?
+                }
+                "--sample-rate" => {
+                    let rate = parse_count(arg, option_value(&mut args, arg)?)?;
+                    if !(8_000..=192_000).contains(&rate) {
+                        return Err(format!("Sample rate out of range: {}", rate));
+                    }
+                    machine.set_sample_rate(rate as u32);
+                }
+                "--record-movie" => {
+                    machine.movie_path = Some(option_value(&mut args, arg)?.to_string())
+                }
+                "--play-movie" => machine.play_movie(Movie::load(option_value(&mut args, arg)?)?),
+                "--load-state" => {
+                    machine.load_state_path = Some(option_value(&mut args, arg)?.to_string())
+                }
+                "--save-state" => {
+                    machine.save_state_path = Some(option_value(&mut args, arg)?.to_string())
+                }
+                "--save-dir" => save_dir = Some(option_value(&mut args, arg)?),
+                "--rewind" => {
+                    rewind_interval = Some(parse_count(arg, option_value(&mut args, arg)?)?.max(1))
+                }
+                "--rewind-budget" => {
+                    rewind_budget =
+                        (parse_count(arg, option_value(&mut args, arg)?)? as usize) << 20
+                }
+                option if option.starts_with('-') => {
+                    return Err(format!("Unknown option: {}", option))
+                }
+                path => rom_path = Some(path),
             }
         }
 
-        machine
+        if let Some(interval) = rewind_interval {
+            machine.enable_rewind(interval, rewind_budget);
+        }
+
+        let rom_path = rom_path.ok_or("No ROM file given, see -h")?;
+        machine.insert_cartridge(Cartridge::load(rom_path)?)?;
+        if machine.has_battery() {
+            machine.attach_battery_file(battery_path(rom_path, save_dir))?;
+        }
+
+        if machine.movie_path.is_some() {
+            let rom_name = Path::new(rom_path).file_stem().unwrap_or_default();
+            machine.start_movie_recording(&rom_name.to_string_lossy());
+        }
+        if let Some((movie, _)) = &machine.playback {
+            machine.frame_limit = machine.frame_limit.or(Some(movie.frames.len() as u64));
+        }
+
+        Ok(machine)
+    }
+
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) -> Result<(), String> {
+        self.battery = cartridge.header.battery;
+        self.rom_fingerprint = fingerprint(&[
+            &cartridge.header.mapper.to_le_bytes(),
+            &cartridge.prg_rom,
+            &cartridge.chr_rom,
+        ]);
+        self.bus.attach_cartridge(new_mapper(cartridge)?);
+        Ok(())
     }
 
     pub fn set_debug(&mut self, debug: bool) {
@@ -55,36 +227,629 @@ impl Machine {
     }
 
     fn reset(&mut self) {
-        self.cpu.reset();
-        self.memory.reset();
-        self.stub_fill_memory_with_insts();
+        self.cpu.reset(&mut self.bus);
+    }
+
+    /*
+     * Presses the reset button. While a movie is being recorded it only
+     * takes effect at the start of the next frame, which is where the
+     * movie can put it on playback.
+     */
+    pub fn press_reset(&mut self) {
+        if self.recording.is_some() {
+            self.pending_commands |= MovieFrame::RESET;
+        } else {
+            self.reset();
+        }
+    }
+
+    /*
+     * Brings the machine to the state right after power-up and the reset
+     * sequence. Battery-backed work RAM is the only memory that survives.
+     */
+    pub fn power_on(&mut self) {
+        let battery_ram = self
+            .bus
+            .cartridge()
+            .filter(|_| self.battery)
+            .map(|cartridge| cartridge.prg_ram().to_vec());
+        self.bus.power_cycle();
+        if let (Some(ram), Some(cartridge)) = (battery_ram, self.bus.cartridge_mut()) {
+            cartridge.prg_ram_mut().copy_from_slice(&ram);
+        }
+
+        let cycles = self.cpu.cycles;
+        self.cpu = CPU::with_variant(self.cpu.variant);
+        self.cpu.cycles = cycles;
+        self.cpu.reset(&mut self.bus);
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles
+    }
+
+    /*
+     * Runs a single instruction, then lets the rest of the machine catch
+     * up, and returns the CPU cycles it took including any DMA stall.
+     */
+    pub fn step(&mut self) -> u16 {
+        if self.input_frame != Some(self.frame_count()) {
+            self.begin_frame();
+        }
+
+        let opcode = self.bus.peek(self.cpu.pc);
+        let sp = self.cpu.sp;
+        let mut cycles = if self.debugger.watchpoints().is_empty() {
+            self.cpu.execute(&mut self.bus)
+        } else {
+            let pc = self.cpu.pc;
+            let mut bus = WatchBus::new(&mut self.bus, self.debugger.watchpoints(), pc);
+            let cycles = self.cpu.execute(&mut bus);
+            let hit = bus.hit();
+            self.debugger.set_watch_hit(hit);
+            cycles
+        } as u16;
+        self.debugger.note_step(opcode, sp, self.cpu.sp);
+        let stall = self.bus.take_stall_cycles();
+        self.cpu.cycles += stall as u64;
+        cycles += stall;
+
+        self.bus.tick(cycles);
+        self.cpu.set_nmi(self.bus.ppu().nmi_line());
+        self.cpu.set_irq(IrqSource::Mapper, self.bus.mapper_irq());
+        self.cpu
+            .set_irq(IrqSource::FrameCounter, self.bus.apu().frame_irq());
+        self.cpu.set_irq(IrqSource::Dmc, self.bus.apu().dmc_irq());
+        if let Some(audio) = self.audio.as_mut() {
+            self.bus.apu_mut().drain_samples_into(audio);
+        }
+        cycles
+    }
+
+    /* Latches the input for the frame just started, from the movie being played if any */
+    fn begin_frame(&mut self) {
+        self.input_frame = Some(self.frame_count());
+        let mut commands = std::mem::take(&mut self.pending_commands);
+
+        if let Some((movie, next)) = &mut self.playback {
+            match movie.frames.get(*next) {
+                Some(frame) => {
+                    commands |= frame.commands;
+                    for (port, &buttons) in frame.buttons.iter().enumerate() {
+                        self.bus.set_buttons(port, buttons);
+                    }
+                    *next += 1;
+                }
+                None => self.playback = None,
+            }
+        }
+
+        if let Some(movie) = &mut self.recording {
+            movie.frames.push(MovieFrame {
+                commands,
+                buttons: [self.bus.buttons(0), self.bus.buttons(1)],
+            });
+        }
+
+        if commands & MovieFrame::POWER != 0 {
+            self.power_on();
+        } else if commands & MovieFrame::RESET != 0 {
+            self.reset();
+        }
+
+        let frame = self.frame_count();
+        if self.battery_path.is_some() && frame.is_multiple_of(BATTERY_FLUSH_FRAMES) {
+            if let Err(msg) = self.flush_battery() {
+                println!("{}", msg);
+            }
+        }
+
+        if self
+            .rewind
+            .as_ref()
+            .is_some_and(|rewind| rewind.wants(frame))
+        {
+            let movie_frame = match (&self.playback, &self.recording) {
+                (Some((_, next)), _) => *next,
+                (None, Some(movie)) => movie.frames.len(),
+                (None, None) => 0,
+            };
+            let mark = RewindMark {
+                frame,
+                cycles: self.cycles(),
+                movie_frame,
+            };
+            let state = self.save_state();
+            if let Some(rewind) = &mut self.rewind {
+                rewind.push(mark, state);
+            }
+        }
+    }
+
+    /* Runs until the PPU reaches the next vblank */
+    pub fn run_frame(&mut self) {
+        let frame = self.bus.ppu().frame_count();
+        while self.bus.ppu().frame_count() == frame && !self.stop {
+            self.step();
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.bus.ppu().frame_count()
+    }
+
+    /* 256x240 pixels of 8-bit RGB */
+    pub fn frame_buffer(&self) -> &[u8] {
+        self.bus.ppu().frame_buffer()
+    }
+
+    /* Holds these buttons down on a controller port (0 or 1) until changed */
+    pub fn set_buttons(&mut self, port: usize, buttons: ButtonState) {
+        self.bus.set_buttons(port, buttons);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.bus.apu_mut().set_sample_rate(sample_rate);
+    }
+
+    /* Mono f32 audio produced since the last call, at the configured sample rate */
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.bus.apu_mut().take_samples()
+    }
+
+    /*
+     * Runs whole instructions until the CPU cycle counter reaches the
+     * deadline, so it may overshoot by a few cycles of the last one.
+     */
+    pub fn run_until(&mut self, deadline: u64) {
+        while self.cpu.cycles < deadline && !self.stop {
+            self.step();
+        }
+    }
+
+    pub fn run_for(&mut self, cycles: u64) {
+        self.run_until(self.cpu.cycles + cycles);
+    }
+
+    /*
+     * The whole console as bytes: CPU, RAM, PPU, APU, controllers and the
+     * cartridge's RAM and registers, behind a header that ties it to the
+     * format version and the ROM.
+     */
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        for &byte in STATE_MAGIC {
+            out.u8(byte);
+        }
+        out.u16(STATE_VERSION);
+        out.u64(self.rom_fingerprint);
+        self.cpu.save_state(&mut out);
+        self.bus.save_state(&mut out);
+        out.into_bytes()
+    }
+
+    /* Leaves the machine as it was if the state can't be loaded */
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        self.restore_state(data)?;
+        /* The snapshots belong to a history this state isn't part of */
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        Ok(())
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), String> {
+        if !data.starts_with(STATE_MAGIC) {
+            return Err("Not a save state".to_string());
+        }
+        let mut input = StateReader::new(&data[STATE_MAGIC.len()..]);
+        let version = input.u16()?;
+        if version != STATE_VERSION {
+            return Err(format!(
+                "Save state version {} is not supported (expected {})",
+                version, STATE_VERSION
+            ));
+        }
+        if input.u64()? != self.rom_fingerprint {
+            return Err("Save state is for a different ROM".to_string());
+        }
+
+        let backup = self.save_state();
+        let result = self
+            .cpu
+            .load_state(&mut input)
+            .and_then(|_| self.bus.load_state(&mut input))
+            .and_then(|_| input.finish());
+        if result.is_err() {
+            self.restore_state(&backup)
+                .expect("Failed to restore the machine after a bad save state");
+        }
+        self.input_frame = Some(self.frame_count());
+        result
+    }
+
+    pub fn save_state_file(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.save_state())
+            .map_err(|err| format!("Failed to write {}: {}", path, err))
+    }
+
+    pub fn load_state_file(&mut self, path: &str) -> Result<(), String> {
+        let data = fs::read(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        self.load_state(&data)
+            .map_err(|err| format!("{}: {}", path, err))
+    }
+
+    /* Whether the cartridge's work RAM is battery-backed */
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    /* Keeps the work RAM in this file: loads it now and writes it back as it changes */
+    pub fn attach_battery_file(&mut self, path: impl Into<PathBuf>) -> Result<(), String> {
+        let path = path.into();
+        let cartridge = self.bus.cartridge_mut().ok_or("No cartridge inserted")?;
+        load_battery(&path, cartridge.prg_ram_mut())?;
+        self.battery_written = cartridge.prg_ram().to_vec();
+        self.battery_path = Some(path);
+        Ok(())
+    }
+
+    /* Writes the work RAM out if it changed since the last time */
+    pub fn flush_battery(&mut self) -> Result<(), String> {
+        let (Some(path), Some(cartridge)) = (&self.battery_path, self.bus.cartridge()) else {
+            return Ok(());
+        };
+        let ram = cartridge.prg_ram();
+        if ram != self.battery_written {
+            save_battery(path, ram)?;
+            self.battery_written = ram.to_vec();
+        }
+        Ok(())
+    }
+
+    /* Keeps a snapshot at the start of every `interval`th frame, in at most `budget` bytes */
+    pub fn enable_rewind(&mut self, interval: u64, budget: usize) {
+        self.rewind = Some(RewindBuffer::new(interval, budget));
+    }
+
+    pub fn rewind_buffer(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    /* Goes back to the start of the frame that many frames before the current one, or frame 0 */
+    pub fn rewind_frames(&mut self, frames: u64) -> Result<(), String> {
+        let target = self.frame_count().saturating_sub(frames);
+        self.rewind_to(|mark| mark.frame <= target)?;
+        while self.frame_count() < target && !self.stop {
+            self.run_frame();
+        }
+        Ok(())
     }
 
-    fn stub_fill_memory_with_insts(&mut self) {
-        /* LDA #$C3 */
-        self.memory.write(0x0, 0xA9);
-        self.memory.write(0x1, 0xC3);
+    /* Goes back to the first instruction boundary at or after the given CPU cycle */
+    pub fn rewind_to_cycle(&mut self, cycle: u64) -> Result<(), String> {
+        if cycle > self.cycles() {
+            return Err(format!("Cycle {} hasn't happened yet", cycle));
+        }
+        self.rewind_to(|mark| mark.cycles <= cycle)?;
+        self.run_until(cycle);
+        Ok(())
+    }
+
+    /* Loads the newest snapshot `keep` accepts, from where the caller runs forward */
+    fn rewind_to(&mut self, keep: impl Fn(&RewindMark) -> bool) -> Result<RewindMark, String> {
+        let rewind = self
+            .rewind
+            .as_mut()
+            .ok_or("Rewind is not enabled, see --rewind")?;
+        let oldest = rewind.oldest();
+        let (mark, state) = rewind.seek(keep).ok_or_else(|| match oldest {
+            Some(oldest) => format!(
+                "Rewind only reaches back to frame {} (cycle {})",
+                oldest.frame, oldest.cycles
+            ),
+            None => "Nothing to rewind to yet".to_string(),
+        })?;
+        let state = state.to_vec();
+        self.restore_state(&state)?;
+
+        /* The movie picks up again from the snapshot's frame */
+        if let Some((_, next)) = &mut self.playback {
+            *next = mark.movie_frame;
+        }
+        if let Some(movie) = &mut self.recording {
+            movie.frames.truncate(mark.movie_frame);
+        }
+        Ok(mark)
+    }
+
+    /* Records input from the next frame on. Movies are meant to start at power-on. */
+    pub fn start_movie_recording(&mut self, rom_name: &str) {
+        self.recording = Some(Movie::new(rom_name));
+    }
+
+    pub fn finish_movie_recording(&mut self) -> Option<Movie> {
+        self.recording.take()
+    }
+
+    /* Drives the controllers and resets from the movie, starting with the next frame */
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.playback = Some((movie, 0));
+    }
+
+    pub fn is_playing_movie(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /* Starts keeping all audio from here on, for save_audio */
+    pub fn start_audio_recording(&mut self) {
+        self.audio = Some(Vec::new());
+    }
+
+    pub fn recorded_audio(&self) -> &[f32] {
+        self.audio.as_deref().unwrap_or(&[])
+    }
+
+    pub fn save_audio(&self, path: &str, encoding: SampleEncoding) -> Result<(), String> {
+        let sample_rate = self.bus.apu().sample_rate();
+        save_wav(path, self.recorded_audio(), sample_rate, encoding)
+    }
+
+    pub fn save_frame(&self, path: &str, format: ImageFormat) -> Result<(), String> {
+        save_frame(path, format, self.frame_buffer())
+    }
 
-        /* My stub HALT */
-        self.memory.write(0x2, 0xFF);
+    /*
+     * Runs the given number of frames without the monitor, writing out
+     * the ones the capture options ask for as they complete.
+     */
+    pub fn run_headless(&mut self, frames: u64, capture: &CaptureOptions) -> Result<(), String> {
+        while self.frame_count() < frames && !self.stop {
+            self.run_frame();
+            let frame = self.frame_count();
+            if capture.wants(frame) {
+                self.save_frame(&capture.path_for(frame), capture.format)?;
+            }
+        }
+        Ok(())
     }
 
     pub fn run(&mut self) -> Result<(), String> {
-        self.stub_fill_memory_with_insts();
+        self.power_on();
+        if let Some(path) = self.load_state_path.clone() {
+            self.load_state_file(&path)?;
+        }
+        if self.wav_path.is_some() {
+            self.start_audio_recording();
+        }
+
+        if let Some(frames) = self.frame_limit {
+            let capture = self.capture.clone();
+            self.run_headless(frames, &capture)?;
+        } else {
+            self.run_interactive();
+        }
+
+        if let Some(path) = &self.wav_path {
+            self.save_audio(path, self.wav_encoding)?;
+        }
+        if let Some(path) = &self.save_state_path {
+            self.save_state_file(path)?;
+        }
+        self.flush_battery()?;
+        match (self.movie_path.clone(), self.finish_movie_recording()) {
+            (Some(path), Some(movie)) => movie.save(&path),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /* The breakpoint that stops execution before the instruction at the PC */
+    pub fn breakpoint_hit(&self) -> Option<&Breakpoint> {
+        self.debugger.check_breakpoints(self)
+    }
+
+    pub fn set_register(&mut self, register: Register, value: u16) -> Result<(), String> {
+        let byte = || {
+            u8::try_from(value).map_err(|_| format!("Value doesn't fit in a byte: ${:X}", value))
+        };
+        match register {
+            Register::A => self.cpu.a = byte()?,
+            Register::X => self.cpu.x = byte()?,
+            Register::Y => self.cpu.y = byte()?,
+            Register::Sp => self.cpu.sp = byte()?,
+            Register::Pc => self.cpu.pc = value,
+            Register::P => self.cpu.status = byte()?.into(),
+        }
+        Ok(())
+    }
+
+    /*
+     * Memory is changed with ordinary bus writes, as if the CPU stored
+     * it, so RAM and PPU/APU registers take the value but ROM doesn't and
+     * mapper registers react as they would to the program.
+     */
+    pub fn write_memory(&mut self, addr: u16, data: &[u8]) {
+        for (offset, &byte) in data.iter().enumerate() {
+            self.bus.write(addr.wrapping_add(offset as u16), byte);
+        }
+    }
+
+    /* The bytes from start to end inclusive, read with peeks */
+    pub fn read_memory(&self, start: u16, end: u16) -> Vec<u8> {
+        (start..=end).map(|addr| self.bus.peek(addr)).collect()
+    }
+
+    pub fn load_memory(&mut self, path: &str, addr: u16) -> Result<usize, String> {
+        let data = fs::read(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        if addr as usize + data.len() > 0x10000 {
+            return Err(format!(
+                "{} is {} bytes, too long to load at ${:04X}",
+                path,
+                data.len(),
+                addr
+            ));
+        }
+        self.write_memory(addr, &data);
+        Ok(data.len())
+    }
+
+    pub fn dump_memory(&self, path: &str, start: u16, end: u16) -> Result<(), String> {
+        fs::write(path, self.read_memory(start, end))
+            .map_err(|err| format!("Failed to write {}: {}", path, err))
+    }
+
+    /* Carries out a `set` from the monitor, e.g. "a=$10" or "[$0200]=$FF" */
+    fn assign(&mut self, text: &str) -> Result<(), String> {
+        let (place, value) = parse_assignment(text)?;
+        let value = value.eval(self);
+        match place {
+            Place::Register(register) => self.set_register(register, value),
+            Place::Memory(addr) => {
+                let addr = addr.eval(self);
+                let data = u8::try_from(value)
+                    .map_err(|_| format!("Value doesn't fit in a byte: ${:X}", value))?;
+                self.write_memory(addr, &[data]);
+                Ok(())
+            }
+        }
+    }
+
+    /* Where the instruction at the PC is going to read or write, if anywhere */
+    pub fn effective_address(&mut self) -> Option<u16> {
+        let inst = self.cpu.get_next_inst(&self.bus);
+        match inst {
+            Instruction::JMP(..) | Instruction::JSR(..) => None,
+            _ if matches!(inst.get_contents().3, OperandType::Mem) => Some(inst.get_address()),
+            _ => None,
+        }
+    }
+
+    /* `count` instructions from `start`, or from a little before the PC */
+    pub fn listing(&mut self, start: Option<u16>, count: usize) -> Vec<String> {
+        let pc = self.cpu.pc;
+        let start = start.unwrap_or_else(|| listing_start(&self.bus, pc, 3));
+        let effective = self
+            .effective_address()
+            .map(|addr| (addr, self.bus.peek(addr)));
+        disassemble_range(&self.bus, start, count)
+            .iter()
+            .map(|line| listing_line(line, line.addr == pc, effective))
+            .collect()
+    }
+
+    /* `next`: over a JSR to the instruction after it, otherwise a single step */
+    pub fn step_over(&self) -> RunUntil {
+        match self.bus.peek(self.cpu.pc) {
+            0x20 => RunUntil::Reach(
+                self.cpu.pc.wrapping_add(3),
+                Some(self.debugger.call_depth()),
+            ),
+            _ => RunUntil::Steps(1),
+        }
+    }
+
+    /* `finish`: until the current subroutine or interrupt handler returns */
+    pub fn step_out(&self) -> RunUntil {
+        RunUntil::Return(self.debugger.call_depth())
+    }
+
+    /*
+     * Runs at least one instruction and then until `until` is met, or
+     * until a breakpoint or watchpoint stops it first, which it describes.
+     * Breakpoints are checked before every instruction but the first, so
+     * resuming from one doesn't stop straight away.
+     */
+    pub fn resume(&mut self, until: RunUntil) -> Option<String> {
+        let mut steps = 0;
         loop {
-            if self.reset {
-                self.reset();
+            self.step();
+            steps += 1;
+            if self.stop {
+                return None;
+            }
+            if let Some(hit) = self.debugger.take_watch_hit() {
+                return Some(format!(
+                    "{} at ${:04X} ({})",
+                    hit,
+                    hit.pc,
+                    disassemble_one(&self.bus, hit.pc).text()
+                ));
             }
+            if let Some(breakpoint) = self.breakpoint_hit() {
+                return Some(breakpoint.to_string());
+            }
+            let depth = self.debugger.call_depth();
+            let done = match until {
+                RunUntil::Break => false,
+                RunUntil::Steps(count) => steps >= count,
+                RunUntil::Reach(pc, at) => self.cpu.pc == pc && at.is_none_or(|at| at == depth),
+                RunUntil::Return(from) => depth < from,
+            };
+            if done {
+                return None;
+            }
+        }
+    }
 
+    fn run_interactive(&mut self) {
+        loop {
             if self.stop {
                 break;
             }
 
             self.monitor();
 
-            self.cpu.execute(&mut self.memory);
+            let until = self.running.take().unwrap_or(RunUntil::Steps(1));
+            if let Some(reason) = self.resume(until) {
+                println!("{}", reason);
+            }
         }
-        Ok(())
+    }
+}
+
+fn option_value<'a>(
+    args: &mut impl Iterator<Item = &'a String>,
+    option: &str,
+) -> Result<&'a str, String> {
+    args.next()
+        .map(|value| value.as_str())
+        .ok_or_else(|| format!("Option {} needs a value", option))
+}
+
+/* Monitor arguments are expressions, so registers and memory work as addresses too */
+fn eval_args<const N: usize>(machine: &Machine, args: &[&str]) -> Result<[u16; N], String> {
+    let mut values = [0; N];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = parse_expr(arg)?.eval(machine);
+    }
+    Ok(values)
+}
+
+fn parse_count(option: &str, value: &str) -> Result<u64, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Option {} expects a number, got {}", option, value))
+}
+
+impl DebugTarget for Machine {
+    fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::A => self.cpu.a as u16,
+            Register::X => self.cpu.x as u16,
+            Register::Y => self.cpu.y as u16,
+            Register::Sp => self.cpu.sp as u16,
+            Register::Pc => self.cpu.pc,
+            Register::P => u8::from(&self.cpu.status) as u16,
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
     }
 }
 
@@ -99,9 +864,8 @@ impl Monitor for Machine {
             loop {
                 cmd.clear();
 
-                // println!("{}", disassemble(&self.cpu.get_next_inst(&self.memory)).trim());
                 if !not_display_next_inst {
-                    println!("{:?}", self.cpu.get_next_inst(&self.memory));
+                    println!("{}", self.listing(Some(self.cpu.pc), 1)[0]);
                     not_display_next_inst = false;
                 }
 
@@ -109,16 +873,253 @@ impl Monitor for Machine {
                 std::io::stdout().flush().expect("Failed to flush stdout");
                 std::io::stdin().read_line(&mut cmd).unwrap();
 
-                match cmd.trim() {
+                let (command, argument) = cmd
+                    .trim()
+                    .split_once(' ')
+                    .map_or((cmd.trim(), ""), |(command, argument)| {
+                        (command, argument.trim())
+                    });
+                match command {
+                    "save" | "load" if argument.is_empty() => {
+                        println!("Usage: {} <file>", command);
+                        println!();
+                        continue;
+                    }
+                    "save" => {
+                        match self.save_state_file(argument) {
+                            Ok(()) => println!("Saved state to {}", argument),
+                            Err(msg) => println!("{}", msg),
+                        }
+                        println!();
+                        not_display_next_inst = true;
+                        continue;
+                    }
+                    "load" if argument.split_whitespace().count() == 2 => {
+                        let args: Vec<&str> = argument.split_whitespace().collect();
+                        let result = eval_args::<1>(self, &args[1..])
+                            .and_then(|[addr]| self.load_memory(args[0], addr).map(|n| (n, addr)));
+                        match result {
+                            Ok((len, addr)) => {
+                                println!("Loaded {} bytes from {} at ${:04X}", len, args[0], addr)
+                            }
+                            Err(msg) => println!("{}", msg),
+                        }
+                        println!();
+                        continue;
+                    }
+                    "load" => {
+                        match self.load_state_file(argument) {
+                            Ok(()) => println!("Loaded state from {}", argument),
+                            Err(msg) => println!("{}", msg),
+                        }
+                        println!();
+                        continue;
+                    }
+                    "b" | "break" if argument.is_empty() => {
+                        println!("Usage: {} <addr> [if <condition>]", command);
+                        println!();
+                        continue;
+                    }
+                    "b" | "break" => {
+                        match self.debugger.parse_breakpoint(argument) {
+                            Ok(breakpoint) => println!("{}", breakpoint),
+                            Err(msg) => println!("{}", msg),
+                        }
+                        println!();
+                        not_display_next_inst = true;
+                        continue;
+                    }
+                    "watch" | "rwatch" | "awatch" if argument.is_empty() => {
+                        println!("Usage: {} <addr>[-<end>] [== <value>]", command);
+                        println!();
+                        continue;
+                    }
+                    "watch" | "rwatch" | "awatch" => {
+                        let kind = match command {
+                            "watch" => WatchKind::Write,
+                            "rwatch" => WatchKind::Read,
+                            _ => WatchKind::Access,
+                        };
+                        match self.debugger.parse_watchpoint(kind, argument) {
+                            Ok(watchpoint) => println!("{}", watchpoint),
+                            Err(msg) => println!("{}", msg),
+                        }
+                        println!();
+                        not_display_next_inst = true;
+                        continue;
+                    }
+                    "delete" if argument.is_empty() => {
+                        self.debugger.clear();
+                        println!("Deleted all breakpoints and watchpoints");
+                        println!();
+                        continue;
+                    }
+                    "delete" => {
+                        match argument.parse().map(|id| (id, self.debugger.delete(id))) {
+                            Ok((id, Some(kind))) => println!("Deleted {} {}", kind, id),
+                            _ => println!("No breakpoint or watchpoint {}", argument),
+                        }
+                        println!();
+                        continue;
+                    }
+                    "list" => {
+                        if self.debugger.breakpoints().is_empty()
+                            && self.debugger.watchpoints().is_empty()
+                        {
+                            println!("No breakpoints or watchpoints");
+                        }
+                        for breakpoint in self.debugger.breakpoints() {
+                            println!("{}", breakpoint);
+                        }
+                        for watchpoint in self.debugger.watchpoints() {
+                            println!("{}", watchpoint);
+                        }
+                        println!();
+                        not_display_next_inst = true;
+                        continue;
+                    }
+                    "c" | "continue" => self.running = Some(RunUntil::Break),
+                    "next" | "n" => self.running = Some(self.step_over()),
+                    "finish" => self.running = Some(self.step_out()),
+                    "until" if argument.is_empty() => {
+                        println!("Usage: until <addr>");
+                        println!();
+                        continue;
+                    }
+                    "until" => match eval_args::<1>(self, &[argument]) {
+                        Ok([addr]) => self.running = Some(RunUntil::Reach(addr, None)),
+                        Err(msg) => {
+                            println!("{}", msg);
+                            println!();
+                            continue;
+                        }
+                    },
+                    "d" => {
+                        let args: Vec<&str> = argument.split_whitespace().collect();
+                        let result = match args.as_slice() {
+                            [] => Ok((None, 10)),
+                            [_] => eval_args::<1>(self, &args).map(|[addr]| (Some(addr), 10)),
+                            [addr, count] => eval_args::<1>(self, &[addr]).and_then(|[addr]| {
+                                parse_count("d", count).map(|count| (Some(addr), count as usize))
+                            }),
+                            _ => Err("Usage: d [addr] [count]".to_string()),
+                        };
+                        match result {
+                            Ok((start, count)) => {
+                                for line in self.listing(start, count) {
+                                    println!("{}", line);
+                                }
+                            }
+                            Err(msg) => println!("{}", msg),
+                        }
+                        println!();
+                        not_display_next_inst = true;
+                        continue;
+                    }
+                    _ if command == "x" || command.starts_with("x/") => {
+                        let count = match command.strip_prefix("x/") {
+                            Some(count) => parse_count("x", count),
+                            None => Ok(16),
+                        };
+                        let args: Vec<&str> = argument.split_whitespace().collect();
+                        match (count, eval_args::<1>(self, &args)) {
+                            (_, _) if args.len() != 1 => println!("Usage: x[/<count>] <addr>"),
+                            (Ok(count), Ok([addr])) => {
+                                for line in hex_dump(self, addr, count as usize) {
+                                    println!("{}", line);
+                                }
+                            }
+                            (Err(msg), _) | (_, Err(msg)) => println!("{}", msg),
+                        }
+                        println!();
+                        not_display_next_inst = true;
+                        continue;
+                    }
+                    "set" if argument.is_empty() => {
+                        println!("Usage: set <register>=<value> or set [<addr>]=<value>");
+                        println!();
+                        continue;
+                    }
+                    "set" => {
+                        if let Err(msg) = self.assign(argument) {
+                            println!("{}", msg);
+                        }
+                        println!();
+                        continue;
+                    }
+                    "fill" => {
+                        let args: Vec<&str> = argument.split_whitespace().collect();
+                        match eval_args::<3>(self, &args) {
+                            _ if args.len() != 3 => println!("Usage: fill <start> <end> <value>"),
+                            Ok([start, end, value]) if start <= end && value <= 0xFF => {
+                                let data = vec![value as u8; (end - start) as usize + 1];
+                                self.write_memory(start, &data);
+                            }
+                            Ok(_) => println!("Expected start <= end and a byte value"),
+                            Err(msg) => println!("{}", msg),
+                        }
+                        println!();
+                        continue;
+                    }
+                    "dump" => {
+                        let args: Vec<&str> = argument.split_whitespace().collect();
+                        match eval_args::<2>(self, args.get(1..).unwrap_or_default()) {
+                            _ if args.len() != 3 => println!("Usage: dump <file> <start> <end>"),
+                            Ok([start, end]) if start <= end => {
+                                match self.dump_memory(args[0], start, end) {
+                                    Ok(()) => {
+                                        println!("Wrote ${:04X}-${:04X} to {}", start, end, args[0])
+                                    }
+                                    Err(msg) => println!("{}", msg),
+                                }
+                            }
+                            Ok(_) => println!("Range ends before it starts"),
+                            Err(msg) => println!("{}", msg),
+                        }
+                        println!();
+                        not_display_next_inst = true;
+                        continue;
+                    }
+                    "rewind" => {
+                        let result = match argument.strip_prefix('@') {
+                            Some(cycle) => parse_count("rewind", cycle)
+                                .and_then(|cycle| self.rewind_to_cycle(cycle)),
+                            None if argument.is_empty() => self.rewind_frames(1),
+                            None => parse_count("rewind", argument)
+                                .and_then(|frames| self.rewind_frames(frames)),
+                        };
+                        match result {
+                            Ok(()) => println!(
+                                "Rewound to frame {}, cycle {}",
+                                self.frame_count(),
+                                self.cycles()
+                            ),
+                            Err(msg) => println!("{}", msg),
+                        }
+                        println!();
+                        continue;
+                    }
                     "r" => {
-                        self.reset = true;
                         println!("Resetting...");
-                        self.reset();
-                        println!("Reset complete");
+                        /* Goes through the movie while one is being recorded */
+                        self.press_reset();
+                        if self.recording.is_some() {
+                            println!("Reset at the start of the next frame");
+                        } else {
+                            println!("Reset complete");
+                        }
                         println!();
                     }
                     "q" => self.stop = true,
-                    "s" => (),
+                    "s" if argument.is_empty() => (),
+                    "s" => match parse_count("s", argument) {
+                        Ok(count) => self.running = Some(RunUntil::Steps(count.max(1))),
+                        Err(msg) => {
+                            println!("{}", msg);
+                            println!();
+                            continue;
+                        }
+                    },
                     "p" => {
                         self.cpu.print_state();
                         not_display_next_inst = true;
@@ -135,3 +1136,552 @@ impl Monitor for Machine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* A 16 KiB NROM image running the given program from $C000, NMI handler at $C080 */
+    fn nrom(program: &[u8]) -> Cartridge {
+        let mut data = b"NES\x1A\x01\x00\x00\x00\0\0\0\0\0\0\0\0".to_vec();
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3FFA] = 0x80;
+        prg[0x3FFB] = 0xC0;
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0xC0;
+        data.extend(prg);
+        Cartridge::from_bytes(&data).unwrap()
+    }
+
+    #[test]
+    fn run_until_stops_at_deadline() {
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(&[0xA9, 0xC3, 0x02])).unwrap();
+        machine.power_on();
+        assert_eq!(machine.cpu.pc, 0xC000);
+        assert_eq!(machine.cycles(), 7);
+
+        /* LDA #$C3 (2 cycles), then the CPU jams */
+        machine.run_for(2);
+        assert_eq!(machine.cycles(), 9);
+        assert_eq!(machine.cpu.a, 0xC3);
+
+        machine.run_until(100);
+        assert!(machine.cycles() >= 100);
+        assert!(machine.cycles() < 100 + 7);
+    }
+
+    #[test]
+    fn read_modify_write_resets_mmc1() {
+        use cartridge::Mirroring;
+
+        /* 32 KiB MMC1: the first bank is all $FF, the program sits in the last one */
+        let mut data = b"NES\x1A\x02\x00\x10\x00\0\0\0\0\0\0\0\0".to_vec();
+        let mut prg = vec![0xFF; 0x8000];
+        /* INC $8000; LDA #$01; STA $8000 five times */
+        let mut program = vec![0xEE, 0x00, 0x80, 0xA9, 0x01];
+        for _ in 0..5 {
+            program.extend([0x8D, 0x00, 0x80]);
+        }
+        prg[0x4000..0x4000 + program.len()].copy_from_slice(&program);
+        prg[0x7FFC] = 0x00;
+        prg[0x7FFD] = 0xC0;
+        data.extend(prg);
+
+        let mut machine = Machine::new();
+        machine
+            .insert_cartridge(Cartridge::from_bytes(&data).unwrap())
+            .unwrap();
+        machine.power_on();
+        let mirroring = |machine: &Machine| machine.bus.cartridge().unwrap().mirroring();
+
+        /*
+         * INC writes back $FF, which resets the shift register, and its $00
+         * lands on the next cycle and is ignored. Four bits in, nothing is
+         * loaded yet; the fifth loads $1F.
+         */
+        for _ in 0..6 {
+            machine.step();
+        }
+        assert_eq!(mirroring(&machine), Mirroring::SingleScreenLower);
+        machine.step();
+        assert_eq!(mirroring(&machine), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn vblank_interrupts_the_cpu_once_per_frame() {
+        let mut program = vec![0; 0x84];
+        /* LDA #$80; STA $2000; JMP $C005 */
+        program[..8].copy_from_slice(&[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0xC0]);
+        /* INC $10; RTI */
+        program[0x80..].copy_from_slice(&[0xE6, 0x10, 0x40, 0x00]);
+
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(&program)).unwrap();
+        machine.power_on();
+        for _ in 0..3 {
+            machine.run_frame();
+        }
+        machine.step();
+        machine.step();
+        assert_eq!(machine.frame_count(), 3);
+        assert_eq!(machine.bus.peek(0x0010), 3);
+    }
+
+    #[test]
+    fn headless_run_captures_selected_frames() {
+        let dir = std::env::temp_dir().join(format!("nesemu-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let args: Vec<String> = [
+            "nesemu",
+            "--frames",
+            "6",
+            "--capture",
+            "1",
+            "--capture-every",
+            "4",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .chain([
+            "--capture-dir".to_string(),
+            dir.to_string_lossy().into_owned(),
+        ])
+        .chain(
+            ["--capture-format", "ppm", "rom.nes"]
+                .iter()
+                .map(|arg| arg.to_string()),
+        )
+        .collect();
+        assert!(Machine::new_from_args(&args)
+            .err()
+            .unwrap()
+            .starts_with("Failed to read rom.nes"));
+
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(&[0x4C, 0x00, 0xC0])).unwrap();
+        machine.power_on();
+        let capture = CaptureOptions {
+            frames: vec![1],
+            every: Some(4),
+            dir: dir.to_string_lossy().into_owned(),
+            format: ImageFormat::Ppm,
+        };
+        machine.run_headless(6, &capture).unwrap();
+        assert_eq!(machine.frame_count(), 6);
+
+        let mut files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["frame_00001.ppm", "frame_00004.ppm"]);
+        let size = std::fs::metadata(dir.join("frame_00004.ppm"))
+            .unwrap()
+            .len();
+        assert_eq!(size, 15 + 256 * 240 * 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_audio_to_wav() {
+        let mut machine = Machine::new();
+        machine.set_sample_rate(22_050);
+        machine.insert_cartridge(nrom(&[0x4C, 0x00, 0xC0])).unwrap();
+        machine.power_on();
+        machine.start_audio_recording();
+        for _ in 0..60 {
+            machine.run_frame();
+        }
+        /* A second of NTSC frames is a second of audio */
+        let samples = machine.recorded_audio().len();
+        assert!((21_900..22_100).contains(&samples), "{}", samples);
+
+        let path = std::env::temp_dir().join(format!("nesemu-audio-{}.wav", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        machine.save_audio(&path, SampleEncoding::Float32).unwrap();
+        let size = std::fs::metadata(&path).unwrap().len() as usize;
+        assert_eq!(size, 58 + samples * 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /* Polls both controllers forever, folding every read into $00 and $01 */
+    const POLL_INPUT: &[u8] = &[
+        0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xA2, 0x08, 0xAD, 0x16, 0x40,
+        0x4A, 0x26, 0x00, 0xCA, 0xD0, 0xF7, 0xA5, 0x01, 0x2A, 0x45, 0x00, 0x85, 0x01, 0x4C, 0x00,
+        0xC0,
+    ];
+
+    #[test]
+    fn movies_replay_input_and_resets() {
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(POLL_INPUT)).unwrap();
+        machine.power_on();
+        machine.start_movie_recording("poll");
+        for frame in 0..8u8 {
+            machine.set_buttons(0, ButtonState(frame.wrapping_mul(37)));
+            machine.set_buttons(1, ButtonState(!frame));
+            if frame == 5 {
+                machine.press_reset();
+            }
+            machine.run_frame();
+        }
+        let movie = machine.finish_movie_recording().unwrap();
+        assert_eq!(movie.frames.len(), 8);
+        assert_eq!(movie.frames[3].buttons, [ButtonState(111), ButtonState(!3)]);
+        assert_eq!(movie.frames[5].commands, MovieFrame::RESET);
+
+        let mut replay = Machine::new();
+        replay.insert_cartridge(nrom(POLL_INPUT)).unwrap();
+        replay.power_on();
+        replay.play_movie(Movie::parse(&movie.to_fm2().unwrap()).unwrap());
+        for _ in 0..8 {
+            replay.run_frame();
+        }
+        assert_eq!(replay.cycles(), machine.cycles());
+        assert_eq!(replay.cpu.pc, machine.cpu.pc);
+        assert_eq!(
+            (replay.bus.peek(0x00), replay.bus.peek(0x01)),
+            (machine.bus.peek(0x00), machine.bus.peek(0x01))
+        );
+
+        assert!(replay.is_playing_movie());
+        replay.run_frame();
+        assert!(!replay.is_playing_movie());
+    }
+
+    #[test]
+    fn rewinding_moves_the_movie_back() {
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(POLL_INPUT)).unwrap();
+        machine.power_on();
+        machine.enable_rewind(1, 1 << 20);
+        machine.start_movie_recording("poll");
+        for frame in 0..8u8 {
+            machine.set_buttons(0, ButtonState(frame));
+            machine.run_frame();
+        }
+
+        /* Back to the start of frame 5: its input stays, later frames are recorded again */
+        machine.rewind_frames(3).unwrap();
+        assert_eq!(machine.recording.as_ref().unwrap().frames.len(), 6);
+        for _ in 0..3 {
+            machine.set_buttons(0, ButtonState::A);
+            machine.run_frame();
+        }
+        let movie = machine.finish_movie_recording().unwrap();
+        assert_eq!(movie.frames.len(), 8);
+        assert_eq!(movie.frames[5].buttons[0], ButtonState(5));
+        assert_eq!(movie.frames[6].buttons[0], ButtonState::A);
+
+        /* Playback replays the same frames after a rewind */
+        let mut replay = Machine::new();
+        replay.insert_cartridge(nrom(POLL_INPUT)).unwrap();
+        replay.power_on();
+        replay.enable_rewind(1, 1 << 20);
+        replay.play_movie(movie);
+        for _ in 0..8 {
+            replay.run_frame();
+        }
+        let end = replay.save_state();
+        replay.rewind_frames(3).unwrap();
+        assert_eq!(replay.playback.as_ref().unwrap().1, 6);
+        for _ in 0..3 {
+            replay.run_frame();
+        }
+        assert_eq!(replay.save_state(), end);
+        assert_eq!(replay.cycles(), machine.cycles());
+    }
+
+    #[test]
+    fn power_cycles_clear_ram_and_devices() {
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(POLL_INPUT)).unwrap();
+        machine.power_on();
+        for _ in 0..2 {
+            machine.run_frame();
+        }
+        machine.bus.write(0x0300, 0x55);
+        machine.bus.write(0x6000, 0x66);
+        machine.bus.write(0x2000, 0x80);
+        machine.cpu.a = 0x42;
+
+        let (frame, cycles) = (machine.frame_count(), machine.cycles());
+        machine.power_on();
+        assert_eq!(machine.bus.peek(0x0300), 0x00);
+        assert_eq!(machine.bus.peek(0x6000), 0x00);
+        assert_eq!(machine.cpu.a, 0x00);
+        assert_eq!(machine.cpu.pc, 0xC000);
+        assert_eq!(machine.frame_count(), frame);
+        assert!(machine.cycles() > cycles);
+
+        /* NMIs stay off through vblank, so the PPU control register was cleared too */
+        while machine.frame_count() < frame + 2 {
+            machine.step();
+            assert!(machine.cpu.pc < 0xC080);
+        }
+
+        /* Battery-backed work RAM is kept */
+        machine.battery = true;
+        machine.bus.write(0x6000, 0x66);
+        machine.power_on();
+        assert_eq!(machine.bus.peek(0x6000), 0x66);
+    }
+
+    #[test]
+    fn save_states_restore_the_whole_machine() {
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(POLL_INPUT)).unwrap();
+        machine.power_on();
+        machine.set_buttons(0, ButtonState::START);
+        for _ in 0..3 {
+            machine.run_frame();
+        }
+        machine.run_for(1234);
+        let state = machine.save_state();
+
+        let run_on = |machine: &mut Machine| {
+            machine.set_buttons(1, ButtonState::A);
+            machine.run_frame();
+            (
+                machine.cycles(),
+                machine.cpu.pc,
+                machine.bus.peek(0x01),
+                machine.frame_buffer().to_vec(),
+                machine.save_state(),
+            )
+        };
+        let expected = run_on(&mut machine);
+
+        let mut restored = Machine::new();
+        restored.insert_cartridge(nrom(POLL_INPUT)).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(run_on(&mut restored), expected);
+    }
+
+    #[test]
+    fn bad_save_states_are_refused() {
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(POLL_INPUT)).unwrap();
+        machine.power_on();
+        let state = machine.save_state();
+        machine.run_frame();
+        let current = machine.save_state();
+
+        let mut other = Machine::new();
+        other.insert_cartridge(nrom(&[0xEA])).unwrap();
+        assert_eq!(
+            other.load_state(&state),
+            Err("Save state is for a different ROM".to_string())
+        );
+
+        let mut newer = state.clone();
+        newer[4] = 0xFF;
+        assert_eq!(
+            machine.load_state(&newer),
+            Err("Save state version 255 is not supported (expected 1)".to_string())
+        );
+        assert_eq!(
+            machine.load_state(b"PNG"),
+            Err("Not a save state".to_string())
+        );
+        assert_eq!(
+            machine.load_state(&state[..state.len() - 10]),
+            Err("Save state is truncated".to_string())
+        );
+        assert_eq!(machine.save_state(), current);
+    }
+
+    #[test]
+    fn rewinds_by_frames_and_to_a_cycle() {
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(POLL_INPUT)).unwrap();
+        machine.power_on();
+        assert_eq!(
+            machine.rewind_frames(1),
+            Err("Rewind is not enabled, see --rewind".to_string())
+        );
+        machine.enable_rewind(1, 1 << 20);
+
+        machine.set_buttons(0, ButtonState::B);
+        while machine.frame_count() < 5 {
+            machine.run_frame();
+        }
+        let frame_5 = machine.save_state();
+        machine.run_frame();
+        machine.run_frame();
+        machine.run_for(1000);
+        let (cycle, mid_frame_7) = (machine.cycles(), machine.save_state());
+        machine.run_frame();
+        machine.run_frame();
+
+        machine.rewind_to_cycle(cycle).unwrap();
+        assert_eq!(machine.save_state(), mid_frame_7);
+        machine.rewind_frames(2).unwrap();
+        assert_eq!(machine.frame_count(), 5);
+        assert_eq!(machine.save_state(), frame_5);
+
+        let buffer = machine.rewind_buffer().unwrap();
+        assert_eq!(buffer.len(), 6);
+        assert!(buffer.used() < 2 * frame_5.len());
+        assert!(machine.rewind_to_cycle(machine.cycles() + 1).is_err());
+    }
+
+    #[test]
+    fn battery_ram_is_loaded_and_written_back() {
+        let path = std::env::temp_dir().join(format!("nesemu-machine-{}.sav", std::process::id()));
+        std::fs::write(&path, [0x00, 0x5A]).unwrap();
+
+        /* LDA $6001, STA $6000 */
+        let mut cartridge = nrom(&[0xAD, 0x01, 0x60, 0x8D, 0x00, 0x60, 0x02]);
+        cartridge.header.battery = true;
+        let mut machine = Machine::new();
+        machine.insert_cartridge(cartridge).unwrap();
+        assert!(machine.has_battery());
+        machine.attach_battery_file(&path).unwrap();
+        machine.power_on();
+        machine.run_for(8);
+
+        machine.flush_battery().unwrap();
+        let saved = std::fs::read(&path).unwrap();
+        assert_eq!(saved.len(), 0x2000);
+        assert_eq!(&saved[..2], &[0x5A, 0x5A]);
+
+        /* Nothing changed since, so nothing gets written */
+        std::fs::write(&path, [0xFF]).unwrap();
+        machine.flush_battery().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), vec![0xFF]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn conditional_breakpoints_stop_the_run() {
+        /* loop: INC $0300; LDA $0300; JMP loop */
+        let program = [0xEE, 0x00, 0x03, 0xAD, 0x00, 0x03, 0x4C, 0x00, 0xC0];
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(&program)).unwrap();
+        machine.power_on();
+        machine
+            .debugger()
+            .parse_breakpoint("$C006 if A==$10 && [$0300]>5")
+            .unwrap();
+
+        let mut steps = 0;
+        while machine.breakpoint_hit().is_none() && steps < 1000 {
+            machine.step();
+            steps += 1;
+        }
+        assert_eq!(machine.cpu.pc, 0xC006);
+        assert_eq!(machine.bus.peek(0x0300), 0x10);
+        assert_eq!(steps, 0x10 * 3 - 1);
+    }
+
+    #[test]
+    fn watchpoints_catch_the_instruction_that_writes() {
+        /* loop: INC $0300; LDX #$07; STX $0300; JMP loop */
+        let program = [
+            0xEE, 0x00, 0x03, 0xA2, 0x07, 0x8E, 0x00, 0x03, 0x4C, 0x00, 0xC0,
+        ];
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(&program)).unwrap();
+        machine.power_on();
+        machine
+            .debugger()
+            .parse_watchpoint(WatchKind::Write, "$0300 == 7")
+            .unwrap();
+
+        let mut steps = 0;
+        let hit = loop {
+            machine.step();
+            steps += 1;
+            if let Some(hit) = machine.debugger().take_watch_hit() {
+                break hit;
+            }
+        };
+        assert_eq!(steps, 3);
+        assert_eq!(hit.pc, 0xC005);
+        assert_eq!((hit.old, hit.new), (0x01, 0x07));
+        assert_eq!(machine.bus.peek(0x0300), 0x07);
+    }
+
+    #[test]
+    fn monitor_edits_registers_and_memory() {
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(&[0xEA])).unwrap();
+        machine.power_on();
+
+        machine.assign("a=$10").unwrap();
+        machine.assign("[$0200]=$FF").unwrap();
+        machine.assign("[$0201] = [$0200]").unwrap();
+        assert_eq!(machine.cpu.a, 0x10);
+        assert_eq!(machine.read_memory(0x0200, 0x0201), vec![0xFF, 0xFF]);
+        assert!(machine.assign("[$0200]=$100").is_err());
+        assert!(machine.assign("pc=$C123").is_ok());
+        assert_eq!(machine.cpu.pc, 0xC123);
+
+        let path = std::env::temp_dir().join(format!("nesemu-dump-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        machine.dump_memory(path, 0x01FF, 0x0201).unwrap();
+        assert_eq!(machine.load_memory(path, 0x0300), Ok(3));
+        assert_eq!(machine.read_memory(0x0300, 0x0302), vec![0x00, 0xFF, 0xFF]);
+        assert!(machine.load_memory(path, 0xFFFF).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn steps_over_and_out_of_nested_calls() {
+        let mut program = vec![0; 0x24];
+        /* JSR $C010; JMP $C000 */
+        program[..6].copy_from_slice(&[0x20, 0x10, 0xC0, 0x4C, 0x00, 0xC0]);
+        /* JSR $C020; INC $00; RTS */
+        program[0x10..0x16].copy_from_slice(&[0x20, 0x20, 0xC0, 0xE6, 0x00, 0x60]);
+        /* INC $01; RTS */
+        program[0x20..0x23].copy_from_slice(&[0xE6, 0x01, 0x60]);
+        let mut machine = Machine::new();
+        machine.insert_cartridge(nrom(&program)).unwrap();
+        machine.power_on();
+
+        let until = machine.step_over();
+        assert_eq!(machine.resume(until), None);
+        assert_eq!(machine.cpu.pc, 0xC003);
+        assert_eq!(machine.read_memory(0x0000, 0x0001), vec![1, 1]);
+
+        assert_eq!(machine.resume(RunUntil::Steps(3)), None);
+        assert_eq!(machine.cpu.pc, 0xC020);
+        assert_eq!(machine.resume(machine.step_out()), None);
+        assert_eq!(machine.cpu.pc, 0xC013);
+        assert_eq!(machine.resume(machine.step_out()), None);
+        assert_eq!(machine.cpu.pc, 0xC003);
+        assert_eq!(machine.debugger().call_depth(), 0);
+
+        machine.debugger().parse_breakpoint("$C013").unwrap();
+        assert_eq!(
+            machine.resume(RunUntil::Reach(0xC015, None)),
+            Some("Breakpoint 1 at $C013".to_string())
+        );
+        assert_eq!(machine.resume(RunUntil::Reach(0xC015, None)), None);
+        assert_eq!(machine.cpu.pc, 0xC015);
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        let args = |list: &[&str]| -> Vec<String> { list.iter().map(|a| a.to_string()).collect() };
+        let err = |list: &[&str]| Machine::new_from_args(&args(list)).err().unwrap();
+        assert_eq!(
+            err(&["nesemu", "--frames"]),
+            "Option --frames needs a value"
+        );
+        assert_eq!(
+            err(&["nesemu", "--capture", "1,x"]),
+            "Option --capture expects a number, got x"
+        );
+        assert_eq!(err(&["nesemu", "--bogus"]), "Unknown option: --bogus");
+        assert_eq!(
+            err(&["nesemu", "--sample-rate", "100"]),
+            "Sample rate out of range: 100"
+        );
+        assert_eq!(err(&["nesemu"]), "No ROM file given, see -h");
+    }
+}
@@ -0,0 +1,1126 @@
+use super::state::{Snapshot, StateReader, StateWriter};
+
+pub const CPU_CLOCK_RATE: f64 = 1_789_773.0;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[rustfmt::skip]
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/* Periods in CPU cycles, NTSC */
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/* Frame counter steps, in CPU cycles since the sequence started */
+const FRAME_STEP_1: u32 = 7457;
+const FRAME_STEP_2: u32 = 14913;
+const FRAME_STEP_3: u32 = 22371;
+const FRAME_STEP_4: u32 = 29829;
+const FRAME_STEP_5: u32 = 37281;
+
+#[derive(Default)]
+struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    value: u8,
+}
+
+impl LengthCounter {
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.value = LENGTH_TABLE[(index >> 3) as usize];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.value = 0;
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halt && self.value > 0 {
+            self.value -= 1;
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.value > 0
+    }
+}
+
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0F;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+struct Pulse {
+    /* Pulse 1 negates with one's complement, pulse 2 with two's */
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    timer: u16,
+    period: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            step: 0,
+            timer: 0,
+            period: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data & 0x07) as u16) << 8;
+                self.length.load(data);
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = change + self.ones_complement as u16;
+            self.period.saturating_sub(change)
+        } else {
+            self.period + change
+        }
+    }
+
+    /* The sweep unit mutes the channel even when it's not enabled */
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /* Clocked every other CPU cycle */
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    step: u8,
+    timer: u16,
+    period: u16,
+    length: LengthCounter,
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0x7F;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((data & 0x07) as u16) << 8;
+                self.length.load(data);
+                self.linear_reload = true;
+            }
+            _ => (),
+        }
+    }
+
+    fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /* Clocked every CPU cycle, the sequencer only runs while both counters are non-zero */
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.step as usize]
+    }
+}
+
+struct Noise {
+    shift: u16,
+    short_mode: bool,
+    timer: u16,
+    period: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Noise {
+            shift: 1,
+            short_mode: false,
+            timer: 0,
+            period: NOISE_PERIODS[0],
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.length.halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.period = NOISE_PERIODS[(data & 0x0F) as usize];
+            }
+            3 => {
+                self.length.load(data);
+                self.envelope.start = true;
+            }
+            _ => (),
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = (self.shift >> 1) | feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 0x01 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+/*
+ * Delta modulation channel. Its memory reader asks the bus for the next
+ * sample byte, which costs the CPU a few cycles each time.
+ */
+struct Dmc {
+    irq_enabled: bool,
+    irq: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    level: u8,
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            period: DMC_RATES[0],
+            timer: 0,
+            level: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = data & 0x40 != 0;
+                self.period = DMC_RATES[(data & 0x0F) as usize];
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_addr = 0xC000 | (data as u16) << 6,
+            _ => self.sample_length = (data as u16) << 4 | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn fetch_addr(&self) -> Option<u16> {
+        match (self.buffer, self.bytes_remaining) {
+            (None, 1..) => Some(self.current_addr),
+            _ => None,
+        }
+    }
+
+    fn fill(&mut self, data: u8) {
+        self.buffer = Some(data);
+        self.current_addr = self.current_addr.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+/* A first-order filter, either high-pass or low-pass */
+struct Filter {
+    alpha: f32,
+    high_pass: bool,
+    previous_in: f32,
+    previous_out: f32,
+}
+
+impl Filter {
+    fn new(sample_rate: u32, cutoff: f32, high_pass: bool) -> Self {
+        let dt = 1.0 / sample_rate as f32;
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+        Filter {
+            alpha: if high_pass {
+                rc / (rc + dt)
+            } else {
+                dt / (rc + dt)
+            },
+            high_pass,
+            previous_in: 0.0,
+            previous_out: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = if self.high_pass {
+            self.alpha * (self.previous_out + input - self.previous_in)
+        } else {
+            self.previous_out + self.alpha * (input - self.previous_out)
+        };
+        self.previous_in = input;
+        self.previous_out = output;
+        output
+    }
+}
+
+/*
+ * The 2A03 sound hardware: two pulse channels, triangle, noise and DMC,
+ * sequenced by the frame counter and mixed the way the console's
+ * resistor network does it. It runs at the CPU clock and emits f32
+ * samples at the host rate, averaging the mixer output over each sample.
+ */
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    even_cycle: bool,
+
+    sample_rate: u32,
+    sample_clock: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    filters: [Filter; 3],
+    samples: Vec<f32>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl Apu {
+    pub fn new(sample_rate: u32) -> Self {
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            even_cycle: false,
+            sample_rate,
+            sample_clock: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            filters: Self::filters(sample_rate),
+            samples: Vec::new(),
+        }
+    }
+
+    /* The console's output stage: high-passes at 90 and 440 Hz, a low-pass at 14 kHz */
+    fn filters(sample_rate: u32) -> [Filter; 3] {
+        [
+            Filter::new(sample_rate, 90.0, true),
+            Filter::new(sample_rate, 440.0, true),
+            Filter::new(sample_rate, 14_000.0, false),
+        ]
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.filters = Self::filters(sample_rate);
+        self.sample_clock = 0.0;
+    }
+
+    /* Back to power-up, keeping the sample rate and samples not yet taken */
+    pub fn power_cycle(&mut self) {
+        let samples = std::mem::take(&mut self.samples);
+        *self = Apu::new(self.sample_rate);
+        self.samples = samples;
+    }
+
+    /* Hands over the samples produced since the last call */
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /* Like take_samples, but appends to a buffer the caller keeps */
+    pub fn drain_samples_into(&mut self, out: &mut Vec<f32>) {
+        out.append(&mut self.samples);
+    }
+
+    pub fn frame_irq(&self) -> bool {
+        self.frame_irq
+    }
+
+    pub fn dmc_irq(&self) -> bool {
+        self.dmc.irq
+    }
+
+    /* The address the DMC wants to read next, if its buffer is empty */
+    pub fn dmc_fetch_addr(&self) -> Option<u16> {
+        self.dmc.fetch_addr()
+    }
+
+    pub fn dmc_fill(&mut self, data: u8) {
+        self.dmc.fill(data);
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr - 0x4000, data),
+            0x4004..=0x4007 => self.pulse2.write(addr - 0x4004, data),
+            0x4008..=0x400B => self.triangle.write(addr - 0x4008, data),
+            0x400C..=0x400F => self.noise.write(addr - 0x400C, data),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, data),
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0x01 != 0);
+                self.pulse2.length.set_enabled(data & 0x02 != 0);
+                self.triangle.length.set_enabled(data & 0x04 != 0);
+                self.noise.length.set_enabled(data & 0x08 != 0);
+                self.dmc.set_enabled(data & 0x10 != 0);
+                self.dmc.irq = false;
+            }
+            0x4017 => {
+                self.five_step = data & 0x80 != 0;
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /* $4015: which channels are still playing and the pending IRQs */
+    pub fn peek_status(&self) -> u8 {
+        (self.pulse1.length.active() as u8)
+            | (self.pulse2.length.active() as u8) << 1
+            | (self.triangle.length.active() as u8) << 2
+            | (self.noise.length.active() as u8) << 3
+            | ((self.dmc.bytes_remaining > 0) as u8) << 4
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq as u8) << 7
+    }
+
+    /* Reading $4015 acknowledges the frame IRQ */
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear_counter();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        match (self.frame_cycle, self.five_step) {
+            (FRAME_STEP_1, _) | (FRAME_STEP_3, _) => self.clock_quarter_frame(),
+            (FRAME_STEP_2, _) | (FRAME_STEP_4, false) | (FRAME_STEP_5, true) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            _ => (),
+        }
+        if !self.five_step
+            && !self.irq_inhibit
+            && (FRAME_STEP_4 - 1..=FRAME_STEP_4 + 1).contains(&self.frame_cycle)
+        {
+            self.frame_irq = true;
+        }
+        let length = if self.five_step {
+            FRAME_STEP_5 + 1
+        } else {
+            FRAME_STEP_4 + 1
+        };
+        if self.frame_cycle >= length {
+            self.frame_cycle = 0;
+        }
+    }
+
+    /* The non-linear DAC, approximated with the usual formulas */
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.level as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse_out + tnd_out
+    }
+
+    /* Advances the APU by one CPU cycle */
+    pub fn step(&mut self) {
+        self.clock_frame_counter();
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.even_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.even_cycle = !self.even_cycle;
+
+        self.sample_sum += self.mix();
+        self.sample_count += 1;
+        self.sample_clock += self.sample_rate as f64;
+        if self.sample_clock >= CPU_CLOCK_RATE {
+            self.sample_clock -= CPU_CLOCK_RATE;
+            let mut sample = self.sample_sum / self.sample_count as f32;
+            for filter in self.filters.iter_mut() {
+                sample = filter.process(sample);
+            }
+            /* Nobody may be collecting, so keep a second of audio at most */
+            if self.samples.len() >= self.sample_rate as usize {
+                self.samples.drain(..self.sample_rate as usize / 2);
+            }
+            self.samples.push(sample);
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+        }
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.enabled);
+        out.bool(self.halt);
+        out.u8(self.value);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.enabled = input.bool()?;
+        self.halt = input.bool()?;
+        self.value = input.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.start);
+        out.bool(self.looping);
+        out.bool(self.constant);
+        out.u8(self.volume);
+        out.u8(self.divider);
+        out.u8(self.decay);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.start = input.bool()?;
+        self.looping = input.bool()?;
+        self.constant = input.bool()?;
+        self.volume = input.u8()?;
+        self.divider = input.u8()?;
+        self.decay = input.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u8(self.duty);
+        out.u8(self.step);
+        out.u16(self.timer);
+        out.u16(self.period);
+        self.envelope.save_state(out);
+        self.length.save_state(out);
+        out.bool(self.sweep_enabled);
+        out.u8(self.sweep_period);
+        out.bool(self.sweep_negate);
+        out.u8(self.sweep_shift);
+        out.u8(self.sweep_divider);
+        out.bool(self.sweep_reload);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.duty = input.u8()? & 0x03;
+        self.step = input.u8()? & 0x07;
+        self.timer = input.u16()?;
+        self.period = input.u16()?;
+        self.envelope.load_state(input)?;
+        self.length.load_state(input)?;
+        self.sweep_enabled = input.bool()?;
+        self.sweep_period = input.u8()?;
+        self.sweep_negate = input.bool()?;
+        self.sweep_shift = input.u8()?;
+        self.sweep_divider = input.u8()?;
+        self.sweep_reload = input.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u8(self.step);
+        out.u16(self.timer);
+        out.u16(self.period);
+        self.length.save_state(out);
+        out.bool(self.control);
+        out.u8(self.linear_reload_value);
+        out.u8(self.linear_counter);
+        out.bool(self.linear_reload);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.step = input.u8()? % 32;
+        self.timer = input.u16()?;
+        self.period = input.u16()?;
+        self.length.load_state(input)?;
+        self.control = input.bool()?;
+        self.linear_reload_value = input.u8()?;
+        self.linear_counter = input.u8()?;
+        self.linear_reload = input.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Noise {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u16(self.shift);
+        out.bool(self.short_mode);
+        out.u16(self.timer);
+        out.u16(self.period);
+        self.envelope.save_state(out);
+        self.length.save_state(out);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.shift = input.u16()?;
+        self.short_mode = input.bool()?;
+        self.timer = input.u16()?;
+        self.period = input.u16()?;
+        if !NOISE_PERIODS.contains(&self.period) {
+            return Err(format!("Save state has a noise period of {}", self.period));
+        }
+        self.envelope.load_state(input)?;
+        self.length.load_state(input)
+    }
+}
+
+impl Snapshot for Dmc {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bool(self.irq_enabled);
+        out.bool(self.irq);
+        out.bool(self.looping);
+        out.u16(self.period);
+        out.u16(self.timer);
+        out.u8(self.level);
+        out.u16(self.sample_addr);
+        out.u16(self.sample_length);
+        out.u16(self.current_addr);
+        out.u16(self.bytes_remaining);
+        out.bool(self.buffer.is_some());
+        out.u8(self.buffer.unwrap_or(0));
+        out.u8(self.shift);
+        out.u8(self.bits_remaining);
+        out.bool(self.silence);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled = input.bool()?;
+        self.irq = input.bool()?;
+        self.looping = input.bool()?;
+        self.period = input.u16()?;
+        if !DMC_RATES.contains(&self.period) {
+            return Err(format!("Save state has a DMC period of {}", self.period));
+        }
+        self.timer = input.u16()?;
+        self.level = input.u8()?;
+        self.sample_addr = input.u16()?;
+        self.sample_length = input.u16()?;
+        self.current_addr = input.u16()?;
+        self.bytes_remaining = input.u16()?;
+        let has_buffer = input.bool()?;
+        let buffer = input.u8()?;
+        self.buffer = has_buffer.then_some(buffer);
+        self.shift = input.u8()?;
+        self.bits_remaining = input.u8()?;
+        if !(1..=8).contains(&self.bits_remaining) {
+            return Err(format!(
+                "Save state has {} DMC bits left in the byte",
+                self.bits_remaining
+            ));
+        }
+        self.silence = input.bool()?;
+        Ok(())
+    }
+}
+
+/*
+ * The host sample rate is a setting rather than console state, so it
+ * isn't saved, but the filters and the partial sample are.
+ */
+impl Snapshot for Apu {
+    fn save_state(&self, out: &mut StateWriter) {
+        self.pulse1.save_state(out);
+        self.pulse2.save_state(out);
+        self.triangle.save_state(out);
+        self.noise.save_state(out);
+        self.dmc.save_state(out);
+
+        out.bool(self.five_step);
+        out.bool(self.irq_inhibit);
+        out.bool(self.frame_irq);
+        out.u32(self.frame_cycle);
+        out.bool(self.even_cycle);
+
+        out.f64(self.sample_clock);
+        out.f32(self.sample_sum);
+        out.u32(self.sample_count);
+        for filter in &self.filters {
+            out.f32(filter.previous_in);
+            out.f32(filter.previous_out);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.pulse1.load_state(input)?;
+        self.pulse2.load_state(input)?;
+        self.triangle.load_state(input)?;
+        self.noise.load_state(input)?;
+        self.dmc.load_state(input)?;
+
+        self.five_step = input.bool()?;
+        self.irq_inhibit = input.bool()?;
+        self.frame_irq = input.bool()?;
+        self.frame_cycle = input.u32()?;
+        self.even_cycle = input.bool()?;
+
+        self.sample_clock = input.f64()?;
+        self.sample_sum = input.f32()?;
+        self.sample_count = input.u32()?;
+        for filter in self.filters.iter_mut() {
+            filter.previous_in = input.f32()?;
+            filter.previous_out = input.f32()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(apu: &mut Apu, cycles: u32) {
+        for _ in 0..cycles {
+            apu.step();
+        }
+    }
+
+    #[test]
+    fn length_counters_show_in_status() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(
+            apu.peek_status() & 0x01,
+            0,
+            "disabled channels ignore loads"
+        );
+
+        apu.write_register(0x4015, 0x0F);
+        apu.write_register(0x4003, 0x18);
+        apu.write_register(0x400F, 0x18);
+        assert_eq!(apu.peek_status() & 0x0F, 0x09);
+
+        /* Index 3 loads a length of 2, gone after two half frames */
+        run(&mut apu, FRAME_STEP_4 + 2);
+        assert_eq!(apu.peek_status() & 0x0F, 0x00);
+
+        apu.write_register(0x400B, 0x18);
+        apu.write_register(0x4015, 0x00);
+        assert_eq!(apu.peek_status() & 0x0F, 0x00);
+    }
+
+    #[test]
+    fn four_step_mode_raises_frame_irq() {
+        let mut apu = Apu::default();
+        run(&mut apu, FRAME_STEP_4 - 2);
+        assert!(!apu.frame_irq());
+        run(&mut apu, 1);
+        assert!(apu.frame_irq());
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.frame_irq());
+
+        apu.write_register(0x4017, 0x40);
+        run(&mut apu, FRAME_STEP_4 * 2);
+        assert!(!apu.frame_irq());
+
+        apu.write_register(0x4017, 0x80);
+        run(&mut apu, FRAME_STEP_5 * 2);
+        assert!(!apu.frame_irq());
+    }
+
+    #[test]
+    fn sweep_mutes_out_of_range_periods() {
+        let mut pulse = Pulse::new(true);
+        pulse.write(2, 0x07);
+        assert!(pulse.muted());
+        pulse.write(2, 0x00);
+        pulse.write(3, 0x04);
+        pulse.write(1, 0x01);
+        assert!(!pulse.muted());
+        pulse.write(3, 0x07);
+        assert!(pulse.muted());
+
+        /* Negation differs between the two channels */
+        let mut pulse1 = Pulse::new(true);
+        let mut pulse2 = Pulse::new(false);
+        for pulse in [&mut pulse1, &mut pulse2] {
+            pulse.write(2, 0x00);
+            pulse.write(3, 0x01);
+            pulse.write(1, 0x89);
+        }
+        assert_eq!(pulse1.sweep_target(), 0x0100 - 0x80 - 1);
+        assert_eq!(pulse2.sweep_target(), 0x0100 - 0x80);
+    }
+
+    #[test]
+    fn dmc_fetches_sample_and_raises_irq() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4010, 0x8F);
+        apu.write_register(0x4012, 0x01);
+        apu.write_register(0x4013, 0x00);
+        assert_eq!(apu.dmc_fetch_addr(), None);
+
+        apu.write_register(0x4015, 0x10);
+        assert_eq!(apu.peek_status() & 0x10, 0x10);
+        assert_eq!(apu.dmc_fetch_addr(), Some(0xC040));
+        apu.dmc_fill(0xFF);
+        assert_eq!(apu.dmc_fetch_addr(), None);
+        assert!(apu.dmc_irq());
+        assert_eq!(apu.peek_status() & 0x90, 0x80);
+
+        /* The output level climbs by 2 per set bit once the byte is shifted in */
+        apu.write_register(0x4011, 0x10);
+        run(&mut apu, 54 * 16);
+        assert_eq!(apu.dmc.level, 0x10 + 16);
+
+        apu.write_register(0x4015, 0x00);
+        assert!(!apu.dmc_irq());
+    }
+
+    #[test]
+    fn produces_samples_at_host_rate() {
+        let mut apu = Apu::new(48_000);
+        run(&mut apu, CPU_CLOCK_RATE as u32 / 10);
+        assert!((4799..=4800).contains(&apu.take_samples().len()));
+        assert!(apu.take_samples().is_empty());
+
+        apu.set_sample_rate(22_050);
+        assert_eq!(apu.sample_rate(), 22_050);
+        run(&mut apu, CPU_CLOCK_RATE as u32 / 10);
+        assert!((2204..=2205).contains(&apu.take_samples().len()));
+    }
+
+    #[test]
+    fn mixer_adds_up_channels() {
+        let mut apu = Apu::default();
+        /* The triangle rests at step 0, which outputs 15 */
+        let rest = apu.mix();
+        assert!((rest - 159.79 / (8227.0 / 15.0 + 100.0)).abs() < 1e-6);
+
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4002, 0xFF);
+        apu.write_register(0x4003, 0x08);
+        let loudest = (0..16)
+            .map(|_| {
+                run(&mut apu, 2 * 0x100);
+                apu.mix()
+            })
+            .fold(0.0, f32::max);
+        assert!((loudest - rest - 95.88 / (8128.0 / 15.0 + 100.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn refuses_states_with_impossible_timers() {
+        let reload = |apu: &Apu| {
+            let mut out = StateWriter::new();
+            apu.save_state(&mut out);
+            let data = out.into_bytes();
+            Apu::default().load_state(&mut StateReader::new(&data))
+        };
+        let mut apu = Apu::default();
+        assert!(reload(&apu).is_ok());
+
+        apu.noise.period = 0;
+        assert_eq!(
+            reload(&apu),
+            Err("Save state has a noise period of 0".to_string())
+        );
+        apu.noise.period = NOISE_PERIODS[3];
+        apu.dmc.period = 0;
+        assert!(reload(&apu).is_err());
+        apu.dmc.period = DMC_RATES[3];
+        apu.dmc.bits_remaining = 0;
+        assert_eq!(
+            reload(&apu),
+            Err("Save state has 0 DMC bits left in the byte".to_string())
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/* Frames between checks for RAM that needs writing out, about five seconds */
+pub const BATTERY_FLUSH_FRAMES: u64 = 300;
+
+/* rom.nes keeps its save in rom.sav, next to it unless a save directory is given */
+pub fn battery_path(rom_path: &str, save_dir: Option<&str>) -> PathBuf {
+    let rom_path = Path::new(rom_path);
+    let file_name = rom_path.with_extension("sav");
+    match save_dir {
+        Some(dir) => Path::new(dir).join(file_name.file_name().unwrap_or_default()),
+        None => file_name,
+    }
+}
+
+/*
+ * Fills the RAM from the save file, if there is one. A file of the wrong
+ * size still loads as much as fits, so saves made with a different RAM
+ * size setting aren't lost.
+ */
+pub fn load_battery(path: &Path, ram: &mut [u8]) -> Result<bool, String> {
+    match fs::read(path) {
+        Ok(data) => {
+            let len = data.len().min(ram.len());
+            ram[..len].copy_from_slice(&data[..len]);
+            Ok(true)
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(format!("Failed to read {}: {}", path.display(), err)),
+    }
+}
+
+/* Writes next to the save and renames over it, so a crash can't leave half a file */
+pub fn save_battery(path: &Path, ram: &[u8]) -> Result<(), String> {
+    let temp = path.with_extension("sav.tmp");
+    fs::write(&temp, ram)
+        .and_then(|_| fs::rename(&temp, path))
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_goes_next_to_the_rom_or_into_the_save_dir() {
+        assert_eq!(
+            battery_path("games/zelda.nes", None),
+            Path::new("games/zelda.sav")
+        );
+        assert_eq!(
+            battery_path("games/zelda.nes", Some("saves")),
+            Path::new("saves").join("zelda.sav")
+        );
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let path = std::env::temp_dir().join(format!("nesemu-battery-{}.sav", std::process::id()));
+        let mut ram = vec![0; 16];
+        assert_eq!(load_battery(&path, &mut ram), Ok(false));
+
+        save_battery(&path, &[7; 8]).unwrap();
+        assert_eq!(load_battery(&path, &mut ram), Ok(true));
+        assert_eq!(ram, [[7; 8], [0; 8]].concat());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,426 @@
+use super::{
+    apu::Apu,
+    controller::{ButtonState, Controller},
+    mapper::Mapper,
+    ppu::Ppu,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+/*
+ * Everything the CPU talks to goes through a Bus. Devices mapped into a
+ * window of the address space implement it as well, so a bus can route
+ * accesses to them and they can react to reads and writes.
+ */
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+
+    /* Reads without side effects, for debuggers and disassemblers */
+    fn peek(&self, addr: u16) -> u8;
+
+    /* Little-endian 16-bit read, used for vectors */
+    fn read_word(&mut self, addr: u16) -> u16 {
+        let low_byte = self.read(addr);
+        let high_byte = self.read(addr.wrapping_add(1));
+        (high_byte as u16) << 8 | low_byte as u16
+    }
+
+    fn peek_word(&self, addr: u16) -> u16 {
+        let low_byte = self.peek(addr);
+        let high_byte = self.peek(addr.wrapping_add(1));
+        (high_byte as u16) << 8 | low_byte as u16
+    }
+}
+
+/* Turns every read into a peek, so decoding for display changes nothing */
+pub struct PeekBus<'a>(pub &'a dyn Bus);
+
+impl Bus for PeekBus<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.0.peek(addr)
+    }
+
+    fn write(&mut self, _addr: u16, _data: u8) {}
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.0.peek(addr)
+    }
+}
+
+const RAM_SIZE: usize = 0x0800;
+
+/*
+ * CPU memory map of the NES:
+ *   $0000-$1FFF  2 KiB internal RAM, mirrored four times
+ *   $2000-$3FFF  PPU registers, mirrored every 8 bytes
+ *   $4000-$4017  APU and I/O registers
+ *   $4018-$401F  APU test mode, normally disabled
+ *   $4020-$FFFF  Cartridge space
+ * Reads from anything not driven by a device return the last value seen
+ * on the data bus.
+ */
+pub struct NesBus {
+    ram: [u8; RAM_SIZE],
+    ppu: Ppu,
+    apu: Apu,
+    controllers: [Controller; 2],
+    cartridge: Option<Box<dyn Mapper>>,
+    /* The mapper's state as inserted, for power cycles */
+    cartridge_power_state: Vec<u8>,
+    open_bus: u8,
+    cycles: u64,
+    stall_cycles: u16,
+}
+
+impl Default for NesBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NesBus {
+    pub fn new() -> Self {
+        NesBus {
+            ram: [0; RAM_SIZE],
+            ppu: Ppu::new(),
+            apu: Apu::default(),
+            controllers: [Controller::new(), Controller::new()],
+            cartridge: None,
+            cartridge_power_state: Vec::new(),
+            open_bus: 0,
+            cycles: 0,
+            stall_cycles: 0,
+        }
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    /* Port 0 is read at $4016, port 1 at $4017 */
+    pub fn set_buttons(&mut self, port: usize, buttons: ButtonState) {
+        self.controllers[port].set_buttons(buttons);
+    }
+
+    pub fn buttons(&self, port: usize) -> ButtonState {
+        self.controllers[port].buttons()
+    }
+
+    pub fn attach_cartridge(&mut self, cartridge: Box<dyn Mapper>) {
+        let mut out = StateWriter::new();
+        cartridge.save_state(&mut out);
+        self.cartridge_power_state = out.into_bytes();
+        self.cartridge = Some(cartridge);
+    }
+
+    /*
+     * Clears RAM and puts the chips and the mapper back the way they
+     * were at power-up. The controllers keep their buttons, and the
+     * cycle and frame counters keep running so the machine's timeline
+     * doesn't jump back.
+     */
+    pub fn power_cycle(&mut self) {
+        self.ram = [0; RAM_SIZE];
+        self.ppu.power_cycle();
+        self.apu.power_cycle();
+        for controller in self.controllers.iter_mut() {
+            let buttons = controller.buttons();
+            *controller = Controller::new();
+            controller.set_buttons(buttons);
+        }
+        if let Some(cartridge) = self.cartridge.as_deref_mut() {
+            cartridge
+                .load_state(&mut StateReader::new(&self.cartridge_power_state))
+                .expect("a mapper reloads its own state");
+        }
+        self.open_bus = 0;
+        self.stall_cycles = 0;
+    }
+
+    pub fn cartridge(&self) -> Option<&(dyn Mapper + 'static)> {
+        self.cartridge.as_deref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut (dyn Mapper + 'static)> {
+        self.cartridge.as_deref_mut()
+    }
+
+    /* Cycles the CPU has to sit out, for DMA and DMC fetches, since the last call */
+    pub fn take_stall_cycles(&mut self) -> u16 {
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    /* Runs the other chips for the CPU cycles just spent, three PPU dots each */
+    pub fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            for _ in 0..3 {
+                self.ppu.step(self.cartridge.as_deref_mut());
+            }
+            if let Some(cartridge) = self.cartridge.as_deref_mut() {
+                cartridge.cpu_cycle();
+            }
+            self.apu.step();
+            if let Some(addr) = self.apu.dmc_fetch_addr() {
+                self.dmc_dma(addr);
+            }
+        }
+        self.cycles += cycles as u64;
+    }
+
+    /* A DMC sample fetch takes the bus away from the CPU for about four cycles */
+    fn dmc_dma(&mut self, addr: u16) {
+        let data = self.read(addr);
+        self.apu.dmc_fill(data);
+        self.stall_cycles += 4;
+    }
+
+    /*
+     * $4014 copies a whole page into OAM through $2004. The CPU is halted
+     * for 513 cycles, plus one more to line up when it starts on an odd one.
+     */
+    fn oam_dma(&mut self, page: u8) {
+        for offset in 0..=0xFF {
+            let data = self.read((page as u16) << 8 | offset);
+            self.ppu.write_oam(data);
+        }
+        self.stall_cycles += 513 + (self.cycles & 1) as u16;
+    }
+
+    pub fn mapper_irq(&self) -> bool {
+        self.cartridge
+            .as_deref()
+            .is_some_and(|cartridge| cartridge.irq_pending())
+    }
+
+    fn device(&self, addr: u16) -> Option<&dyn Bus> {
+        match addr {
+            0x4020..=0xFFFF => self.cartridge.as_deref().map(|c| c as &dyn Bus),
+            _ => None,
+        }
+    }
+
+    fn device_mut(&mut self, addr: u16) -> Option<&mut (dyn Bus + 'static)> {
+        match addr {
+            0x4020..=0xFFFF => self.cartridge.as_deref_mut().map(|c| c as &mut dyn Bus),
+            _ => None,
+        }
+    }
+}
+
+/* Devices only ever see the canonical address of a mirrored register */
+fn canonical_addr(addr: u16) -> u16 {
+    match addr {
+        0x2000..=0x3FFF => 0x2000 | (addr & 0x0007),
+        _ => addr,
+    }
+}
+
+impl Bus for NesBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize % RAM_SIZE],
+            0x2000..=0x3FFF => self
+                .ppu
+                .read_register(canonical_addr(addr), self.cartridge.as_deref_mut()),
+            /* Bit 5 isn't driven */
+            0x4015 => self.apu.read_status() | (self.open_bus & 0x20),
+            /* Only bit 0 comes from a standard controller, the top bits float */
+            0x4016 | 0x4017 => {
+                self.controllers[addr as usize - 0x4016].read() | (self.open_bus & 0xE0)
+            }
+            _ => match self.device_mut(addr) {
+                Some(device) => device.read(canonical_addr(addr)),
+                None => self.open_bus,
+            },
+        };
+        self.open_bus = data;
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize % RAM_SIZE] = data,
+            0x2000..=0x3FFF => {
+                self.ppu
+                    .write_register(canonical_addr(addr), data, self.cartridge.as_deref_mut())
+            }
+            0x4014 => self.oam_dma(data),
+            0x4016 => {
+                for controller in self.controllers.iter_mut() {
+                    controller.write_strobe(data);
+                }
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr, data),
+            _ => {
+                if let Some(device) = self.device_mut(addr) {
+                    device.write(canonical_addr(addr), data);
+                }
+            }
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize % RAM_SIZE],
+            0x2000..=0x3FFF => self.ppu.peek_register(addr),
+            0x4015 => self.apu.peek_status() | (self.open_bus & 0x20),
+            0x4016 | 0x4017 => {
+                self.controllers[addr as usize - 0x4016].peek() | (self.open_bus & 0xE0)
+            }
+            _ => match self.device(addr) {
+                Some(device) => device.peek(canonical_addr(addr)),
+                None => self.open_bus,
+            },
+        }
+    }
+}
+
+impl Snapshot for NesBus {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.ram);
+        out.u8(self.open_bus);
+        out.u64(self.cycles);
+        out.u16(self.stall_cycles);
+        for controller in &self.controllers {
+            controller.save_state(out);
+        }
+        self.ppu.save_state(out);
+        self.apu.save_state(out);
+        out.bool(self.cartridge.is_some());
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.save_state(out);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.ram)?;
+        self.open_bus = input.u8()?;
+        self.cycles = input.u64()?;
+        self.stall_cycles = input.u16()?;
+        for controller in self.controllers.iter_mut() {
+            controller.load_state(input)?;
+        }
+        self.ppu.load_state(input)?;
+        self.apu.load_state(input)?;
+        match (input.bool()?, &mut self.cartridge) {
+            (true, Some(cartridge)) => cartridge.load_state(input),
+            (false, None) => Ok(()),
+            _ => Err("Save state doesn't match the inserted cartridge".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::mapper::{nrom::Nrom, tests::cartridge};
+
+    #[test]
+    fn ram_is_mirrored_every_2k() {
+        let mut bus = NesBus::new();
+        bus.write(0x0012, 0xAB);
+        assert_eq!(bus.read(0x0812), 0xAB);
+        assert_eq!(bus.read(0x1012), 0xAB);
+        bus.write(0x1FFF, 0xCD);
+        assert_eq!(bus.peek(0x07FF), 0xCD);
+    }
+
+    #[test]
+    fn ppu_registers_are_mirrored_every_8_bytes() {
+        let mut bus = NesBus::new();
+        /* $2006 twice, then $2007 */
+        bus.write(0x200E, 0x24);
+        bus.write(0x3456, 0x00);
+        bus.write(0x3FFF, 0xAB);
+
+        bus.write(0x2006, 0x24);
+        bus.write(0x2006, 0x00);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2FEF), 0xAB);
+    }
+
+    #[test]
+    fn devices_get_their_windows() {
+        let mut bus = NesBus::new();
+        bus.attach_cartridge(Box::new(Nrom::new(cartridge(0, 2, 1))));
+
+        bus.write(0x4015, 0x01);
+        bus.write(0x4003, 0x08);
+        assert_eq!(bus.read(0x4015) & 0x1F, 0x01);
+
+        bus.write(0x6000, 0x12);
+        assert_eq!(bus.read(0x6000), 0x12);
+        assert_eq!(bus.peek(0xA000), 1);
+        assert_eq!(bus.peek(0xFFFC), 0);
+    }
+
+    #[test]
+    fn dmc_fetches_steal_cpu_cycles() {
+        let mut bus = NesBus::new();
+        bus.attach_cartridge(Box::new(Nrom::new(cartridge(0, 2, 1))));
+        bus.write(0x4012, 0x00);
+        bus.write(0x4013, 0x01);
+        bus.write(0x4015, 0x10);
+
+        bus.tick(1);
+        assert_eq!(bus.take_stall_cycles(), 4);
+        bus.tick(1);
+        assert_eq!(bus.take_stall_cycles(), 0);
+    }
+
+    #[test]
+    fn unmapped_reads_return_open_bus() {
+        let mut bus = NesBus::new();
+        bus.write(0x0000, 0x42);
+        bus.read(0x0000);
+        assert_eq!(bus.read(0x4018), 0x42);
+        assert_eq!(bus.read(0x8000), 0x42);
+    }
+
+    #[test]
+    fn controllers_are_read_serially_on_both_ports() {
+        let mut bus = NesBus::new();
+        bus.set_buttons(0, ButtonState::START);
+        bus.set_buttons(1, ButtonState::A | ButtonState::B);
+        bus.write(0x4016, 0x01);
+        bus.write(0x4016, 0x00);
+
+        let mut port = |addr| -> Vec<u8> { (0..8).map(|_| bus.read(addr) & 0x01).collect() };
+        assert_eq!(port(0x4016), vec![0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(port(0x4017), vec![1, 1, 0, 0, 0, 0, 0, 0]);
+
+        /* The upper bits keep whatever was last on the bus, here the high byte of $4016 */
+        bus.write(0x0000, 0x40);
+        bus.read(0x0000);
+        assert_eq!(bus.read(0x4016), 0x41);
+        assert_eq!(bus.peek(0x4017), 0x41);
+    }
+
+    #[test]
+    fn oam_dma_copies_a_page_and_stalls_the_cpu() {
+        let mut bus = NesBus::new();
+        for offset in 0..=0xFF {
+            bus.write(0x0200 + offset, offset as u8 ^ 0x5A);
+        }
+        bus.write(0x2003, 0x00);
+        bus.write(0x4014, 0x02);
+        assert_eq!(bus.take_stall_cycles(), 513);
+        assert_eq!(bus.take_stall_cycles(), 0);
+
+        bus.write(0x2003, 0x10);
+        assert_eq!(bus.read(0x2004), 0x10 ^ 0x5A);
+
+        bus.tick(1);
+        bus.write(0x4014, 0x02);
+        assert_eq!(bus.take_stall_cycles(), 514);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{fs::File, io::BufWriter, io::Write, path::Path};
+
+use super::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn parse(name: &str) -> Result<ImageFormat, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "ppm" => Ok(ImageFormat::Ppm),
+            "png" => Ok(ImageFormat::Png),
+            _ => Err(format!("Unknown image format: {}", name)),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+/* Which frames a headless run writes out, and where */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureOptions {
+    pub frames: Vec<u64>,
+    pub every: Option<u64>,
+    pub dir: String,
+    pub format: ImageFormat,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        CaptureOptions {
+            frames: Vec::new(),
+            every: None,
+            dir: ".".to_string(),
+            format: ImageFormat::Png,
+        }
+    }
+}
+
+impl CaptureOptions {
+    pub fn wants(&self, frame: u64) -> bool {
+        self.frames.contains(&frame) || self.every.is_some_and(|every| frame.is_multiple_of(every))
+    }
+
+    pub fn path_for(&self, frame: u64) -> String {
+        Path::new(&self.dir)
+            .join(format!("frame_{:05}.{}", frame, self.format.extension()))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/* Binary PPM (P6), the simplest format any image tool reads */
+pub fn write_ppm(out: &mut dyn Write, frame: &[u8]) -> std::io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT)?;
+    out.write_all(frame)
+}
+
+pub fn write_png(out: &mut dyn Write, frame: &[u8]) -> Result<(), String> {
+    let mut encoder = png::Encoder::new(out, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+    writer
+        .write_image_data(frame)
+        .map_err(|err| err.to_string())
+}
+
+pub fn save_frame(path: &str, format: ImageFormat, frame: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| format!("Failed to create {}: {}", path, err))?;
+    let mut out = BufWriter::new(file);
+    match format {
+        ImageFormat::Ppm => write_ppm(&mut out, frame).map_err(|err| err.to_string()),
+        ImageFormat::Png => write_png(&mut out, frame),
+    }
+    .and_then(|_| out.flush().map_err(|err| err.to_string()))
+    .map_err(|err| format!("Failed to write {}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> Vec<u8> {
+        (0..SCREEN_WIDTH * SCREEN_HEIGHT * 3)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn writes_ppm() {
+        let frame = gradient();
+        let mut out = Vec::new();
+        write_ppm(&mut out, &frame).unwrap();
+        let header = b"P6\n256 240\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..], &frame[..]);
+    }
+
+    #[test]
+    fn writes_png_that_decodes_back() {
+        let frame = gradient();
+        let mut out = Vec::new();
+        write_png(&mut out, &frame).unwrap();
+
+        let decoder = png::Decoder::new(&out[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (256, 240));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(pixels, frame);
+    }
+
+    #[test]
+    fn selects_frames() {
+        let options = CaptureOptions {
+            frames: vec![1, 7],
+            every: Some(5),
+            dir: "out".to_string(),
+            format: ImageFormat::parse("PPM").unwrap(),
+        };
+        let picked: Vec<u64> = (1..=12).filter(|&frame| options.wants(frame)).collect();
+        assert_eq!(picked, vec![1, 5, 7, 10]);
+        assert_eq!(
+            options.path_for(42),
+            Path::new("out").join("frame_00042.ppm").to_string_lossy()
+        );
+        assert!(ImageFormat::parse("bmp").is_err());
+    }
+}
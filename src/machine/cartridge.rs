@@ -0,0 +1,312 @@
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const DEFAULT_PRG_RAM_SIZE: usize = 0x2000;
+const DEFAULT_CHR_RAM_SIZE: usize = 0x2000;
+const MAGIC: [u8; 4] = *b"NES\x1A";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingRegion {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+/*
+ * Everything the 16-byte iNES header tells about the board. NES 2.0
+ * headers fill in the fields iNES leaves at their defaults.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomHeader {
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: TimingRegion,
+}
+
+/*
+ * NES 2.0 ROM sizes: a plain count of banks, or when the MSB nibble is
+ * $F, an exponent-multiplier pair packed in the LSB byte.
+ */
+fn nes2_rom_size(lsb: u8, msb: u8, bank_size: usize) -> Result<usize, String> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .ok_or_else(|| format!("ROM size 2^{}*{} is too large", exponent, multiplier))
+    } else {
+        Ok(((msb as usize) << 8 | lsb as usize) * bank_size)
+    }
+}
+
+/* NES 2.0 RAM sizes are shift counts, zero meaning no RAM at all */
+fn nes2_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        _ => 64 << shift,
+    }
+}
+
+impl RomHeader {
+    pub fn parse(data: &[u8]) -> Result<RomHeader, String> {
+        if data.len() < HEADER_SIZE {
+            return Err(format!(
+                "File is too short for an iNES header ({} bytes)",
+                data.len()
+            ));
+        }
+        if data[0..4] != MAGIC {
+            return Err("Not an iNES file (missing NES<EOF> signature)".to_string());
+        }
+
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let nes2 = flags7 & 0x0C == 0x08;
+
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = flags6 & 0x02 != 0;
+        let trainer = flags6 & 0x04 != 0;
+        let mapper_low = (flags6 >> 4) as u16;
+
+        let header = if nes2 {
+            let prg_rom_size = nes2_rom_size(data[4], data[9] & 0x0F, PRG_BANK_SIZE)?;
+            let chr_rom_size = nes2_rom_size(data[5], data[9] >> 4, CHR_BANK_SIZE)?;
+            RomHeader {
+                nes2,
+                mapper: mapper_low | (flags7 & 0xF0) as u16 | ((data[8] & 0x0F) as u16) << 8,
+                submapper: data[8] >> 4,
+                prg_rom_size,
+                chr_rom_size,
+                prg_ram_size: nes2_ram_size(data[10] & 0x0F),
+                prg_nvram_size: nes2_ram_size(data[10] >> 4),
+                chr_ram_size: nes2_ram_size(data[11] & 0x0F),
+                mirroring,
+                battery,
+                trainer,
+                timing: match data[12] & 0x03 {
+                    0 => TimingRegion::Ntsc,
+                    1 => TimingRegion::Pal,
+                    2 => TimingRegion::MultiRegion,
+                    _ => TimingRegion::Dendy,
+                },
+            }
+        } else {
+            /*
+             * Old dumping tools wrote their name into bytes 7-15, so the
+             * upper mapper nibble is only trusted when the tail is clean.
+             */
+            let clean_tail = flags7 & 0x0C == 0 && data[12..16].iter().all(|&b| b == 0);
+            let mapper_high = if clean_tail {
+                (flags7 & 0xF0) as u16
+            } else {
+                0
+            };
+            let chr_rom_size = data[5] as usize * CHR_BANK_SIZE;
+            let prg_ram_size = match data[8] {
+                0 => DEFAULT_PRG_RAM_SIZE,
+                banks => banks as usize * DEFAULT_PRG_RAM_SIZE,
+            };
+            RomHeader {
+                nes2,
+                mapper: mapper_low | mapper_high,
+                submapper: 0,
+                prg_rom_size: data[4] as usize * PRG_BANK_SIZE,
+                chr_rom_size,
+                /* iNES cannot tell battery-backed RAM apart from plain RAM */
+                prg_ram_size: if battery { 0 } else { prg_ram_size },
+                prg_nvram_size: if battery { prg_ram_size } else { 0 },
+                chr_ram_size: if chr_rom_size == 0 {
+                    DEFAULT_CHR_RAM_SIZE
+                } else {
+                    0
+                },
+                mirroring,
+                battery,
+                trainer,
+                timing: if clean_tail && data[9] & 0x01 != 0 {
+                    TimingRegion::Pal
+                } else {
+                    TimingRegion::Ntsc
+                },
+            }
+        };
+
+        if header.prg_rom_size == 0 {
+            return Err("Header declares no PRG ROM".to_string());
+        }
+
+        Ok(header)
+    }
+}
+
+pub struct Cartridge {
+    pub header: RomHeader,
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+}
+
+impl Cartridge {
+    pub fn from_bytes(data: &[u8]) -> Result<Cartridge, String> {
+        let header = RomHeader::parse(data)?;
+
+        let trainer_size = if header.trainer { TRAINER_SIZE } else { 0 };
+        /* Exponent sizes can add up to more than fits in a usize */
+        let expected = (HEADER_SIZE + trainer_size)
+            .checked_add(header.prg_rom_size)
+            .and_then(|size| size.checked_add(header.chr_rom_size))
+            .ok_or("Header declares more ROM than can be addressed")?;
+        if data.len() < expected {
+            return Err(format!(
+                "File is truncated: header declares {} bytes, file has {}",
+                expected,
+                data.len()
+            ));
+        }
+
+        let mut offset = HEADER_SIZE;
+        let mut take = |size: usize| {
+            let chunk = data[offset..offset + size].to_vec();
+            offset += size;
+            chunk
+        };
+        let trainer = header.trainer.then(|| take(TRAINER_SIZE));
+        let prg_rom = take(header.prg_rom_size);
+        let chr_rom = take(header.chr_rom_size);
+
+        let mut prg_ram =
+            vec![0; (header.prg_ram_size + header.prg_nvram_size).max(DEFAULT_PRG_RAM_SIZE)];
+        /* The trainer lives at $7000-$71FF */
+        if let Some(trainer) = &trainer {
+            prg_ram[0x1000..0x1000 + TRAINER_SIZE].copy_from_slice(trainer);
+        }
+
+        Ok(Cartridge {
+            header,
+            trainer,
+            prg_rom,
+            chr_rom,
+            prg_ram,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Cartridge, String> {
+        let data =
+            std::fs::read(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        Cartridge::from_bytes(&data).map_err(|err| format!("{}: {}", path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(header: [u8; 16], extra: usize) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(HEADER_SIZE + extra, 0);
+        data
+    }
+
+    #[test]
+    fn parses_ines_header() {
+        let header = RomHeader::parse(b"NES\x1A\x02\x01\x13\x40\0\0\0\0\0\0\0\0").unwrap();
+        assert!(!header.nes2);
+        assert_eq!(header.mapper, 0x41);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert!(header.battery);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert!(!header.trainer);
+        assert_eq!(header.timing, TimingRegion::Ntsc);
+    }
+
+    #[test]
+    fn ignores_upper_mapper_nibble_with_junk_tail() {
+        let header = RomHeader::parse(b"NES\x1A\x01\x00\x10DiskDude!").unwrap();
+        assert_eq!(header.mapper, 1);
+        assert_eq!(header.chr_ram_size, 0x2000);
+    }
+
+    #[test]
+    fn parses_nes2_header() {
+        let header =
+            RomHeader::parse(b"NES\x1A\x02\x00\x02\x48\x31\x10\x70\x07\x01\0\0\0").unwrap();
+        assert!(header.nes2);
+        assert_eq!(header.mapper, 0x140);
+        assert_eq!(header.submapper, 3);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x100 * 0x2000);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+        assert_eq!(header.chr_ram_size, 0x2000);
+        assert_eq!(header.mirroring, Mirroring::Horizontal);
+        assert!(header.battery);
+        assert_eq!(header.timing, TimingRegion::Pal);
+    }
+
+    #[test]
+    fn parses_nes2_exponent_sizes() {
+        /* 2^5 * 3 bytes of PRG ROM */
+        let header = RomHeader::parse(b"NES\x1A\x15\x00\x00\x08\0\x0F\0\0\0\0\0\0").unwrap();
+        assert_eq!(header.prg_rom_size, 96);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(RomHeader::parse(b"NES\x1A").is_err());
+        assert!(RomHeader::parse(b"NES\x00\x01\0\0\0\0\0\0\0\0\0\0\0").is_err());
+        assert!(RomHeader::parse(b"NES\x1A\x00\0\0\0\0\0\0\0\0\0\0\0").is_err());
+
+        let truncated = image(*b"NES\x1A\x02\x01\0\0\0\0\0\0\0\0\0\0", 0x8000);
+        let err = Cartridge::from_bytes(&truncated).err().unwrap();
+        assert!(err.contains("truncated"), "{}", err);
+
+        /* 2^61 * 7 bytes each of PRG and CHR ROM */
+        let huge = b"NES\x1A\xF7\xF7\x00\x08\0\xFF\0\0\0\0\0\0";
+        assert_eq!(
+            Cartridge::from_bytes(huge).err().unwrap(),
+            "Header declares more ROM than can be addressed"
+        );
+    }
+
+    #[test]
+    fn loads_trainer_into_prg_ram() {
+        let mut data = image(*b"NES\x1A\x01\x00\x04\0\0\0\0\0\0\0\0\0", 512 + 0x4000);
+        data[HEADER_SIZE] = 0xAA;
+        data[HEADER_SIZE + 512] = 0x4C;
+        let cartridge = Cartridge::from_bytes(&data).unwrap();
+        assert_eq!(cartridge.prg_ram[0x1000], 0xAA);
+        assert_eq!(cartridge.prg_rom[0], 0x4C);
+        assert_eq!(cartridge.prg_rom.len(), 0x4000);
+    }
+}
@@ -0,0 +1,153 @@
+use super::state::{Snapshot, StateReader, StateWriter};
+
+/*
+ * Buttons of a standard joypad, one bit each, in the order the shift
+ * register hands them to the CPU.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ButtonState(pub u8);
+
+impl ButtonState {
+    pub const A: ButtonState = ButtonState(0x01);
+    pub const B: ButtonState = ButtonState(0x02);
+    pub const SELECT: ButtonState = ButtonState(0x04);
+    pub const START: ButtonState = ButtonState(0x08);
+    pub const UP: ButtonState = ButtonState(0x10);
+    pub const DOWN: ButtonState = ButtonState(0x20);
+    pub const LEFT: ButtonState = ButtonState(0x40);
+    pub const RIGHT: ButtonState = ButtonState(0x80);
+
+    pub fn empty() -> Self {
+        ButtonState(0)
+    }
+
+    pub fn contains(&self, buttons: ButtonState) -> bool {
+        self.0 & buttons.0 == buttons.0
+    }
+
+    pub fn set(&mut self, buttons: ButtonState, pressed: bool) {
+        if pressed {
+            self.0 |= buttons.0;
+        } else {
+            self.0 &= !buttons.0;
+        }
+    }
+}
+
+impl std::ops::BitOr for ButtonState {
+    type Output = ButtonState;
+
+    fn bitor(self, other: ButtonState) -> ButtonState {
+        ButtonState(self.0 | other.0)
+    }
+}
+
+/*
+ * The 4021 shift register in a standard controller. While the strobe is
+ * high it keeps reloading the buttons, so every read returns A. Once it
+ * drops, each read shifts out the next button, and after all eight the
+ * register is empty and reads return 1.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Controller {
+    buttons: ButtonState,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buttons(&self) -> ButtonState {
+        self.buttons
+    }
+
+    pub fn set_buttons(&mut self, buttons: ButtonState) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons.0;
+        }
+    }
+
+    pub fn write_strobe(&mut self, data: u8) {
+        self.strobe = data & 0x01 != 0;
+        if self.strobe {
+            self.shift = self.buttons.0;
+        }
+    }
+
+    /* Bit 0 of the next serial read, without shifting */
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            self.buttons.0 & 0x01
+        } else {
+            self.shift & 0x01
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let data = self.peek();
+        if !self.strobe {
+            self.shift = 0x80 | (self.shift >> 1);
+        }
+        data
+    }
+}
+
+impl Snapshot for Controller {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u8(self.buttons.0);
+        out.u8(self.shift);
+        out.bool(self.strobe);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.buttons = ButtonState(input.u8()?);
+        self.shift = input.u8()?;
+        self.strobe = input.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifts_out_buttons_in_order() {
+        let mut controller = Controller::new();
+        controller.set_buttons(ButtonState::A | ButtonState::START | ButtonState::RIGHT);
+        controller.write_strobe(1);
+        controller.write_strobe(0);
+        let bits: Vec<u8> = (0..10).map(|_| controller.read()).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn strobe_high_keeps_returning_a() {
+        let mut controller = Controller::new();
+        controller.write_strobe(1);
+        controller.set_buttons(ButtonState::A);
+        assert_eq!(controller.read(), 1);
+        assert_eq!(controller.read(), 1);
+        controller.set_buttons(ButtonState::B);
+        assert_eq!(controller.read(), 0);
+
+        /* Changes after the strobe drops wait for the next one */
+        controller.write_strobe(0);
+        controller.set_buttons(ButtonState::A);
+        assert_eq!(controller.read(), 0);
+        assert_eq!(controller.read(), 1);
+    }
+
+    #[test]
+    fn button_state_sets_and_clears() {
+        let mut buttons = ButtonState::empty();
+        buttons.set(ButtonState::UP | ButtonState::B, true);
+        assert!(buttons.contains(ButtonState::UP));
+        buttons.set(ButtonState::UP, false);
+        assert_eq!(buttons, ButtonState::B);
+    }
+}
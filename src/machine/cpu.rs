@@ -1,9 +1,19 @@
 use super::{
-    instruction::{ADCInst, InstEXE, Instruction, LDAInst},
-    memory::Memory,
+    bus::{Bus, PeekBus},
+    instruction::{
+        ADCInst, ALRInst, ANCInst, ANDInst, ARRInst, ASLInst, AXSInst, AddressingMode, BCCInst,
+        BCSInst, BEQInst, BITInst, BMIInst, BNEInst, BPLInst, BRKInst, BVCInst, BVSInst, CLCInst,
+        CLDInst, CLIInst, CLVInst, CMPInst, CPXInst, CPYInst, DCPInst, DECInst, DEXInst, DEYInst,
+        EORInst, INCInst, INXInst, INYInst, ISCInst, InstEXE, Instruction, JAMInst, JMPInst,
+        JSRInst, LASInst, LAXInst, LDAInst, LDXInst, LDYInst, LSRInst, LXAInst, NOPInst, ORAInst,
+        PHAInst, PHPInst, PLAInst, PLPInst, RLAInst, ROLInst, RORInst, RRAInst, RTIInst, RTSInst,
+        SAXInst, SBCInst, SECInst, SEDInst, SEIInst, SHAInst, SHXInst, SHYInst, SLOInst, SREInst,
+        STAInst, STXInst, STYInst, TASInst, TAXInst, TAYInst, TSXInst, TXAInst, TXSInst, TYAInst,
+        XAAInst, OPCODE_TABLE,
+    },
     monitor::MonitorState,
+    state::{Snapshot, StateReader, StateWriter},
 };
-// use super::instruction::
 
 pub struct StatusRegister {
     pub negative: bool,
@@ -29,6 +39,31 @@ impl std::convert::From<&StatusRegister> for u8 {
     }
 }
 
+/*
+ * Bits 5 and 4 (the "B flag") don't exist in the register itself, they
+ * only show up in the copy pushed on the stack, so pulling P ignores them.
+ */
+impl std::convert::From<u8> for StatusRegister {
+    fn from(x: u8) -> Self {
+        StatusRegister {
+            negative: (x & 0b1000_0000) != 0,
+            overflow: (x & 0b0100_0000) != 0,
+            b_high: false,
+            b_low: false,
+            decimal: (x & 0b0000_1000) != 0,
+            interrupt_disable: (x & 0b0000_0100) != 0,
+            zero: (x & 0b0000_0010) != 0,
+            carry: (x & 0b0000_0001) != 0,
+        }
+    }
+}
+
+impl Default for StatusRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StatusRegister {
     pub fn new() -> Self {
         StatusRegister {
@@ -50,9 +85,22 @@ pub struct CPU {
     pub y: u8,
     pub status: StatusRegister,
     pub pc: u16,
+    pub sp: u8,
+    pub variant: CpuVariant,
+
+    nmi_line: bool,
+    nmi_pending: bool,
+    irq_lines: u8,
+    /* The I flag as seen by the interrupt poll at the end of the last instruction */
+    irq_poll_disable: bool,
+
+    pub cycles: u64,
+    page_crossed: bool,
+    dummy_read_addr: Option<u16>,
+    extra_cycles: u8,
 }
 
-enum StatusRegBit {
+pub enum StatusRegBit {
     Negative,
     Overflow,
     BHigh,
@@ -63,9 +111,31 @@ enum StatusRegBit {
     Carry,
 }
 
-const OPERAND_SINGLE_ENCODING: u8 = 1;
-const OPERAND_DOUBLE_ENCODING: u8 = 2;
-const OPERAND_NON: u8 = 0;
+/* The hardware stack lives in page $01 and grows downwards */
+const STACK_BASE: u16 = 0x0100;
+
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+
+/* Reset, NMI and IRQ sequences all take as long as BRK */
+const INTERRUPT_CYCLES: u8 = 7;
+
+/*
+ * Every device that can pull the shared /IRQ line low owns one bit,
+ * the line stays asserted as long as any of them holds it.
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum IrqSource {
+    FrameCounter = 0b0001,
+    Dmc = 0b0010,
+    Mapper = 0b0100,
+    External = 0b1000,
+}
+
+pub const OPERAND_SINGLE_ENCODING: u8 = 1;
+pub const OPERAND_DOUBLE_ENCODING: u8 = 2;
+pub const OPERAND_NON: u8 = 0;
 
 #[derive(Debug)]
 pub enum OperandType {
@@ -77,1057 +147,365 @@ pub enum OperandType {
     Indirect,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuVariant {
+    /* Stock NMOS 6502, ADC and SBC honour the decimal flag */
+    Nmos6502,
+    /* The NES CPU, D can be set and cleared but the BCD logic is cut off */
+    Ricoh2A03,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CPU {
     pub fn new() -> Self {
+        CPU::with_variant(CpuVariant::Ricoh2A03)
+    }
+
+    /* Power-on state, a reset sequence still has to run before the first fetch */
+    pub fn with_variant(variant: CpuVariant) -> Self {
+        let mut status = StatusRegister::new();
+        status.interrupt_disable = true;
         CPU {
             a: 0,
             x: 0,
             y: 0,
-            status: StatusRegister::new(),
+            status,
             pc: 0,
+            sp: 0,
+            variant,
+
+            nmi_line: false,
+            nmi_pending: false,
+            irq_lines: 0,
+            irq_poll_disable: true,
+
+            cycles: 0,
+            page_crossed: false,
+            dummy_read_addr: None,
+            extra_cycles: 0,
         }
     }
 
-    pub fn reset(&mut self) {
-        self.a = 0;
-        self.x = 0;
-        self.y = 0;
-        self.status = StatusRegister::new();
-        self.pc = 0; /* TODO: Does PC go from 0x0? */
+    /*
+     * Reset runs the interrupt sequence with the bus in read mode: the
+     * three stack "pushes" only decrement SP, A/X/Y are left alone, I is
+     * set and PC is loaded from the $FFFC/$FFFD vector.
+     */
+    pub fn reset(&mut self, bus: &mut dyn Bus) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.status.interrupt_disable = true;
+        self.pc = bus.read_word(RESET_VECTOR);
+
+        self.nmi_pending = false;
+        self.irq_poll_disable = true;
+        self.cycles += INTERRUPT_CYCLES as u64;
     }
 
-    fn _resolve_imm_opnd(&mut self, memory: &Memory) -> u16 {
-        let operand = memory.read(self.pc);
-        self.pc += 1;
-        operand as u16
+    /*
+     * /NMI is edge triggered: only a high-to-low transition of the line
+     * (asserting it) latches an NMI, holding it asserted doesn't repeat.
+     */
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
     }
 
-    fn _resolve_zero_page_opnd(&mut self, memory: &Memory) -> u16 {
-        let zero_page_addr = memory.read(self.pc);
-        self.pc += 1;
-        zero_page_addr as u16
+    /* /IRQ is level triggered and is serviced for as long as it's held */
+    pub fn set_irq(&mut self, source: IrqSource, asserted: bool) {
+        if asserted {
+            self.irq_lines |= source as u8;
+        } else {
+            self.irq_lines &= !(source as u8);
+        }
     }
 
-    fn _resolve_zero_page_x_opnd(&mut self, memory: &Memory) -> u16 {
-        let zero_page_addr = memory.read(self.pc);
-        self.pc += 1;
-        (zero_page_addr + self.x) as u16
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi_pending
     }
 
-    fn _resolve_zero_page_y_opnd(&mut self, memory: &Memory) -> u16 {
-        let zero_page_addr = memory.read(self.pc);
-        self.pc += 1;
-        (zero_page_addr + self.y) as u16
+    pub fn irq_asserted(&self) -> bool {
+        self.irq_lines != 0
     }
 
-    fn _resolve_absolute_opnd(&mut self, memory: &Memory) -> u16 {
-        let low_byte = memory.read(self.pc);
-        self.pc += 1;
-        let high_byte = memory.read(self.pc);
-        self.pc += 1;
-        (high_byte as u16) << 8 | low_byte as u16
+    /*
+     * Shared tail of BRK, IRQ and NMI: push PC and P, set I and jump
+     * through the vector. An NMI that arrives before the vector fetch
+     * hijacks a BRK/IRQ sequence, the pushed B bit is kept as is but the
+     * NMI handler runs instead.
+     */
+    pub fn interrupt(&mut self, bus: &mut dyn Bus, return_addr: u16, brk: bool, vector: u16) {
+        self.push_word(bus, return_addr);
+        self.push_status(bus, brk);
+        self.status.interrupt_disable = true;
+
+        let vector = if self.nmi_pending {
+            self.nmi_pending = false;
+            NMI_VECTOR
+        } else {
+            vector
+        };
+        self.pc = bus.read_word(vector);
     }
 
-    fn _resolve_absolute_x_opnd(&mut self, memory: &Memory) -> u16 {
-        let low_byte = memory.read(self.pc);
-        self.pc += 1;
-        let high_byte = memory.read(self.pc);
-        self.pc += 1;
-        ((high_byte as u16) << 8 | low_byte as u16) + self.x as u16
+    /* Returns true if an interrupt sequence took the place of the next instruction */
+    fn poll_interrupts(&mut self, bus: &mut dyn Bus) -> bool {
+        if self.nmi_pending {
+            self.interrupt(bus, self.pc, false, NMI_VECTOR);
+            true
+        } else if self.irq_asserted() && !self.irq_poll_disable {
+            self.interrupt(bus, self.pc, false, IRQ_VECTOR);
+            true
+        } else {
+            false
+        }
     }
 
-    fn _resolve_absolute_y_opnd(&mut self, memory: &Memory) -> u16 {
-        let low_byte = memory.read(self.pc);
-        self.pc += 1;
-        let high_byte = memory.read(self.pc);
-        self.pc += 1;
-        ((high_byte as u16) << 8 | low_byte as u16) + self.y as u16
+    fn _fetch_byte(&mut self, bus: &mut dyn Bus) -> u8 {
+        let data = bus.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        data
     }
 
-    fn _resolve_indirect_opnd(&mut self, memory: &Memory) -> u16 {
-        let low_byte = memory.read(self.pc);
-        self.pc += 1;
-        let high_byte = memory.read(self.pc);
-        self.pc += 1;
-        let indirect_addr = ((high_byte as u16) << 8 | low_byte as u16) + self.x as u16;
-        let low_byte = memory.read(indirect_addr);
-        let high_byte = memory.read(indirect_addr + 1);
+    fn _fetch_word(&mut self, bus: &mut dyn Bus) -> u16 {
+        let low_byte = self._fetch_byte(bus);
+        let high_byte = self._fetch_byte(bus);
         (high_byte as u16) << 8 | low_byte as u16
     }
 
-    fn _resolve_index_indirect_opnd(&mut self, memory: &Memory) -> u16 {
-        let base_addr = memory.read(self.pc);
-        self.pc += 1;
-        let addr = (base_addr + self.x) as u16;
-        let low_byte = memory.read(addr);
-        let high_byte = memory.read(addr + 1);
+    /* Pointers in zero page wrap around within it, $FF is followed by $00 */
+    fn _read_zero_page_word(bus: &mut dyn Bus, ptr: u8) -> u16 {
+        let low_byte = bus.read(ptr as u16);
+        let high_byte = bus.read(ptr.wrapping_add(1) as u16);
         (high_byte as u16) << 8 | low_byte as u16
     }
 
-    fn _resolve_indirect_index_opnd(&mut self, memory: &Memory) -> u16 {
-        let indirect_addr = memory.read(self.pc);
-        self.pc += 1;
-        let low_byte = memory.read(indirect_addr as u16);
-        let high_byte = memory.read((indirect_addr + 1) as u16);
-        ((high_byte as u16) << 8 | low_byte as u16) + self.y as u16
-    }
-
-    fn fetch_inst(&mut self, memory: &Memory) -> Instruction {
-        let opcode = memory.read(self.pc);
-        self.pc += 1;
-
-        /* TODO: Refactor the process to fetch operand for some insts */
-        match opcode {
-            0x69 => Instruction::ADC(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-            0x65 => Instruction::ADC(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-            0x75 => Instruction::ADC(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x6D => Instruction::ADC(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x7D => Instruction::ADC(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x79 => Instruction::ADC(
-                opcode,
-                self._resolve_absolute_y_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x61 => Instruction::ADC(
-                opcode,
-                self._resolve_index_indirect_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x71 => Instruction::ADC(
-                opcode,
-                self._resolve_indirect_index_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x29 => Instruction::AND(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0x25 => Instruction::AND(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x35 => Instruction::AND(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x2D => Instruction::AND(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x3D => Instruction::AND(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x39 => Instruction::AND(
-                opcode,
-                self._resolve_absolute_y_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x21 => Instruction::AND(
-                opcode,
-                self._resolve_index_indirect_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x31 => Instruction::AND(
-                opcode,
-                self._resolve_indirect_index_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x0A => Instruction::ASL(opcode, 0, OPERAND_NON, OperandType::Accumulator),
-
-            0x06 => Instruction::ASL(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x16 => Instruction::ASL(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x0E => Instruction::ASL(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x1E => Instruction::ASL(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x90 => Instruction::BCC(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Relative,
-            ),
-
-            0xB0 => Instruction::BCS(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Relative,
-            ),
-
-            0xF0 => Instruction::BEQ(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Relative,
-            ),
-
-            0x24 => Instruction::BIT(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x2C => Instruction::BIT(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x30 => Instruction::BMI(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Relative,
-            ),
-
-            0xD0 => Instruction::BNE(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Relative,
-            ),
-
-            0x10 => Instruction::BPL(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Relative,
-            ),
-
-            0x00 => Instruction::BRK(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x50 => Instruction::BVC(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Relative,
-            ),
-
-            0x70 => Instruction::BVS(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Relative,
-            ),
-
-            0x18 => Instruction::CLC(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0xD8 => Instruction::CLD(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x58 => Instruction::CLI(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0xB8 => Instruction::CLV(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0xC9 => Instruction::CMP(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0xC5 => Instruction::CMP(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xD5 => Instruction::CMP(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xCD => Instruction::CMP(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xDD => Instruction::CMP(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xD9 => Instruction::CMP(
-                opcode,
-                self._resolve_absolute_y_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xC1 => Instruction::CMP(
-                opcode,
-                self._resolve_index_indirect_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xD1 => Instruction::CMP(
-                opcode,
-                self._resolve_indirect_index_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xE0 => Instruction::CPX(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0xE4 => Instruction::CPX(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xEC => Instruction::CPX(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xC0 => Instruction::CPY(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0xC4 => Instruction::CPY(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xCC => Instruction::CPY(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xC6 => Instruction::DEC(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xD6 => Instruction::DEC(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xCE => Instruction::DEC(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xDE => Instruction::DEC(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xCA => Instruction::DEX(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x88 => Instruction::DEY(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x49 => Instruction::EOR(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0x45 => Instruction::EOR(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x55 => Instruction::EOR(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x4D => Instruction::EOR(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x5D => Instruction::EOR(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x59 => Instruction::EOR(
-                opcode,
-                self._resolve_absolute_y_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x41 => Instruction::EOR(
-                opcode,
-                self._resolve_index_indirect_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x51 => Instruction::EOR(
-                opcode,
-                self._resolve_indirect_index_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xE6 => Instruction::INC(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xF6 => Instruction::INC(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xEE => Instruction::INC(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xFE => Instruction::INC(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xE8 => Instruction::INX(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0xC8 => Instruction::INY(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x4C => Instruction::JMP(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x6C => Instruction::JMP(
-                opcode,
-                self._resolve_indirect_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Indirect,
-            ),
-
-            0x20 => Instruction::JSR(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xA9 => Instruction::LDA(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0xA5 => Instruction::LDA(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xB5 => Instruction::LDA(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xAD => Instruction::LDA(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xBD => Instruction::LDA(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xB9 => Instruction::LDA(
-                opcode,
-                self._resolve_absolute_y_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xA1 => Instruction::LDA(
-                opcode,
-                self._resolve_index_indirect_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xB1 => Instruction::LDA(
-                opcode,
-                self._resolve_indirect_index_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xA2 => Instruction::LDX(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0xA6 => Instruction::LDX(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xB6 => Instruction::LDX(
-                opcode,
-                self._resolve_zero_page_y_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xAE => Instruction::LDX(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xBE => Instruction::LDX(
-                opcode,
-                self._resolve_absolute_y_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xA0 => Instruction::LDY(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0xA4 => Instruction::LDY(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xB4 => Instruction::LDY(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xAC => Instruction::LDY(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xBC => Instruction::LDY(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x4A => Instruction::LSR(opcode, 0, OPERAND_NON, OperandType::Accumulator),
-
-            0x46 => Instruction::LSR(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x56 => Instruction::LSR(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x4E => Instruction::LSR(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x5E => Instruction::LSR(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xEA => Instruction::NOP(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x09 => Instruction::ORA(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0x05 => Instruction::ORA(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x15 => Instruction::ORA(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x0D => Instruction::ORA(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x1D => Instruction::ORA(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x19 => Instruction::ORA(
-                opcode,
-                self._resolve_absolute_y_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x01 => Instruction::ORA(
-                opcode,
-                self._resolve_index_indirect_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x11 => Instruction::ORA(
-                opcode,
-                self._resolve_indirect_index_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x48 => Instruction::PHA(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x08 => Instruction::PHP(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x68 => Instruction::PLA(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x28 => Instruction::PLP(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x2A => Instruction::ROL(opcode, 0, OPERAND_NON, OperandType::Accumulator),
-
-            0x26 => Instruction::ROL(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x36 => Instruction::ROL(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x2E => Instruction::ROL(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x3E => Instruction::ROL(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x6A => Instruction::ROR(opcode, 0, OPERAND_NON, OperandType::Accumulator),
-
-            0x66 => Instruction::ROR(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x76 => Instruction::ROR(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x6E => Instruction::ROR(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x7E => Instruction::ROR(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x40 => Instruction::RTI(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x60 => Instruction::RTS(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0xE9 => Instruction::SBC(
-                opcode,
-                self._resolve_imm_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Imm,
-            ),
-
-            0xE5 => Instruction::SBC(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xF5 => Instruction::SBC(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xED => Instruction::SBC(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xFD => Instruction::SBC(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xF9 => Instruction::SBC(
-                opcode,
-                self._resolve_absolute_y_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xE1 => Instruction::SBC(
-                opcode,
-                self._resolve_index_indirect_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xF1 => Instruction::SBC(
-                opcode,
-                self._resolve_indirect_index_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x38 => Instruction::SEC(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0xF8 => Instruction::SED(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x78 => Instruction::SEI(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x85 => Instruction::STA(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x95 => Instruction::STA(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x8D => Instruction::STA(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x9D => Instruction::STA(
-                opcode,
-                self._resolve_absolute_x_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x99 => Instruction::STA(
-                opcode,
-                self._resolve_absolute_y_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x81 => Instruction::STA(
-                opcode,
-                self._resolve_index_indirect_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x91 => Instruction::STA(
-                opcode,
-                self._resolve_indirect_index_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x86 => Instruction::STX(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x96 => Instruction::STX(
-                opcode,
-                self._resolve_zero_page_y_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x8E => Instruction::STX(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x84 => Instruction::STY(
-                opcode,
-                self._resolve_zero_page_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x94 => Instruction::STY(
-                opcode,
-                self._resolve_zero_page_x_opnd(memory),
-                OPERAND_SINGLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0x8C => Instruction::STY(
-                opcode,
-                self._resolve_absolute_opnd(memory),
-                OPERAND_DOUBLE_ENCODING,
-                OperandType::Mem,
-            ),
-
-            0xAA => Instruction::TAX(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0xA8 => Instruction::TAY(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0xBA => Instruction::TSX(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x8A => Instruction::TXA(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x9A => Instruction::TXS(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0x98 => Instruction::TYA(opcode, 0, OPERAND_NON, OperandType::Implied),
-
-            0xFF => Instruction::MyHalt(255),
-            _ => Instruction::Unknown(opcode),
+    /*
+     * Resolves the operand bytes following the opcode. Immediate and
+     * relative modes give back the raw byte, JMP ($nnnn) the jump target
+     * and every other memory mode the effective address.
+     */
+    fn resolve_operand(&mut self, mode: AddressingMode, bus: &mut dyn Bus) -> u16 {
+        self.dummy_read_addr = None;
+        match mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => {
+                /* One byte instructions still read the byte after the opcode */
+                bus.read(self.pc);
+                0
+            }
+            AddressingMode::Immediate | AddressingMode::Relative => self._fetch_byte(bus) as u16,
+            AddressingMode::ZeroPage => self._fetch_byte(bus) as u16,
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                let base_addr = self._fetch_byte(bus);
+                let index = if mode == AddressingMode::ZeroPageX {
+                    self.x
+                } else {
+                    self.y
+                };
+                /* The base address is read while the index is being added */
+                bus.read(base_addr as u16);
+                base_addr.wrapping_add(index) as u16
+            }
+            AddressingMode::Absolute => self._fetch_word(bus),
+            AddressingMode::AbsoluteX => {
+                let base_addr = self._fetch_word(bus);
+                self._index_addr(base_addr, self.x)
+            }
+            AddressingMode::AbsoluteY => {
+                let base_addr = self._fetch_word(bus);
+                self._index_addr(base_addr, self.y)
+            }
+            AddressingMode::Indirect => {
+                /*
+                 * The pointer's high byte is fetched without carrying into
+                 * its page, so JMP ($10FF) reads $10FF and $1000.
+                 */
+                let ptr = self._fetch_word(bus);
+                let low_byte = bus.read(ptr);
+                let high_byte = bus.read((ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF));
+                (high_byte as u16) << 8 | low_byte as u16
+            }
+            AddressingMode::IndexedIndirect => {
+                let base_ptr = self._fetch_byte(bus);
+                bus.read(base_ptr as u16);
+                CPU::_read_zero_page_word(bus, base_ptr.wrapping_add(self.x))
+            }
+            AddressingMode::IndirectIndexed => {
+                let ptr = self._fetch_byte(bus);
+                let base_addr = CPU::_read_zero_page_word(bus, ptr);
+                self._index_addr(base_addr, self.y)
+            }
         }
     }
 
+    pub fn page_crossed(&self) -> bool {
+        self.page_crossed
+    }
+
+    /*
+     * Indexed modes take an extra cycle when the index carries into the
+     * high byte. During that cycle the CPU reads from the address with
+     * the high byte not fixed up yet.
+     */
+    fn _index_addr(&mut self, base_addr: u16, index: u8) -> u16 {
+        let addr = base_addr.wrapping_add(index as u16);
+        self.page_crossed = (base_addr & 0xFF00) != (addr & 0xFF00);
+        self.dummy_read_addr = Some((base_addr & 0xFF00) | (addr & 0x00FF));
+        addr
+    }
+
+    fn fetch_inst(&mut self, bus: &mut dyn Bus) -> Instruction {
+        let opcode = self._fetch_byte(bus);
+        let (decode, mode) = OPCODE_TABLE[opcode as usize];
+        let operand = self.resolve_operand(mode, bus);
+        let inst = decode(opcode, operand, mode.operand_size(), mode.operand_type());
+
+        /*
+         * Reads only take the extra cycle (and its dummy read) when they
+         * cross a page, stores and read-modify-write always spend it.
+         */
+        if let Some(addr) = self.dummy_read_addr {
+            if self.page_crossed || !inst.has_page_cross_penalty() {
+                bus.read(addr);
+            }
+        }
+
+        inst
+    }
+
     /* This method is for debug */
-    pub fn get_next_inst(&mut self, memory: &Memory) -> Instruction {
+    pub fn get_next_inst(&mut self, bus: &dyn Bus) -> Instruction {
         /*
          * self.fetch_inst function increased PC automatically,
          * so we need to recovery it.
          **/
-        let inst = self.fetch_inst(memory);
+        let pc = self.pc;
+        let page_crossed = self.page_crossed;
+        let inst = self.fetch_inst(&mut PeekBus(bus));
+        self.pc = pc;
+        self.page_crossed = page_crossed;
 
-        self.pc -= 1; /* Eat opcode byte */
+        inst
+    }
 
-        /* Eat operand byte(s) */
-        match inst.get_operand_size() {
-            OPERAND_SINGLE_ENCODING => self.pc -= 1,
-            OPERAND_DOUBLE_ENCODING => self.pc -= 2,
-            _ => (),
+    /* Runs one instruction (or interrupt sequence) and returns the cycles it took */
+    pub fn execute(&mut self, bus: &mut dyn Bus) -> u8 {
+        if self.poll_interrupts(bus) {
+            self.irq_poll_disable = self.status.interrupt_disable;
+            self.cycles += INTERRUPT_CYCLES as u64;
+            return INTERRUPT_CYCLES;
         }
 
-        inst
-    }
+        self.page_crossed = false;
+        self.extra_cycles = 0;
 
-    pub fn execute(&mut self, memory: &mut Memory) {
-        let inst = self.fetch_inst(memory);
-        self.interpret(&inst, memory);
+        let interrupt_disable = self.status.interrupt_disable;
+        let inst = self.fetch_inst(bus);
+        self.interpret(&inst, bus);
+
+        /*
+         * CLI, SEI and PLP change I after the interrupt poll of their last
+         * cycle, so an IRQ is only let in (or kept out) one instruction later.
+         */
+        self.irq_poll_disable = match inst {
+            Instruction::CLI(..) | Instruction::SEI(..) | Instruction::PLP(..) => interrupt_disable,
+            _ => self.status.interrupt_disable,
+        };
+
+        let mut cycles = inst.get_cycles() + self.extra_cycles;
+        if self.page_crossed && inst.has_page_cross_penalty() {
+            cycles += 1;
+        }
+        self.cycles += cycles as u64;
+        cycles
     }
 
     /* Stub method for test */
-    pub fn interpret(&mut self, inst: &Instruction, memory: &mut Memory) {
+    pub fn interpret(&mut self, inst: &Instruction, bus: &mut dyn Bus) {
         match inst {
             /* TODO: Refactor this piece of code to a small framework */
-            Instruction::LDA(_, _, _, _) => LDAInst::execute(self, inst, memory),
-            Instruction::ADC(_, _, _, _) => ADCInst::execute(self, inst, memory),
-            Instruction::MyHalt(_) => {
-                self.pc -= 1;
-            }
-            _ => panic!("Unimplemented instruction"),
+            Instruction::ADC(_, _, _, _) => ADCInst::execute(self, inst, bus),
+            Instruction::AND(_, _, _, _) => ANDInst::execute(self, inst, bus),
+            Instruction::ASL(_, _, _, _) => ASLInst::execute(self, inst, bus),
+            Instruction::BCC(_, _, _, _) => BCCInst::execute(self, inst, bus),
+            Instruction::BCS(_, _, _, _) => BCSInst::execute(self, inst, bus),
+            Instruction::BEQ(_, _, _, _) => BEQInst::execute(self, inst, bus),
+            Instruction::BIT(_, _, _, _) => BITInst::execute(self, inst, bus),
+            Instruction::BMI(_, _, _, _) => BMIInst::execute(self, inst, bus),
+            Instruction::BNE(_, _, _, _) => BNEInst::execute(self, inst, bus),
+            Instruction::BPL(_, _, _, _) => BPLInst::execute(self, inst, bus),
+            Instruction::BVC(_, _, _, _) => BVCInst::execute(self, inst, bus),
+            Instruction::BVS(_, _, _, _) => BVSInst::execute(self, inst, bus),
+            Instruction::CLC(_, _, _, _) => CLCInst::execute(self, inst, bus),
+            Instruction::CLD(_, _, _, _) => CLDInst::execute(self, inst, bus),
+            Instruction::CLI(_, _, _, _) => CLIInst::execute(self, inst, bus),
+            Instruction::CLV(_, _, _, _) => CLVInst::execute(self, inst, bus),
+            Instruction::CMP(_, _, _, _) => CMPInst::execute(self, inst, bus),
+            Instruction::CPX(_, _, _, _) => CPXInst::execute(self, inst, bus),
+            Instruction::CPY(_, _, _, _) => CPYInst::execute(self, inst, bus),
+            Instruction::DEC(_, _, _, _) => DECInst::execute(self, inst, bus),
+            Instruction::DEX(_, _, _, _) => DEXInst::execute(self, inst, bus),
+            Instruction::DEY(_, _, _, _) => DEYInst::execute(self, inst, bus),
+            Instruction::EOR(_, _, _, _) => EORInst::execute(self, inst, bus),
+            Instruction::INC(_, _, _, _) => INCInst::execute(self, inst, bus),
+            Instruction::INX(_, _, _, _) => INXInst::execute(self, inst, bus),
+            Instruction::INY(_, _, _, _) => INYInst::execute(self, inst, bus),
+            Instruction::JMP(_, _, _, _) => JMPInst::execute(self, inst, bus),
+            Instruction::LDA(_, _, _, _) => LDAInst::execute(self, inst, bus),
+            Instruction::LDX(_, _, _, _) => LDXInst::execute(self, inst, bus),
+            Instruction::LDY(_, _, _, _) => LDYInst::execute(self, inst, bus),
+            Instruction::LSR(_, _, _, _) => LSRInst::execute(self, inst, bus),
+            Instruction::NOP(_, _, _, _) => NOPInst::execute(self, inst, bus),
+            Instruction::ORA(_, _, _, _) => ORAInst::execute(self, inst, bus),
+            Instruction::ROL(_, _, _, _) => ROLInst::execute(self, inst, bus),
+            Instruction::ROR(_, _, _, _) => RORInst::execute(self, inst, bus),
+            Instruction::SBC(_, _, _, _) => SBCInst::execute(self, inst, bus),
+            Instruction::SEC(_, _, _, _) => SECInst::execute(self, inst, bus),
+            Instruction::SED(_, _, _, _) => SEDInst::execute(self, inst, bus),
+            Instruction::SEI(_, _, _, _) => SEIInst::execute(self, inst, bus),
+            Instruction::STA(_, _, _, _) => STAInst::execute(self, inst, bus),
+            Instruction::STX(_, _, _, _) => STXInst::execute(self, inst, bus),
+            Instruction::STY(_, _, _, _) => STYInst::execute(self, inst, bus),
+            Instruction::TAX(_, _, _, _) => TAXInst::execute(self, inst, bus),
+            Instruction::TAY(_, _, _, _) => TAYInst::execute(self, inst, bus),
+            Instruction::TXA(_, _, _, _) => TXAInst::execute(self, inst, bus),
+            Instruction::TYA(_, _, _, _) => TYAInst::execute(self, inst, bus),
+            Instruction::BRK(_, _, _, _) => BRKInst::execute(self, inst, bus),
+            Instruction::JSR(_, _, _, _) => JSRInst::execute(self, inst, bus),
+            Instruction::PHA(_, _, _, _) => PHAInst::execute(self, inst, bus),
+            Instruction::PHP(_, _, _, _) => PHPInst::execute(self, inst, bus),
+            Instruction::PLA(_, _, _, _) => PLAInst::execute(self, inst, bus),
+            Instruction::PLP(_, _, _, _) => PLPInst::execute(self, inst, bus),
+            Instruction::RTI(_, _, _, _) => RTIInst::execute(self, inst, bus),
+            Instruction::RTS(_, _, _, _) => RTSInst::execute(self, inst, bus),
+            Instruction::TSX(_, _, _, _) => TSXInst::execute(self, inst, bus),
+            Instruction::TXS(_, _, _, _) => TXSInst::execute(self, inst, bus),
+            Instruction::ALR(_, _, _, _) => ALRInst::execute(self, inst, bus),
+            Instruction::ANC(_, _, _, _) => ANCInst::execute(self, inst, bus),
+            Instruction::ARR(_, _, _, _) => ARRInst::execute(self, inst, bus),
+            Instruction::AXS(_, _, _, _) => AXSInst::execute(self, inst, bus),
+            Instruction::DCP(_, _, _, _) => DCPInst::execute(self, inst, bus),
+            Instruction::ISC(_, _, _, _) => ISCInst::execute(self, inst, bus),
+            Instruction::JAM(_, _, _, _) => JAMInst::execute(self, inst, bus),
+            Instruction::LAS(_, _, _, _) => LASInst::execute(self, inst, bus),
+            Instruction::LAX(_, _, _, _) => LAXInst::execute(self, inst, bus),
+            Instruction::LXA(_, _, _, _) => LXAInst::execute(self, inst, bus),
+            Instruction::RLA(_, _, _, _) => RLAInst::execute(self, inst, bus),
+            Instruction::RRA(_, _, _, _) => RRAInst::execute(self, inst, bus),
+            Instruction::SAX(_, _, _, _) => SAXInst::execute(self, inst, bus),
+            Instruction::SHA(_, _, _, _) => SHAInst::execute(self, inst, bus),
+            Instruction::SHX(_, _, _, _) => SHXInst::execute(self, inst, bus),
+            Instruction::SHY(_, _, _, _) => SHYInst::execute(self, inst, bus),
+            Instruction::SLO(_, _, _, _) => SLOInst::execute(self, inst, bus),
+            Instruction::SRE(_, _, _, _) => SREInst::execute(self, inst, bus),
+            Instruction::TAS(_, _, _, _) => TASInst::execute(self, inst, bus),
+            Instruction::XAA(_, _, _, _) => XAAInst::execute(self, inst, bus),
         }
     }
 
@@ -1139,16 +517,835 @@ impl CPU {
         self.status.negative = (target_reg & 0b1000_0000) != 0;
         self.status.zero = target_reg == 0;
     }
+
+    pub fn set_status_bit(&mut self, bit: StatusRegBit, value: bool) {
+        match bit {
+            StatusRegBit::Negative => self.status.negative = value,
+            StatusRegBit::Overflow => self.status.overflow = value,
+            StatusRegBit::BHigh => self.status.b_high = value,
+            StatusRegBit::BLow => self.status.b_low = value,
+            StatusRegBit::Decimal => self.status.decimal = value,
+            StatusRegBit::InterruptDisable => self.status.interrupt_disable = value,
+            StatusRegBit::Zero => self.status.zero = value,
+            StatusRegBit::Carry => self.status.carry = value,
+        }
+    }
+
+    /* SP wraps within page $01 on both overflow and underflow */
+    pub fn push(&mut self, bus: &mut dyn Bus, data: u8) {
+        bus.write(STACK_BASE | self.sp as u16, data);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    pub fn pop(&mut self, bus: &mut dyn Bus) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        bus.read(STACK_BASE | self.sp as u16)
+    }
+
+    pub fn push_word(&mut self, bus: &mut dyn Bus, data: u16) {
+        self.push(bus, (data >> 8) as u8);
+        self.push(bus, data as u8);
+    }
+
+    pub fn pop_word(&mut self, bus: &mut dyn Bus) -> u16 {
+        let low_byte = self.pop(bus);
+        let high_byte = self.pop(bus);
+        (high_byte as u16) << 8 | low_byte as u16
+    }
+
+    /*
+     * Bit 5 is always set in the pushed copy of P, bit 4 tells
+     * PHP/BRK (set) apart from a hardware IRQ/NMI (clear).
+     */
+    pub fn push_status(&mut self, bus: &mut dyn Bus, brk: bool) {
+        let status = u8::from(&self.status) | 0b0010_0000 | ((brk as u8) << 4);
+        self.push(bus, status);
+    }
+
+    pub fn pop_status(&mut self, bus: &mut dyn Bus) {
+        self.status = StatusRegister::from(self.pop(bus));
+    }
+
+    fn decimal_mode(&self) -> bool {
+        self.status.decimal && self.variant == CpuVariant::Nmos6502
+    }
+
+    pub fn add_with_carry(&mut self, operand: u8) {
+        let a = self.a;
+        let carry = self.status.carry as u8;
+        self.add_binary(operand);
+
+        if self.decimal_mode() {
+            /*
+             * NMOS decimal mode: Z comes from the binary sum, N and V from
+             * the sum after the low nibble fix-up, C from the final result.
+             */
+            let mut low = (a & 0x0F) + (operand & 0x0F) + carry;
+            if low >= 0x0A {
+                low = ((low + 0x06) & 0x0F) + 0x10;
+            }
+            let mut sum = (a & 0xF0) as u16 + (operand & 0xF0) as u16 + low as u16;
+            let signed_sum = (a & 0xF0) as i8 as i16 + (operand & 0xF0) as i8 as i16 + low as i16;
+            self.status.negative = (sum & 0b1000_0000) != 0;
+            self.status.overflow = !(-128..=127).contains(&signed_sum);
+            if sum >= 0xA0 {
+                sum += 0x60;
+            }
+            self.status.carry = sum >= 0x100;
+            self.a = sum as u8;
+        }
+    }
+
+    pub fn subtract_with_borrow(&mut self, operand: u8) {
+        let a = self.a;
+        let carry = self.status.carry as i16;
+        /* A - M - (1 - C) == A + !M + C, all flags come from the binary result */
+        self.add_binary(!operand);
+
+        if self.decimal_mode() {
+            let mut low = (a & 0x0F) as i16 - (operand & 0x0F) as i16 + carry - 1;
+            if low < 0 {
+                low = ((low - 0x06) & 0x0F) - 0x10;
+            }
+            let mut result = (a & 0xF0) as i16 - (operand & 0xF0) as i16 + low;
+            if result < 0 {
+                result -= 0x60;
+            }
+            self.a = result as u8;
+        }
+    }
+
+    fn add_binary(&mut self, operand: u8) {
+        let sum = self.a as u16 + operand as u16 + self.status.carry as u16;
+        let result = sum as u8;
+        self.status.carry = sum > 0xFF;
+        /* Overflow if both inputs have the same sign and the result differs */
+        self.status.overflow = ((self.a ^ result) & (operand ^ result) & 0b1000_0000) != 0;
+        self.a = result;
+        self.set_nz(self.a);
+    }
+
+    pub fn compare(&mut self, target_reg: u8, operand: u8) {
+        self.status.carry = target_reg >= operand;
+        self.set_nz(target_reg.wrapping_sub(operand));
+    }
+
+    /*
+     * Branch offsets are signed and relative to the next instruction.
+     * A taken branch costs one more cycle, two if it lands on another page.
+     */
+    pub fn branch(&mut self, condition: bool, offset: u8) {
+        if condition {
+            let target = self.pc.wrapping_add(offset as i8 as u16);
+            self.extra_cycles += 1;
+            if (self.pc & 0xFF00) != (target & 0xFF00) {
+                self.extra_cycles += 1;
+            }
+            self.pc = target;
+        }
+    }
 }
 
-impl MonitorState for CPU {
-    fn print_state(&self) {
-        println!(
-            "A: 0x{:X}\nX: 0x{:X}\nY: 0x{:X}\nflags: 0b{:b}",
+impl Snapshot for CPU {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u8(self.a);
+        out.u8(self.x);
+        out.u8(self.y);
+        out.u8(u8::from(&self.status));
+        out.u16(self.pc);
+        out.u8(self.sp);
+        out.bool(self.variant == CpuVariant::Nmos6502);
+        out.bool(self.nmi_line);
+        out.bool(self.nmi_pending);
+        out.u8(self.irq_lines);
+        out.bool(self.irq_poll_disable);
+        out.u64(self.cycles);
+        out.bool(self.page_crossed);
+        out.bool(self.dummy_read_addr.is_some());
+        out.u16(self.dummy_read_addr.unwrap_or(0));
+        out.u8(self.extra_cycles);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.a = input.u8()?;
+        self.x = input.u8()?;
+        self.y = input.u8()?;
+        self.status = StatusRegister::from(input.u8()?);
+        self.pc = input.u16()?;
+        self.sp = input.u8()?;
+        self.variant = if input.bool()? {
+            CpuVariant::Nmos6502
+        } else {
+            CpuVariant::Ricoh2A03
+        };
+        self.nmi_line = input.bool()?;
+        self.nmi_pending = input.bool()?;
+        self.irq_lines = input.u8()?;
+        self.irq_poll_disable = input.bool()?;
+        self.cycles = input.u64()?;
+        self.page_crossed = input.bool()?;
+        let has_dummy_read = input.bool()?;
+        let dummy_read_addr = input.u16()?;
+        self.dummy_read_addr = has_dummy_read.then_some(dummy_read_addr);
+        self.extra_cycles = input.u8()?;
+        Ok(())
+    }
+}
+
+impl CPU {
+    pub fn state_text(&self) -> String {
+        format!(
+            "A: 0x{:X}\nX: 0x{:X}\nY: 0x{:X}\nSP: 0x{:X}\nflags: 0b{:b}",
             self.a,
             self.x,
             self.y,
+            self.sp,
             u8::from(&self.status)
+        )
+    }
+}
+
+impl MonitorState for CPU {
+    fn print_state(&self) {
+        println!("{}", self.state_text());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::memory::Memory;
+
+    const RESET_ADDR: u16 = 0x8000;
+    const NMI_ADDR: u16 = 0x9000;
+    const IRQ_ADDR: u16 = 0xA000;
+
+    fn load(memory: &mut Memory, addr: u16, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            memory.write(addr + i as u16, *byte);
+        }
+    }
+
+    /* A CPU just past its reset sequence, with NOP sleds at every vector */
+    fn setup(program: &[u8]) -> (CPU, Memory) {
+        let mut memory = Memory::new();
+        load(&mut memory, 0xFFFA, &[0x00, 0x90, 0x00, 0x80, 0x00, 0xA0]);
+        load(&mut memory, NMI_ADDR, &[0xEA; 4]);
+        load(&mut memory, IRQ_ADDR, &[0xEA; 4]);
+        load(&mut memory, RESET_ADDR, program);
+
+        let mut cpu = CPU::new();
+        cpu.reset(&mut memory);
+        (cpu, memory)
+    }
+
+    /* Runs `count` instructions of `program` starting with the given A and C */
+    fn run(program: &[u8], count: usize, a: u8, carry: bool) -> (CPU, Memory) {
+        let (mut cpu, mut memory) = setup(program);
+        cpu.a = a;
+        cpu.status.carry = carry;
+        for _ in 0..count {
+            cpu.execute(&mut memory);
+        }
+        (cpu, memory)
+    }
+
+    fn flags(cpu: &CPU) -> (bool, bool, bool, bool) {
+        (
+            cpu.status.negative,
+            cpu.status.overflow,
+            cpu.status.zero,
+            cpu.status.carry,
+        )
+    }
+
+    #[test]
+    fn adc_sets_carry_and_overflow() {
+        /* (A, operand, carry in) -> (result, N, V, Z, C) */
+        for (a, operand, carry, result, expected) in [
+            (0x50, 0x50, false, 0xA0, (true, true, false, false)),
+            (0xFF, 0x01, false, 0x00, (false, false, true, true)),
+            (0x80, 0xFF, false, 0x7F, (false, true, false, true)),
+            (0x01, 0x01, true, 0x03, (false, false, false, false)),
+        ] {
+            let (cpu, _) = run(&[0x69, operand], 1, a, carry);
+            assert_eq!(
+                (cpu.a, flags(&cpu)),
+                (result, expected),
+                "{:02X}+{:02X}",
+                a,
+                operand
+            );
+        }
+    }
+
+    #[test]
+    fn sbc_sets_carry_and_overflow() {
+        for (a, operand, carry, result, expected) in [
+            (0x50, 0xB0, true, 0xA0, (true, true, false, false)),
+            (0x05, 0x03, true, 0x02, (false, false, false, true)),
+            (0x03, 0x05, true, 0xFE, (true, false, false, false)),
+            (0x05, 0x04, false, 0x00, (false, false, true, true)),
+        ] {
+            let (cpu, _) = run(&[0xE9, operand], 1, a, carry);
+            assert_eq!(
+                (cpu.a, flags(&cpu)),
+                (result, expected),
+                "{:02X}-{:02X}",
+                a,
+                operand
+            );
+        }
+    }
+
+    #[test]
+    fn compares_set_carry_and_zero() {
+        /* CMP, CPX and CPY immediate against a register holding $40 */
+        for opcode in [0xC9, 0xE0, 0xC0] {
+            for (operand, expected) in [
+                (0x40, (false, false, true, true)),
+                (0x41, (true, false, false, false)),
+                (0x3F, (false, false, false, true)),
+            ] {
+                let (mut cpu, mut memory) = setup(&[opcode, operand]);
+                (cpu.a, cpu.x, cpu.y) = (0x40, 0x40, 0x40);
+                cpu.execute(&mut memory);
+                assert_eq!(flags(&cpu), expected, "{:02X} #{:02X}", opcode, operand);
+                assert_eq!(cpu.a, 0x40);
+            }
+        }
+    }
+
+    #[test]
+    fn bit_copies_bits_7_and_6() {
+        let (mut cpu, mut memory) = setup(&[0x24, 0x10, 0x24, 0x11]);
+        load(&mut memory, 0x0010, &[0xC0, 0x3F]);
+        cpu.a = 0x01;
+        cpu.execute(&mut memory);
+        assert_eq!(flags(&cpu), (true, true, true, false));
+        cpu.execute(&mut memory);
+        assert_eq!(flags(&cpu), (false, false, false, false));
+        assert_eq!(cpu.a, 0x01);
+    }
+
+    #[test]
+    fn shifts_and_rotates_on_a_and_memory() {
+        /* (opcode on A, opcode on $10, value, carry in) -> (result, carry out) */
+        for (op_a, op_zp, value, carry, result, carry_out) in [
+            (0x0A, 0x06, 0x81, false, 0x02, true),
+            (0x0A, 0x06, 0x40, true, 0x80, false),
+            (0x4A, 0x46, 0x01, false, 0x00, true),
+            (0x4A, 0x46, 0x80, true, 0x40, false),
+            (0x2A, 0x26, 0x80, true, 0x01, true),
+            (0x2A, 0x26, 0x40, false, 0x80, false),
+            (0x6A, 0x66, 0x01, true, 0x80, true),
+            (0x6A, 0x66, 0x02, false, 0x01, false),
+        ] {
+            let (cpu, _) = run(&[op_a], 1, value, carry);
+            assert_eq!(
+                (cpu.a, cpu.status.carry),
+                (result, carry_out),
+                "{:02X}",
+                op_a
+            );
+            assert_eq!(cpu.status.zero, result == 0);
+            assert_eq!(cpu.status.negative, result & 0x80 != 0);
+
+            let (mut cpu, mut memory) = setup(&[op_zp, 0x10]);
+            memory.write(0x0010, value);
+            cpu.status.carry = carry;
+            cpu.execute(&mut memory);
+            assert_eq!(
+                (memory.read(0x0010), cpu.status.carry),
+                (result, carry_out),
+                "{:02X}",
+                op_zp
+            );
+        }
+    }
+
+    #[test]
+    fn branches_follow_their_flag() {
+        type SetFlag = fn(&mut StatusRegister, bool);
+        let set_flag: [(u8, SetFlag); 8] = [
+            (0x10, |p, v| p.negative = !v),
+            (0x30, |p, v| p.negative = v),
+            (0x50, |p, v| p.overflow = !v),
+            (0x70, |p, v| p.overflow = v),
+            (0x90, |p, v| p.carry = !v),
+            (0xB0, |p, v| p.carry = v),
+            (0xD0, |p, v| p.zero = !v),
+            (0xF0, |p, v| p.zero = v),
+        ];
+        for (opcode, set) in set_flag {
+            for taken in [true, false] {
+                let (mut cpu, mut memory) = setup(&[opcode, 0x10]);
+                set(&mut cpu.status, taken);
+                cpu.execute(&mut memory);
+                let target = if taken { 0x12 } else { 0x02 };
+                assert_eq!(cpu.pc, RESET_ADDR + target, "{:02X} {}", opcode, taken);
+            }
+        }
+    }
+
+    #[test]
+    fn inc_and_dec_wrap_in_memory() {
+        let (mut cpu, mut memory) = setup(&[0xE6, 0x10, 0xC6, 0x11]);
+        load(&mut memory, 0x0010, &[0xFF, 0x00]);
+        cpu.execute(&mut memory);
+        assert_eq!(memory.read(0x0010), 0x00);
+        assert!(cpu.status.zero && !cpu.status.negative);
+        cpu.execute(&mut memory);
+        assert_eq!(memory.read(0x0011), 0xFF);
+        assert!(!cpu.status.zero && cpu.status.negative);
+    }
+
+    #[test]
+    fn stack_pointer_wraps_within_page_one() {
+        let (mut cpu, mut memory) = setup(&[]);
+        cpu.sp = 0x00;
+        cpu.push(&mut memory, 0xAB);
+        cpu.push(&mut memory, 0xCD);
+        assert_eq!(cpu.sp, 0xFE);
+        assert_eq!((memory.read(0x0100), memory.read(0x01FF)), (0xAB, 0xCD));
+        assert_eq!((memory.read(0x0000), memory.read(0x0200)), (0x00, 0x00));
+
+        assert_eq!(cpu.pop(&mut memory), 0xCD);
+        assert_eq!(cpu.pop(&mut memory), 0xAB);
+        assert_eq!(cpu.sp, 0x00);
+    }
+
+    #[test]
+    fn php_and_brk_push_b_and_bit_5() {
+        /* PHP; BRK */
+        let (mut cpu, mut memory) = setup(&[0x08, 0x00]);
+        cpu.status.carry = true;
+        cpu.execute(&mut memory);
+        assert_eq!(memory.read(0x01FD), 0b0011_0101);
+
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, IRQ_ADDR);
+        assert_eq!(memory.read_word(0x01FB), RESET_ADDR + 3);
+        assert_eq!(memory.read(0x01FA), 0b0011_0101);
+    }
+
+    #[test]
+    fn plp_and_rti_ignore_bits_4_and_5() {
+        /* PLP with $FF on the stack */
+        let (mut cpu, mut memory) = setup(&[0x28]);
+        memory.write(0x01FE, 0xFF);
+        cpu.execute(&mut memory);
+        assert_eq!(u8::from(&cpu.status), 0b1100_1111);
+
+        /* RTI to $1234 with $30 as the pulled P */
+        let (mut cpu, mut memory) = setup(&[0x40]);
+        cpu.sp = 0xFC;
+        load(&mut memory, 0x01FD, &[0x30, 0x34, 0x12]);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(u8::from(&cpu.status), 0x00);
+    }
+
+    #[test]
+    fn jsr_pushes_return_minus_one() {
+        /* JSR $8010; NOP ... $8010: RTS */
+        let mut program = vec![0xEA; 0x11];
+        program[..3].copy_from_slice(&[0x20, 0x10, 0x80]);
+        program[0x10] = 0x60;
+        let (mut cpu, mut memory) = setup(&program);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, 0x8010);
+        assert_eq!(cpu.sp, 0xFB);
+        assert_eq!(memory.read_word(0x01FC), RESET_ADDR + 2);
+
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, RESET_ADDR + 3);
+        assert_eq!(cpu.sp, 0xFD);
+    }
+
+    #[test]
+    fn tsx_sets_flags_and_txs_does_not() {
+        /* TSX; TXS */
+        let (mut cpu, mut memory) = setup(&[0xBA, 0x9A]);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.x, 0xFD);
+        assert!(cpu.status.negative && !cpu.status.zero);
+
+        cpu.x = 0x00;
+        cpu.status.negative = false;
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.sp, 0x00);
+        assert!(!cpu.status.zero && !cpu.status.negative);
+        assert!(cpu.state_text().contains("SP: 0x0"));
+    }
+
+    #[test]
+    fn reset_loads_vector_and_sets_i() {
+        let (mut cpu, mut memory) = setup(&[]);
+        assert_eq!(cpu.pc, RESET_ADDR);
+        assert_eq!(cpu.sp, 0xFD);
+        assert!(cpu.status.interrupt_disable);
+
+        /* A warm reset keeps A/X/Y and moves SP down by three again */
+        cpu.a = 0x12;
+        cpu.status.interrupt_disable = false;
+        cpu.reset(&mut memory);
+        assert_eq!(cpu.a, 0x12);
+        assert_eq!(cpu.sp, 0xFA);
+        assert!(cpu.status.interrupt_disable);
+    }
+
+    #[test]
+    fn nmi_is_edge_triggered() {
+        let (mut cpu, mut memory) = setup(&[0xEA; 4]);
+        cpu.set_nmi(true);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, NMI_ADDR);
+        assert!(cpu.status.interrupt_disable);
+        /* Hardware interrupts push P with bit 5 set and bit 4 clear */
+        assert_eq!(memory.read(0x01FB) & 0b0011_0000, 0b0010_0000);
+        assert_eq!(memory.read_word(0x01FC), RESET_ADDR);
+
+        /* Holding the line doesn't retrigger, a new edge does */
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, NMI_ADDR + 1);
+        cpu.set_nmi(false);
+        cpu.set_nmi(true);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, NMI_ADDR);
+    }
+
+    #[test]
+    fn irq_is_level_triggered_and_masked_by_i() {
+        /* CLI; NOP; NOP */
+        let (mut cpu, mut memory) = setup(&[0x58, 0xEA, 0xEA]);
+        cpu.set_irq(IrqSource::Mapper, true);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, RESET_ADDR + 1);
+
+        /* The poll inside CLI still saw I set, so one more instruction runs */
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, RESET_ADDR + 2);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, IRQ_ADDR);
+
+        /* Still asserted, but masked by the I flag the sequence set */
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, IRQ_ADDR + 1);
+    }
+
+    #[test]
+    fn irq_lines_are_wired_or() {
+        let (mut cpu, _) = setup(&[]);
+        cpu.set_irq(IrqSource::Mapper, true);
+        cpu.set_irq(IrqSource::FrameCounter, true);
+        cpu.set_irq(IrqSource::Mapper, false);
+        assert!(cpu.irq_asserted());
+        cpu.set_irq(IrqSource::FrameCounter, false);
+        assert!(!cpu.irq_asserted());
+    }
+
+    #[test]
+    fn nmi_takes_priority_over_irq() {
+        let (mut cpu, mut memory) = setup(&[0xEA]);
+        cpu.status.interrupt_disable = false;
+        cpu.irq_poll_disable = false;
+        cpu.set_irq(IrqSource::External, true);
+        cpu.set_nmi(true);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, NMI_ADDR);
+        assert!(!cpu.nmi_pending());
+    }
+
+    #[test]
+    fn nmi_hijacks_brk() {
+        /* BRK; padding */
+        let (mut cpu, mut memory) = setup(&[0x00, 0x00]);
+
+        /* NMI shows up while BRK is already running, after the poll */
+        let inst = cpu.fetch_inst(&mut memory);
+        cpu.set_nmi(true);
+        cpu.interpret(&inst, &mut memory);
+
+        assert_eq!(cpu.pc, NMI_ADDR);
+        assert!(!cpu.nmi_pending());
+        /* The pushed copy of P still says BRK */
+        assert_eq!(memory.read(0x01FB) & 0b0011_0000, 0b0011_0000);
+        assert_eq!(memory.read_word(0x01FC), RESET_ADDR + 2);
+    }
+
+    #[test]
+    fn indexed_reads_pay_for_page_crossing() {
+        /* LDX #$01; LDA $80FF,X; LDA $8000,X; STA $80FF,X */
+        let (mut cpu, mut memory) = setup(&[
+            0xA2, 0x01, 0xBD, 0xFF, 0x80, 0xBD, 0x00, 0x80, 0x9D, 0xFF, 0x80,
+        ]);
+        assert_eq!(cpu.execute(&mut memory), 2);
+        assert_eq!(cpu.execute(&mut memory), 5);
+        assert_eq!(cpu.execute(&mut memory), 4);
+        /* Stores always take the fix-up cycle */
+        assert_eq!(cpu.execute(&mut memory), 5);
+        assert_eq!(cpu.cycles, 7 + 2 + 5 + 4 + 5);
+    }
+
+    #[test]
+    fn taken_branches_cost_extra() {
+        /* CLC; BCS +2 (not taken); BCC +0 (taken); BCC -128 (other page) */
+        let (mut cpu, mut memory) = setup(&[0x18, 0xB0, 0x02, 0x90, 0x00, 0x90, 0x80]);
+        assert_eq!(cpu.execute(&mut memory), 2);
+        assert_eq!(cpu.execute(&mut memory), 2);
+        assert_eq!(cpu.execute(&mut memory), 3);
+        assert_eq!(cpu.execute(&mut memory), 4);
+        assert_eq!(cpu.pc, RESET_ADDR + 7 - 128);
+    }
+
+    #[test]
+    fn interrupt_sequence_takes_seven_cycles() {
+        let (mut cpu, mut memory) = setup(&[0xEA]);
+        cpu.set_nmi(true);
+        assert_eq!(cpu.execute(&mut memory), 7);
+    }
+
+    fn run_adc_sbc(variant: CpuVariant, opcode: u8, a: u8, operand: u8, carry: bool) -> CPU {
+        let mut memory = Memory::new();
+        /* SED; ADC/SBC #operand */
+        load(&mut memory, 0x0000, &[0xF8, opcode, operand]);
+        let mut cpu = CPU::with_variant(variant);
+        cpu.a = a;
+        cpu.status.carry = carry;
+        cpu.execute(&mut memory);
+        cpu.execute(&mut memory);
+        cpu
+    }
+
+    #[test]
+    fn nmos_adc_in_decimal_mode() {
+        let cpu = run_adc_sbc(CpuVariant::Nmos6502, 0x69, 0x58, 0x46, true);
+        assert_eq!(cpu.a, 0x05);
+        assert!(cpu.status.carry);
+
+        let cpu = run_adc_sbc(CpuVariant::Nmos6502, 0x69, 0x12, 0x34, false);
+        assert_eq!(cpu.a, 0x46);
+        assert!(!cpu.status.carry);
+
+        /* Z follows the binary sum: $99 + $01 is $9A in binary */
+        let cpu = run_adc_sbc(CpuVariant::Nmos6502, 0x69, 0x99, 0x01, false);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.status.carry);
+        assert!(!cpu.status.zero);
+
+        /* N and V are taken before the high nibble is adjusted */
+        let cpu = run_adc_sbc(CpuVariant::Nmos6502, 0x69, 0x79, 0x00, true);
+        assert_eq!(cpu.a, 0x80);
+        assert!(cpu.status.negative);
+        assert!(cpu.status.overflow);
+    }
+
+    #[test]
+    fn nmos_sbc_in_decimal_mode() {
+        let cpu = run_adc_sbc(CpuVariant::Nmos6502, 0xE9, 0x46, 0x12, true);
+        assert_eq!(cpu.a, 0x34);
+        assert!(cpu.status.carry);
+
+        let cpu = run_adc_sbc(CpuVariant::Nmos6502, 0xE9, 0x40, 0x13, true);
+        assert_eq!(cpu.a, 0x27);
+
+        let cpu = run_adc_sbc(CpuVariant::Nmos6502, 0xE9, 0x32, 0x02, false);
+        assert_eq!(cpu.a, 0x29);
+
+        /* Borrow out of the top digit wraps around like a BCD counter */
+        let cpu = run_adc_sbc(CpuVariant::Nmos6502, 0xE9, 0x12, 0x21, true);
+        assert_eq!(cpu.a, 0x91);
+        assert!(!cpu.status.carry);
+    }
+
+    #[test]
+    fn ricoh_ignores_decimal_flag() {
+        let cpu = run_adc_sbc(CpuVariant::Ricoh2A03, 0x69, 0x58, 0x46, true);
+        assert!(cpu.status.decimal);
+        assert_eq!(cpu.a, 0x9F);
+        assert!(!cpu.status.carry);
+        assert!(cpu.status.overflow);
+
+        let cpu = run_adc_sbc(CpuVariant::Ricoh2A03, 0xE9, 0x46, 0x12, true);
+        assert_eq!(cpu.a, 0x34);
+    }
+
+    #[test]
+    fn illegal_opcodes_decode_and_execute() {
+        /* LAX $10; DCP $11; SLO $12; NOP $1234,X; SBC #$01 (unofficial) */
+        let (mut cpu, mut memory) = setup(&[
+            0xA7, 0x10, 0xC7, 0x11, 0x07, 0x12, 0x1C, 0x34, 0x12, 0xEB, 0x01,
+        ]);
+        load(&mut memory, 0x0010, &[0x42, 0x43, 0x81]);
+
+        cpu.execute(&mut memory);
+        assert_eq!((cpu.a, cpu.x), (0x42, 0x42));
+
+        /* $43 - 1 == A, so the compare sets Z and C */
+        cpu.execute(&mut memory);
+        assert_eq!(memory.read(0x0011), 0x42);
+        assert!(cpu.status.zero && cpu.status.carry);
+
+        cpu.execute(&mut memory);
+        assert_eq!(memory.read(0x0012), 0x02);
+        assert_eq!(cpu.a, 0x42);
+        assert!(cpu.status.carry);
+
+        let inst = cpu.get_next_inst(&memory);
+        assert!(inst.is_illegal());
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, RESET_ADDR + 9);
+
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.a, 0x41);
+    }
+
+    #[test]
+    fn jam_halts_the_cpu() {
+        let (mut cpu, mut memory) = setup(&[0x02]);
+        cpu.execute(&mut memory);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, RESET_ADDR);
+    }
+
+    /* Decodes `program` at the reset address and returns the operand it resolves to */
+    fn resolve(program: &[u8], x: u8, y: u8, memory_init: &[(u16, u8)]) -> (CPU, Memory, u16) {
+        let (mut cpu, mut memory) = setup(program);
+        for (addr, data) in memory_init {
+            memory.write(*addr, *data);
+        }
+        cpu.x = x;
+        cpu.y = y;
+        let operand = cpu.get_next_inst(&memory).get_address();
+        (cpu, memory, operand)
+    }
+
+    #[test]
+    fn immediate_and_zero_page_modes() {
+        /* LDA #$42 */
+        let (mut cpu, mut memory, operand) = resolve(&[0xA9, 0x42], 0, 0, &[]);
+        assert_eq!(operand, 0x42);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.pc, RESET_ADDR + 2);
+
+        /* LDA $10 */
+        let (_, _, operand) = resolve(&[0xA5, 0x10], 0, 0, &[]);
+        assert_eq!(operand, 0x0010);
+    }
+
+    #[test]
+    fn zero_page_indexed_wraps_within_zero_page() {
+        /* LDA $FF,X */
+        let (_, _, operand) = resolve(&[0xB5, 0xFF], 0x02, 0, &[]);
+        assert_eq!(operand, 0x0001);
+
+        /* LDX $80,Y */
+        let (mut cpu, mut memory, operand) = resolve(&[0xB6, 0x80], 0, 0x90, &[(0x0010, 0x33)]);
+        assert_eq!(operand, 0x0010);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.x, 0x33);
+    }
+
+    #[test]
+    fn absolute_modes() {
+        /* LDA $1234 */
+        let (_, _, operand) = resolve(&[0xAD, 0x34, 0x12], 0, 0, &[]);
+        assert_eq!(operand, 0x1234);
+
+        /* LDA $1234,X and LDA $1234,Y */
+        let (_, _, operand) = resolve(&[0xBD, 0x34, 0x12], 0x10, 0, &[]);
+        assert_eq!(operand, 0x1244);
+        let (_, _, operand) = resolve(&[0xB9, 0x34, 0x12], 0, 0x20, &[]);
+        assert_eq!(operand, 0x1254);
+    }
+
+    #[test]
+    fn absolute_indexed_wraps_around_64k() {
+        /* LDA $FFFF,X */
+        let (mut cpu, mut memory, operand) =
+            resolve(&[0xBD, 0xFF, 0xFF], 0x02, 0, &[(0x0001, 0x5A)]);
+        assert_eq!(operand, 0x0001);
+        assert_eq!(cpu.execute(&mut memory), 5);
+        assert_eq!(cpu.a, 0x5A);
+    }
+
+    #[test]
+    fn indirect_jmp_page_wrap_bug() {
+        /* JMP ($02FF) takes its high byte from $0200, not $0300 */
+        let (mut cpu, mut memory, operand) = resolve(
+            &[0x6C, 0xFF, 0x02],
+            0x05,
+            0,
+            &[(0x02FF, 0x34), (0x0200, 0x12), (0x0300, 0x56)],
         );
+        assert_eq!(operand, 0x1234);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, 0x1234);
+
+        /* X plays no part in JMP ($nnnn) */
+        let (_, _, operand) = resolve(
+            &[0x6C, 0x00, 0x03],
+            0x05,
+            0,
+            &[(0x0300, 0x78), (0x0301, 0x56)],
+        );
+        assert_eq!(operand, 0x5678);
+    }
+
+    #[test]
+    fn indexed_indirect_wraps_within_zero_page() {
+        /* LDA ($FE,X) with X = 3 reads the pointer from $01/$02 */
+        let (_, _, operand) = resolve(&[0xA1, 0xFE], 0x03, 0, &[(0x0001, 0x00), (0x0002, 0x04)]);
+        assert_eq!(operand, 0x0400);
+
+        /* A pointer at $FF takes its high byte from $00 */
+        let (_, _, operand) = resolve(&[0xA1, 0xFF], 0x00, 0, &[(0x00FF, 0x20), (0x0000, 0x03)]);
+        assert_eq!(operand, 0x0320);
+    }
+
+    #[test]
+    fn indirect_indexed_wraps() {
+        /* LDA ($FF),Y with the pointer split between $FF and $00 */
+        let (_, _, operand) = resolve(&[0xB1, 0xFF], 0, 0x10, &[(0x00FF, 0x00), (0x0000, 0x03)]);
+        assert_eq!(operand, 0x0310);
+
+        /* Adding Y carries across the top of memory */
+        let (mut cpu, mut memory, operand) = resolve(
+            &[0xB1, 0x10],
+            0,
+            0x02,
+            &[(0x0010, 0xFF), (0x0011, 0xFF), (0x0001, 0x77)],
+        );
+        assert_eq!(operand, 0x0001);
+        assert_eq!(cpu.execute(&mut memory), 6);
+        assert_eq!(cpu.a, 0x77);
+    }
+
+    #[test]
+    fn accumulator_and_relative_modes() {
+        /* ASL A; BCS -3 */
+        let (mut cpu, mut memory) = setup(&[0x0A, 0xB0, 0xFD]);
+        cpu.a = 0x81;
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.a, 0x02);
+        assert_eq!(cpu.pc, RESET_ADDR + 1);
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, RESET_ADDR);
+    }
+
+    #[test]
+    fn rti_returns_from_interrupt() {
+        let (mut cpu, mut memory) = setup(&[0xEA, 0xEA]);
+        load(&mut memory, NMI_ADDR, &[0x40]);
+        cpu.status.carry = true;
+        cpu.set_nmi(true);
+        cpu.execute(&mut memory);
+        cpu.status.carry = false;
+        cpu.execute(&mut memory);
+        assert_eq!(cpu.pc, RESET_ADDR);
+        assert_eq!(cpu.sp, 0xFD);
+        assert!(cpu.status.carry);
     }
 }
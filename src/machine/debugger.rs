@@ -0,0 +1,939 @@
+use std::fmt;
+
+use super::{
+    bus::Bus,
+    instruction::{AddressingMode, OPCODE_TABLE},
+};
+use crate::{disassemble_one, Disassembly};
+
+/* What conditions can look at: the CPU registers and memory, read with peeks */
+pub trait DebugTarget {
+    fn register(&self, register: Register) -> u16;
+    fn peek(&self, addr: u16) -> u8;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    P,
+}
+
+impl Register {
+    pub fn parse(name: &str) -> Option<Register> {
+        match name.to_ascii_uppercase().as_str() {
+            "A" => Some(Register::A),
+            "X" => Some(Register::X),
+            "Y" => Some(Register::Y),
+            "S" | "SP" => Some(Register::Sp),
+            "PC" => Some(Register::Pc),
+            "P" => Some(Register::P),
+            _ => None,
+        }
+    }
+}
+
+/* $C000 and 0xC000 are hex, %1010 is binary, anything else decimal */
+pub fn parse_number(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let parsed = if let Some(hex) = text.strip_prefix('$') {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(binary) = text.strip_prefix('%') {
+        u16::from_str_radix(binary, 2)
+    } else {
+        text.parse()
+    };
+    parsed.map_err(|_| format!("Not a number: {}", text))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl CompareOp {
+    fn apply(self, left: u16, right: u16) -> bool {
+        match self {
+            CompareOp::Equal => left == right,
+            CompareOp::NotEqual => left != right,
+            CompareOp::Less => left < right,
+            CompareOp::LessEqual => left <= right,
+            CompareOp::Greater => left > right,
+            CompareOp::GreaterEqual => left >= right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(u16),
+    Register(Register),
+    /* [addr], the byte at an address */
+    Memory(Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /* Comparisons and logic give 1 or 0 */
+    pub fn eval(&self, target: &dyn DebugTarget) -> u16 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(register) => target.register(*register),
+            Expr::Memory(addr) => target.peek(addr.eval(target)) as u16,
+            Expr::Compare(left, op, right) => {
+                op.apply(left.eval(target), right.eval(target)) as u16
+            }
+            Expr::And(left, right) => (left.eval(target) != 0 && right.eval(target) != 0) as u16,
+            Expr::Or(left, right) => (left.eval(target) != 0 || right.eval(target) != 0) as u16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u16),
+    Register(Register),
+    Compare(CompareOp),
+    And,
+    Or,
+    Open(char),
+    Close(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let two = text.get(start..start + 2).unwrap_or("");
+        let token = match c {
+            ' ' | '\t' => continue,
+            '[' | '(' => Token::Open(c),
+            ']' | ')' => Token::Close(c),
+            _ if two == "&&" || two == "||" || two == "==" || two == "!=" => {
+                chars.next();
+                match two {
+                    "&&" => Token::And,
+                    "||" => Token::Or,
+                    "==" => Token::Compare(CompareOp::Equal),
+                    _ => Token::Compare(CompareOp::NotEqual),
+                }
+            }
+            '<' | '>' => {
+                let or_equal = chars.next_if(|&(_, next)| next == '=').is_some();
+                Token::Compare(match (c, or_equal) {
+                    ('<', false) => CompareOp::Less,
+                    ('<', true) => CompareOp::LessEqual,
+                    ('>', false) => CompareOp::Greater,
+                    _ => CompareOp::GreaterEqual,
+                })
+            }
+            _ if c.is_ascii_alphanumeric() || c == '$' || c == '%' => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(index, next)) = chars.peek() {
+                    if !next.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    end = index + next.len_utf8();
+                    chars.next();
+                }
+                let word = &text[start..end];
+                match Register::parse(word) {
+                    Some(register) => Token::Register(register),
+                    None => Token::Number(parse_number(word)?),
+                }
+            }
+            _ => return Err(format!("Unexpected '{}' in expression", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/* Recursive descent over: or := and ('||' and)*, and := compare ('&&' compare)* */
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.compare()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.compare()?));
+        }
+        Ok(expr)
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let left = self.value()?;
+        match self.peek() {
+            Some(&Token::Compare(op)) => {
+                self.pos += 1;
+                Ok(Expr::Compare(Box::new(left), op, Box::new(self.value()?)))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn value(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Register(register)) => Ok(Expr::Register(register)),
+            Some(Token::Open('[')) => {
+                let addr = self.or()?;
+                self.expect(']')?;
+                Ok(Expr::Memory(Box::new(addr)))
+            }
+            Some(Token::Open(_)) => {
+                let expr = self.or()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(token) => Err(format!("Unexpected {:?} in expression", token)),
+            None => Err("Expression ends too early".to_string()),
+        }
+    }
+
+    fn expect(&mut self, close: char) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Close(c)) if c == close => Ok(()),
+            _ => Err(format!("Missing '{}' in expression", close)),
+        }
+    }
+}
+
+pub fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+    let expr = parser.or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(format!("Trailing input in expression: {}", text.trim()));
+    }
+    Ok(expr)
+}
+
+/* What `set` can change */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Place {
+    Register(Register),
+    Memory(Expr),
+}
+
+/* "a=$10" or "[$0200]=[$10]", the right side evaluated when it's applied */
+pub fn parse_assignment(text: &str) -> Result<(Place, Expr), String> {
+    let (place, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("Expected <register>=<value> or [<addr>]=<value>: {}", text))?;
+    let place = match parse_expr(place)? {
+        Expr::Register(register) => Place::Register(register),
+        Expr::Memory(addr) => Place::Memory(*addr),
+        _ => return Err(format!("Can't set {}", place.trim())),
+    };
+    Ok((place, parse_expr(value)?))
+}
+
+/* Sixteen bytes a line, in hex and then as ASCII with dots for the rest */
+pub fn hex_dump(target: &dyn DebugTarget, start: u16, count: usize) -> Vec<String> {
+    let bytes: Vec<u8> = (0..count)
+        .map(|offset| target.peek(start.wrapping_add(offset as u16)))
+        .collect();
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(line, chunk)| {
+            let mut hex = String::new();
+            for (column, byte) in chunk.iter().enumerate() {
+                let gap = if column == 8 { "  " } else { " " };
+                hex += &format!("{}{:02X}", gap, byte);
+            }
+            let ascii: String = chunk
+                .iter()
+                .map(|&byte| match byte {
+                    0x20..=0x7E => byte as char,
+                    _ => '.',
+                })
+                .collect();
+            format!(
+                "{:04X} {:<49}  {}",
+                start.wrapping_add(line as u16 * 16),
+                hex,
+                ascii
+            )
+        })
+        .collect()
+}
+
+/* The usual names of the PPU, APU and I/O registers */
+pub fn register_name(addr: u16) -> Option<&'static str> {
+    let name = match addr {
+        0x2000 => "PPUCTRL",
+        0x2001 => "PPUMASK",
+        0x2002 => "PPUSTATUS",
+        0x2003 => "OAMADDR",
+        0x2004 => "OAMDATA",
+        0x2005 => "PPUSCROLL",
+        0x2006 => "PPUADDR",
+        0x2007 => "PPUDATA",
+        0x4000 => "SQ1_VOL",
+        0x4001 => "SQ1_SWEEP",
+        0x4002 => "SQ1_LO",
+        0x4003 => "SQ1_HI",
+        0x4004 => "SQ2_VOL",
+        0x4005 => "SQ2_SWEEP",
+        0x4006 => "SQ2_LO",
+        0x4007 => "SQ2_HI",
+        0x4008 => "TRI_LINEAR",
+        0x400A => "TRI_LO",
+        0x400B => "TRI_HI",
+        0x400C => "NOISE_VOL",
+        0x400E => "NOISE_LO",
+        0x400F => "NOISE_HI",
+        0x4010 => "DMC_FREQ",
+        0x4011 => "DMC_RAW",
+        0x4012 => "DMC_START",
+        0x4013 => "DMC_LEN",
+        0x4014 => "OAMDMA",
+        0x4015 => "SND_CHN",
+        0x4016 => "JOY1",
+        0x4017 => "JOY2",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/*
+ * Code can't be decoded backwards for sure, so this looks for the
+ * earliest address up to `before` instructions back whose decoding runs
+ * through only documented opcodes and lands exactly on the PC.
+ */
+pub fn listing_start(bus: &dyn Bus, pc: u16, before: usize) -> u16 {
+    for back in (1..=before as u16 * 3).rev() {
+        let start = pc.wrapping_sub(back);
+        let mut offset = 0;
+        let mut count = 0;
+        while offset < back && count < before {
+            let line = disassemble_one(bus, start.wrapping_add(offset));
+            if line.illegal {
+                break;
+            }
+            offset += line.len();
+            count += 1;
+        }
+        if offset == back {
+            return start;
+        }
+    }
+    pc
+}
+
+/*
+ * "> C002  BD 00 03  LDA $0300,X     ; [$0305] = $12", the arrow and the
+ * memory it's about to touch only for the instruction at the PC. Fixed
+ * addresses of hardware registers get their names.
+ */
+pub fn listing_line(line: &Disassembly, at_pc: bool, effective: Option<(u16, u8)>) -> String {
+    let marker = if at_pc { ">" } else { " " };
+    let mut notes = Vec::new();
+    let (_, mode) = OPCODE_TABLE[line.bytes[0] as usize];
+    if let (AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY, 3) =
+        (mode, line.bytes.len())
+    {
+        if let Some(name) = register_name(u16::from_le_bytes([line.bytes[1], line.bytes[2]])) {
+            notes.push(name.to_string());
+        }
+    }
+    if let Some((addr, value)) = effective.filter(|_| at_pc) {
+        notes.push(format!("[${:04X}] = ${:02X}", addr, value));
+    }
+    if notes.is_empty() {
+        format!("{} {}", marker, line)
+    } else {
+        format!("{} {:<32}; {}", marker, line.to_string(), notes.join(", "))
+    }
+}
+
+/* A parsed condition, keeping the text it was written as for listing */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub text: String,
+    pub expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        Ok(Condition {
+            text: text.trim().to_string(),
+            expr: parse_expr(text)?,
+        })
+    }
+
+    pub fn holds(&self, target: &dyn DebugTarget) -> bool {
+        self.expr.eval(target) != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: u32,
+    pub addr: u16,
+    pub condition: Option<Condition>,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Breakpoint {} at ${:04X}", self.id, self.addr)?;
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    fn name(self) -> &'static str {
+        match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+        }
+    }
+}
+
+/* Stops on accesses to start..=end, or only those that read or write `value` */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: u32,
+    pub kind: WatchKind,
+    pub start: u16,
+    pub end: u16,
+    pub value: Option<u8>,
+}
+
+impl Watchpoint {
+    fn matches(&self, write: bool, addr: u16, data: u8) -> bool {
+        let kind = match write {
+            true => WatchKind::Write,
+            false => WatchKind::Read,
+        };
+        (self.kind == kind || self.kind == WatchKind::Access)
+            && (self.start..=self.end).contains(&addr)
+            && self.value.is_none_or(|value| value == data)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Watchpoint {} ({}) on ${:04X}",
+            self.id,
+            self.kind.name(),
+            self.start
+        )?;
+        if self.end != self.start {
+            write!(f, "-${:04X}", self.end)?;
+        }
+        if let Some(value) = self.value {
+            write!(f, " == ${:02X}", value)?;
+        }
+        Ok(())
+    }
+}
+
+/* The first access a watchpoint caught during an instruction */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: u32,
+    /* Where the instruction that made the access starts */
+    pub pc: u16,
+    pub write: bool,
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.write {
+            write!(
+                f,
+                "Watchpoint {}: write ${:04X}: ${:02X} -> ${:02X}",
+                self.id, self.addr, self.old, self.new
+            )
+        } else {
+            write!(
+                f,
+                "Watchpoint {}: read ${:04X} = ${:02X}",
+                self.id, self.addr, self.new
+            )
+        }
+    }
+}
+
+/*
+ * Sits between the CPU and the real bus while watchpoints are set, so
+ * every access an instruction makes, operands and dummy reads included,
+ * can be checked against them.
+ */
+pub struct WatchBus<'a> {
+    bus: &'a mut dyn Bus,
+    watchpoints: &'a [Watchpoint],
+    pc: u16,
+    hit: Option<WatchHit>,
+}
+
+impl<'a> WatchBus<'a> {
+    pub fn new(bus: &'a mut dyn Bus, watchpoints: &'a [Watchpoint], pc: u16) -> Self {
+        WatchBus {
+            bus,
+            watchpoints,
+            pc,
+            hit: None,
+        }
+    }
+
+    pub fn hit(&self) -> Option<WatchHit> {
+        self.hit
+    }
+
+    fn check(&mut self, write: bool, addr: u16, old: u8, new: u8) {
+        /* A read-modify-write stores twice, the hit shows what it ends up with */
+        if let Some(hit) = &mut self.hit {
+            if write && hit.write && hit.addr == addr {
+                hit.new = new;
+            }
+            return;
+        }
+        if let Some(watchpoint) = self
+            .watchpoints
+            .iter()
+            .find(|watchpoint| watchpoint.matches(write, addr, new))
+        {
+            self.hit = Some(WatchHit {
+                id: watchpoint.id,
+                pc: self.pc,
+                write,
+                addr,
+                old,
+                new,
+            });
+        }
+    }
+}
+
+impl Bus for WatchBus<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = self.bus.read(addr);
+        self.check(false, addr, data, data);
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let old = self.bus.peek(addr);
+        self.bus.write(addr, data);
+        self.check(true, addr, old, data);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+}
+
+/* Where a run started from the monitor goes back to the prompt, unless something stops it first */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunUntil {
+    /* Only a breakpoint or watchpoint */
+    Break,
+    /* After this many instructions */
+    Steps(u64),
+    /* The PC gets here, at this call depth if one is given */
+    Reach(u16, Option<i64>),
+    /* The call depth drops below this, back in the caller */
+    Return(i64),
+}
+
+/*
+ * How an instruction moved the call depth, judged by its opcode and what
+ * it did to the stack pointer. JSR pushes two bytes and RTS pulls them,
+ * BRK and interrupts push three and RTI pulls them. An interrupt is taken
+ * in place of the instruction at the PC, so three bytes pushed count as
+ * an entry whatever the opcode.
+ */
+pub fn call_depth_change(opcode: u8, sp_before: u8, sp_after: u8) -> i64 {
+    match (opcode, sp_before.wrapping_sub(sp_after)) {
+        (_, 3) | (0x20, 2) => 1,
+        (0x60, 0xFE) | (0x40, 0xFD) => -1,
+        _ => 0,
+    }
+}
+
+/*
+ * Breakpoints and watchpoints set from the monitor, numbered together in
+ * the order they were made, and how deep in subroutines the CPU is.
+ */
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+    next_id: u32,
+    call_depth: i64,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* "<addr> [if <condition>]", as typed after `break` */
+    pub fn parse_breakpoint(&mut self, text: &str) -> Result<&Breakpoint, String> {
+        let (addr, condition) = match text.split_once(" if ") {
+            Some((addr, condition)) => (addr, Some(Condition::parse(condition)?)),
+            None => (text, None),
+        };
+        Ok(self.add_breakpoint(parse_number(addr)?, condition))
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16, condition: Option<Condition>) -> &Breakpoint {
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id: self.next_id,
+            addr,
+            condition,
+        });
+        self.breakpoints.last().unwrap()
+    }
+
+    /* "<addr>[-<end>] [== <value>]", as typed after `watch`, `rwatch` or `awatch` */
+    pub fn parse_watchpoint(&mut self, kind: WatchKind, text: &str) -> Result<&Watchpoint, String> {
+        let (range, value) = match text.split_once("==") {
+            Some((range, value)) => {
+                let value = parse_number(value)?;
+                if value > 0xFF {
+                    return Err(format!("Value doesn't fit in a byte: {}", value));
+                }
+                (range, Some(value as u8))
+            }
+            None => (text, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+            None => (parse_number(range)?, parse_number(range)?),
+        };
+        if end < start {
+            return Err(format!("Range ends before it starts: {}", range.trim()));
+        }
+        Ok(self.add_watchpoint(kind, start, end, value))
+    }
+
+    pub fn add_watchpoint(
+        &mut self,
+        kind: WatchKind,
+        start: u16,
+        end: u16,
+        value: Option<u8>,
+    ) -> &Watchpoint {
+        self.next_id += 1;
+        self.watchpoints.push(Watchpoint {
+            id: self.next_id,
+            kind,
+            start,
+            end,
+            value,
+        });
+        self.watchpoints.last().unwrap()
+    }
+
+    /* Removes the breakpoint or watchpoint with this number and says which it was */
+    pub fn delete(&mut self, id: u32) -> Option<&'static str> {
+        if let Some(index) = self.breakpoints.iter().position(|b| b.id == id) {
+            self.breakpoints.remove(index);
+            Some("breakpoint")
+        } else if let Some(index) = self.watchpoints.iter().position(|w| w.id == id) {
+            self.watchpoints.remove(index);
+            Some("watchpoint")
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    /* Only differences matter, it starts at zero wherever the CPU is */
+    pub fn call_depth(&self) -> i64 {
+        self.call_depth
+    }
+
+    pub fn note_step(&mut self, opcode: u8, sp_before: u8, sp_after: u8) {
+        self.call_depth += call_depth_change(opcode, sp_before, sp_after);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn set_watch_hit(&mut self, hit: Option<WatchHit>) {
+        self.watch_hit = hit;
+    }
+
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /* The first breakpoint on the PC whose condition holds, if any */
+    pub fn check_breakpoints(&self, target: &dyn DebugTarget) -> Option<&Breakpoint> {
+        let pc = target.register(Register::Pc);
+        self.breakpoints.iter().find(|breakpoint| {
+            breakpoint.addr == pc
+                && breakpoint
+                    .condition
+                    .as_ref()
+                    .is_none_or(|condition| condition.holds(target))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Target {
+        a: u8,
+        pc: u16,
+        memory: [u8; 0x400],
+    }
+
+    impl DebugTarget for Target {
+        fn register(&self, register: Register) -> u16 {
+            match register {
+                Register::A => self.a as u16,
+                Register::Pc => self.pc,
+                _ => 0,
+            }
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            self.memory[addr as usize % 0x400]
+        }
+    }
+
+    #[test]
+    fn parses_numbers() {
+        assert_eq!(parse_number("$C000"), Ok(0xC000));
+        assert_eq!(parse_number("0x10"), Ok(0x10));
+        assert_eq!(parse_number("%101"), Ok(5));
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("$1G"), Err("Not a number: $1G".to_string()));
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        let mut target = Target {
+            a: 0x10,
+            pc: 0xC000,
+            memory: [0; 0x400],
+        };
+        target.memory[0x300] = 6;
+        let holds = |text: &str, target: &Target| Condition::parse(text).unwrap().holds(target);
+
+        assert!(holds("A==$10 && [$0300]>5", &target));
+        assert!(!holds("A==$10 && [$0300]>6", &target));
+        assert!(holds("a != 0 || [$0300] <= 5", &target));
+        assert!(holds("(A < 5 || A >= $10) && PC == $C000", &target));
+        target.memory[0x10] = 0x99;
+        assert!(holds("[A] == $99", &target));
+
+        assert!(Condition::parse("A ==").is_err());
+        assert!(Condition::parse("[$0300 > 5").is_err());
+        assert!(Condition::parse("A == 1 B").is_err());
+        assert!(Condition::parse("A = 1").is_err());
+    }
+
+    #[test]
+    fn parses_assignments() {
+        assert_eq!(
+            parse_assignment("a=$10"),
+            Ok((Place::Register(Register::A), Expr::Number(0x10)))
+        );
+        assert_eq!(
+            parse_assignment("[$0200] = $FF"),
+            Ok((Place::Memory(Expr::Number(0x200)), Expr::Number(0xFF)))
+        );
+        assert!(parse_assignment("a").is_err());
+        assert!(parse_assignment("$10=1").is_err());
+    }
+
+    #[test]
+    fn dumps_hex_and_ascii() {
+        let mut target = Target {
+            a: 0,
+            pc: 0,
+            memory: [0; 0x400],
+        };
+        target.memory[0x200..0x205].copy_from_slice(b"HELLO");
+        let lines = hex_dump(&target, 0x0200, 20);
+        assert_eq!(
+            lines,
+            vec![
+                "0200  48 45 4C 4C 4F 00 00 00  00 00 00 00 00 00 00 00  HELLO...........",
+                "0210  00 00 00 00                                       ....",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_with_annotations() {
+        let mut ram = crate::machine::memory::Memory::new();
+        /* LDA #$00; STA $2000; LDA $0300,X; BNE $C000 */
+        let code = [0xA9, 0x00, 0x8D, 0x00, 0x20, 0xBD, 0x00, 0x03, 0xD0, 0xF6];
+        ram.blocks[0xC000..0xC00A].copy_from_slice(&code);
+
+        assert_eq!(listing_start(&ram, 0xC005, 2), 0xC000);
+        assert_eq!(listing_start(&ram, 0xC005, 1), 0xC002);
+        let lines: Vec<String> = crate::disassemble_range(&ram, 0xC002, 3)
+            .iter()
+            .map(|line| listing_line(line, line.addr == 0xC005, Some((0x0305, 0x12))))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "  C002  8D 00 20  STA $2000       ; PPUCTRL",
+                "> C005  BD 00 03  LDA $0300,X     ; [$0305] = $12",
+                "  C008  D0 F6     BNE $C000",
+            ]
+        );
+    }
+
+    #[test]
+    fn tracks_calls_and_returns() {
+        assert_eq!(call_depth_change(0x20, 0xFD, 0xFB), 1);
+        assert_eq!(call_depth_change(0x60, 0xFB, 0xFD), -1);
+        assert_eq!(call_depth_change(0x00, 0xFD, 0xFA), 1);
+        assert_eq!(call_depth_change(0x40, 0xFA, 0xFD), -1);
+        /* An NMI taken at a JSR or RTS */
+        assert_eq!(call_depth_change(0x20, 0xFD, 0xFA), 1);
+        assert_eq!(call_depth_change(0x60, 0x01, 0xFE), 1);
+        /* PHA, PLA and TXS */
+        assert_eq!(call_depth_change(0x48, 0xFD, 0xFC), 0);
+        assert_eq!(call_depth_change(0x68, 0xFC, 0xFD), 0);
+        assert_eq!(call_depth_change(0x9A, 0xFD, 0x10), 0);
+    }
+
+    #[test]
+    fn watch_bus_reports_the_first_matching_access() {
+        let mut debugger = Debugger::new();
+        debugger
+            .parse_watchpoint(WatchKind::Write, "$0300-$030F == $FF")
+            .unwrap();
+        debugger.parse_watchpoint(WatchKind::Read, "$0010").unwrap();
+        assert_eq!(
+            debugger.watchpoints()[0].to_string(),
+            "Watchpoint 1 (write) on $0300-$030F == $FF"
+        );
+        assert!(debugger
+            .parse_watchpoint(WatchKind::Read, "$20-$10")
+            .is_err());
+        assert!(debugger
+            .parse_watchpoint(WatchKind::Read, "$10 == $100")
+            .is_err());
+
+        let mut ram = crate::machine::memory::Memory::new();
+        let mut bus = WatchBus::new(&mut ram, debugger.watchpoints(), 0xC000);
+        bus.write(0x0305, 0x01);
+        bus.read(0x0305);
+        assert_eq!(bus.hit(), None);
+        bus.write(0x0305, 0xFF);
+        bus.read(0x0010);
+        assert_eq!(
+            bus.hit().unwrap().to_string(),
+            "Watchpoint 1: write $0305: $01 -> $FF"
+        );
+        assert_eq!(bus.hit().unwrap().pc, 0xC000);
+
+        let mut bus = WatchBus::new(&mut ram, debugger.watchpoints(), 0xC000);
+        bus.read(0x0010);
+        assert_eq!(
+            bus.hit().unwrap().to_string(),
+            "Watchpoint 2: read $0010 = $00"
+        );
+    }
+
+    #[test]
+    fn breakpoints_stop_only_when_their_condition_holds() {
+        let mut debugger = Debugger::new();
+        let id = debugger.parse_breakpoint("$C000 if A==$10").unwrap().id;
+        assert_eq!(
+            debugger.breakpoints()[0].to_string(),
+            "Breakpoint 1 at $C000 if A==$10"
+        );
+        debugger.parse_breakpoint("$C003").unwrap();
+
+        let mut target = Target {
+            a: 0,
+            pc: 0xC000,
+            memory: [0; 0x400],
+        };
+        assert!(debugger.check_breakpoints(&target).is_none());
+        target.a = 0x10;
+        assert_eq!(debugger.check_breakpoints(&target).unwrap().id, id);
+        target.pc = 0xC003;
+        assert_eq!(debugger.check_breakpoints(&target).unwrap().id, 2);
+
+        assert_eq!(debugger.delete(id), Some("breakpoint"));
+        assert_eq!(debugger.delete(id), None);
+        let id = debugger
+            .parse_watchpoint(WatchKind::Write, "$0300")
+            .unwrap()
+            .id;
+        assert_eq!(debugger.delete(id), Some("watchpoint"));
+        assert_eq!(debugger.breakpoints().len(), 1);
+    }
+}
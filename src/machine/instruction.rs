@@ -1,6 +1,335 @@
-use super::cpu::{ CPU, OperandType };
-use super::memory::Memory;
-use core::panic;
+use super::bus::Bus;
+use super::cpu::{
+    OperandType, StatusRegBit, CPU, IRQ_VECTOR, OPERAND_DOUBLE_ENCODING, OPERAND_NON,
+    OPERAND_SINGLE_ENCODING,
+};
+
+/* Base cycle counts, without page crossing or taken branch penalties */
+const CYCLE_TABLE: [u8; 256] = [
+    /*  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F */
+    7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6, /* 0 */
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, /* 1 */
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6, /* 2 */
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, /* 3 */
+    6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6, /* 4 */
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, /* 5 */
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6, /* 6 */
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, /* 7 */
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, /* 8 */
+    2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5, /* 9 */
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, /* A */
+    2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4, /* B */
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, /* C */
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, /* D */
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, /* E */
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, /* F */
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    /* JMP ($nnnn) only */
+    Indirect,
+    /* ($nn,X) */
+    IndexedIndirect,
+    /* ($nn),Y */
+    IndirectIndexed,
+    Relative,
+}
+
+impl AddressingMode {
+    pub fn operand_size(self) -> u8 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => OPERAND_NON,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => OPERAND_DOUBLE_ENCODING,
+            _ => OPERAND_SINGLE_ENCODING,
+        }
+    }
+
+    pub fn operand_type(self) -> OperandType {
+        match self {
+            AddressingMode::Implied => OperandType::Implied,
+            AddressingMode::Accumulator => OperandType::Accumulator,
+            AddressingMode::Immediate => OperandType::Imm,
+            AddressingMode::Relative => OperandType::Relative,
+            AddressingMode::Indirect => OperandType::Indirect,
+            _ => OperandType::Mem,
+        }
+    }
+}
+
+pub type InstDecoder = fn(u8, u16, u8, OperandType) -> Instruction;
+
+/* Mnemonic and addressing mode of every opcode, official or not */
+pub const OPCODE_TABLE: [(InstDecoder, AddressingMode); 256] = [
+    (Instruction::BRK, AddressingMode::Implied), /* 00 */
+    (Instruction::ORA, AddressingMode::IndexedIndirect), /* 01 */
+    (Instruction::JAM, AddressingMode::Implied), /* 02 */
+    (Instruction::SLO, AddressingMode::IndexedIndirect), /* 03 */
+    (Instruction::NOP, AddressingMode::ZeroPage), /* 04 */
+    (Instruction::ORA, AddressingMode::ZeroPage), /* 05 */
+    (Instruction::ASL, AddressingMode::ZeroPage), /* 06 */
+    (Instruction::SLO, AddressingMode::ZeroPage), /* 07 */
+    (Instruction::PHP, AddressingMode::Implied), /* 08 */
+    (Instruction::ORA, AddressingMode::Immediate), /* 09 */
+    (Instruction::ASL, AddressingMode::Accumulator), /* 0A */
+    (Instruction::ANC, AddressingMode::Immediate), /* 0B */
+    (Instruction::NOP, AddressingMode::Absolute), /* 0C */
+    (Instruction::ORA, AddressingMode::Absolute), /* 0D */
+    (Instruction::ASL, AddressingMode::Absolute), /* 0E */
+    (Instruction::SLO, AddressingMode::Absolute), /* 0F */
+    (Instruction::BPL, AddressingMode::Relative), /* 10 */
+    (Instruction::ORA, AddressingMode::IndirectIndexed), /* 11 */
+    (Instruction::JAM, AddressingMode::Implied), /* 12 */
+    (Instruction::SLO, AddressingMode::IndirectIndexed), /* 13 */
+    (Instruction::NOP, AddressingMode::ZeroPageX), /* 14 */
+    (Instruction::ORA, AddressingMode::ZeroPageX), /* 15 */
+    (Instruction::ASL, AddressingMode::ZeroPageX), /* 16 */
+    (Instruction::SLO, AddressingMode::ZeroPageX), /* 17 */
+    (Instruction::CLC, AddressingMode::Implied), /* 18 */
+    (Instruction::ORA, AddressingMode::AbsoluteY), /* 19 */
+    (Instruction::NOP, AddressingMode::Implied), /* 1A */
+    (Instruction::SLO, AddressingMode::AbsoluteY), /* 1B */
+    (Instruction::NOP, AddressingMode::AbsoluteX), /* 1C */
+    (Instruction::ORA, AddressingMode::AbsoluteX), /* 1D */
+    (Instruction::ASL, AddressingMode::AbsoluteX), /* 1E */
+    (Instruction::SLO, AddressingMode::AbsoluteX), /* 1F */
+    (Instruction::JSR, AddressingMode::Absolute), /* 20 */
+    (Instruction::AND, AddressingMode::IndexedIndirect), /* 21 */
+    (Instruction::JAM, AddressingMode::Implied), /* 22 */
+    (Instruction::RLA, AddressingMode::IndexedIndirect), /* 23 */
+    (Instruction::BIT, AddressingMode::ZeroPage), /* 24 */
+    (Instruction::AND, AddressingMode::ZeroPage), /* 25 */
+    (Instruction::ROL, AddressingMode::ZeroPage), /* 26 */
+    (Instruction::RLA, AddressingMode::ZeroPage), /* 27 */
+    (Instruction::PLP, AddressingMode::Implied), /* 28 */
+    (Instruction::AND, AddressingMode::Immediate), /* 29 */
+    (Instruction::ROL, AddressingMode::Accumulator), /* 2A */
+    (Instruction::ANC, AddressingMode::Immediate), /* 2B */
+    (Instruction::BIT, AddressingMode::Absolute), /* 2C */
+    (Instruction::AND, AddressingMode::Absolute), /* 2D */
+    (Instruction::ROL, AddressingMode::Absolute), /* 2E */
+    (Instruction::RLA, AddressingMode::Absolute), /* 2F */
+    (Instruction::BMI, AddressingMode::Relative), /* 30 */
+    (Instruction::AND, AddressingMode::IndirectIndexed), /* 31 */
+    (Instruction::JAM, AddressingMode::Implied), /* 32 */
+    (Instruction::RLA, AddressingMode::IndirectIndexed), /* 33 */
+    (Instruction::NOP, AddressingMode::ZeroPageX), /* 34 */
+    (Instruction::AND, AddressingMode::ZeroPageX), /* 35 */
+    (Instruction::ROL, AddressingMode::ZeroPageX), /* 36 */
+    (Instruction::RLA, AddressingMode::ZeroPageX), /* 37 */
+    (Instruction::SEC, AddressingMode::Implied), /* 38 */
+    (Instruction::AND, AddressingMode::AbsoluteY), /* 39 */
+    (Instruction::NOP, AddressingMode::Implied), /* 3A */
+    (Instruction::RLA, AddressingMode::AbsoluteY), /* 3B */
+    (Instruction::NOP, AddressingMode::AbsoluteX), /* 3C */
+    (Instruction::AND, AddressingMode::AbsoluteX), /* 3D */
+    (Instruction::ROL, AddressingMode::AbsoluteX), /* 3E */
+    (Instruction::RLA, AddressingMode::AbsoluteX), /* 3F */
+    (Instruction::RTI, AddressingMode::Implied), /* 40 */
+    (Instruction::EOR, AddressingMode::IndexedIndirect), /* 41 */
+    (Instruction::JAM, AddressingMode::Implied), /* 42 */
+    (Instruction::SRE, AddressingMode::IndexedIndirect), /* 43 */
+    (Instruction::NOP, AddressingMode::ZeroPage), /* 44 */
+    (Instruction::EOR, AddressingMode::ZeroPage), /* 45 */
+    (Instruction::LSR, AddressingMode::ZeroPage), /* 46 */
+    (Instruction::SRE, AddressingMode::ZeroPage), /* 47 */
+    (Instruction::PHA, AddressingMode::Implied), /* 48 */
+    (Instruction::EOR, AddressingMode::Immediate), /* 49 */
+    (Instruction::LSR, AddressingMode::Accumulator), /* 4A */
+    (Instruction::ALR, AddressingMode::Immediate), /* 4B */
+    (Instruction::JMP, AddressingMode::Absolute), /* 4C */
+    (Instruction::EOR, AddressingMode::Absolute), /* 4D */
+    (Instruction::LSR, AddressingMode::Absolute), /* 4E */
+    (Instruction::SRE, AddressingMode::Absolute), /* 4F */
+    (Instruction::BVC, AddressingMode::Relative), /* 50 */
+    (Instruction::EOR, AddressingMode::IndirectIndexed), /* 51 */
+    (Instruction::JAM, AddressingMode::Implied), /* 52 */
+    (Instruction::SRE, AddressingMode::IndirectIndexed), /* 53 */
+    (Instruction::NOP, AddressingMode::ZeroPageX), /* 54 */
+    (Instruction::EOR, AddressingMode::ZeroPageX), /* 55 */
+    (Instruction::LSR, AddressingMode::ZeroPageX), /* 56 */
+    (Instruction::SRE, AddressingMode::ZeroPageX), /* 57 */
+    (Instruction::CLI, AddressingMode::Implied), /* 58 */
+    (Instruction::EOR, AddressingMode::AbsoluteY), /* 59 */
+    (Instruction::NOP, AddressingMode::Implied), /* 5A */
+    (Instruction::SRE, AddressingMode::AbsoluteY), /* 5B */
+    (Instruction::NOP, AddressingMode::AbsoluteX), /* 5C */
+    (Instruction::EOR, AddressingMode::AbsoluteX), /* 5D */
+    (Instruction::LSR, AddressingMode::AbsoluteX), /* 5E */
+    (Instruction::SRE, AddressingMode::AbsoluteX), /* 5F */
+    (Instruction::RTS, AddressingMode::Implied), /* 60 */
+    (Instruction::ADC, AddressingMode::IndexedIndirect), /* 61 */
+    (Instruction::JAM, AddressingMode::Implied), /* 62 */
+    (Instruction::RRA, AddressingMode::IndexedIndirect), /* 63 */
+    (Instruction::NOP, AddressingMode::ZeroPage), /* 64 */
+    (Instruction::ADC, AddressingMode::ZeroPage), /* 65 */
+    (Instruction::ROR, AddressingMode::ZeroPage), /* 66 */
+    (Instruction::RRA, AddressingMode::ZeroPage), /* 67 */
+    (Instruction::PLA, AddressingMode::Implied), /* 68 */
+    (Instruction::ADC, AddressingMode::Immediate), /* 69 */
+    (Instruction::ROR, AddressingMode::Accumulator), /* 6A */
+    (Instruction::ARR, AddressingMode::Immediate), /* 6B */
+    (Instruction::JMP, AddressingMode::Indirect), /* 6C */
+    (Instruction::ADC, AddressingMode::Absolute), /* 6D */
+    (Instruction::ROR, AddressingMode::Absolute), /* 6E */
+    (Instruction::RRA, AddressingMode::Absolute), /* 6F */
+    (Instruction::BVS, AddressingMode::Relative), /* 70 */
+    (Instruction::ADC, AddressingMode::IndirectIndexed), /* 71 */
+    (Instruction::JAM, AddressingMode::Implied), /* 72 */
+    (Instruction::RRA, AddressingMode::IndirectIndexed), /* 73 */
+    (Instruction::NOP, AddressingMode::ZeroPageX), /* 74 */
+    (Instruction::ADC, AddressingMode::ZeroPageX), /* 75 */
+    (Instruction::ROR, AddressingMode::ZeroPageX), /* 76 */
+    (Instruction::RRA, AddressingMode::ZeroPageX), /* 77 */
+    (Instruction::SEI, AddressingMode::Implied), /* 78 */
+    (Instruction::ADC, AddressingMode::AbsoluteY), /* 79 */
+    (Instruction::NOP, AddressingMode::Implied), /* 7A */
+    (Instruction::RRA, AddressingMode::AbsoluteY), /* 7B */
+    (Instruction::NOP, AddressingMode::AbsoluteX), /* 7C */
+    (Instruction::ADC, AddressingMode::AbsoluteX), /* 7D */
+    (Instruction::ROR, AddressingMode::AbsoluteX), /* 7E */
+    (Instruction::RRA, AddressingMode::AbsoluteX), /* 7F */
+    (Instruction::NOP, AddressingMode::Immediate), /* 80 */
+    (Instruction::STA, AddressingMode::IndexedIndirect), /* 81 */
+    (Instruction::NOP, AddressingMode::Immediate), /* 82 */
+    (Instruction::SAX, AddressingMode::IndexedIndirect), /* 83 */
+    (Instruction::STY, AddressingMode::ZeroPage), /* 84 */
+    (Instruction::STA, AddressingMode::ZeroPage), /* 85 */
+    (Instruction::STX, AddressingMode::ZeroPage), /* 86 */
+    (Instruction::SAX, AddressingMode::ZeroPage), /* 87 */
+    (Instruction::DEY, AddressingMode::Implied), /* 88 */
+    (Instruction::NOP, AddressingMode::Immediate), /* 89 */
+    (Instruction::TXA, AddressingMode::Implied), /* 8A */
+    (Instruction::XAA, AddressingMode::Immediate), /* 8B */
+    (Instruction::STY, AddressingMode::Absolute), /* 8C */
+    (Instruction::STA, AddressingMode::Absolute), /* 8D */
+    (Instruction::STX, AddressingMode::Absolute), /* 8E */
+    (Instruction::SAX, AddressingMode::Absolute), /* 8F */
+    (Instruction::BCC, AddressingMode::Relative), /* 90 */
+    (Instruction::STA, AddressingMode::IndirectIndexed), /* 91 */
+    (Instruction::JAM, AddressingMode::Implied), /* 92 */
+    (Instruction::SHA, AddressingMode::IndirectIndexed), /* 93 */
+    (Instruction::STY, AddressingMode::ZeroPageX), /* 94 */
+    (Instruction::STA, AddressingMode::ZeroPageX), /* 95 */
+    (Instruction::STX, AddressingMode::ZeroPageY), /* 96 */
+    (Instruction::SAX, AddressingMode::ZeroPageY), /* 97 */
+    (Instruction::TYA, AddressingMode::Implied), /* 98 */
+    (Instruction::STA, AddressingMode::AbsoluteY), /* 99 */
+    (Instruction::TXS, AddressingMode::Implied), /* 9A */
+    (Instruction::TAS, AddressingMode::AbsoluteY), /* 9B */
+    (Instruction::SHY, AddressingMode::AbsoluteX), /* 9C */
+    (Instruction::STA, AddressingMode::AbsoluteX), /* 9D */
+    (Instruction::SHX, AddressingMode::AbsoluteY), /* 9E */
+    (Instruction::SHA, AddressingMode::AbsoluteY), /* 9F */
+    (Instruction::LDY, AddressingMode::Immediate), /* A0 */
+    (Instruction::LDA, AddressingMode::IndexedIndirect), /* A1 */
+    (Instruction::LDX, AddressingMode::Immediate), /* A2 */
+    (Instruction::LAX, AddressingMode::IndexedIndirect), /* A3 */
+    (Instruction::LDY, AddressingMode::ZeroPage), /* A4 */
+    (Instruction::LDA, AddressingMode::ZeroPage), /* A5 */
+    (Instruction::LDX, AddressingMode::ZeroPage), /* A6 */
+    (Instruction::LAX, AddressingMode::ZeroPage), /* A7 */
+    (Instruction::TAY, AddressingMode::Implied), /* A8 */
+    (Instruction::LDA, AddressingMode::Immediate), /* A9 */
+    (Instruction::TAX, AddressingMode::Implied), /* AA */
+    (Instruction::LXA, AddressingMode::Immediate), /* AB */
+    (Instruction::LDY, AddressingMode::Absolute), /* AC */
+    (Instruction::LDA, AddressingMode::Absolute), /* AD */
+    (Instruction::LDX, AddressingMode::Absolute), /* AE */
+    (Instruction::LAX, AddressingMode::Absolute), /* AF */
+    (Instruction::BCS, AddressingMode::Relative), /* B0 */
+    (Instruction::LDA, AddressingMode::IndirectIndexed), /* B1 */
+    (Instruction::JAM, AddressingMode::Implied), /* B2 */
+    (Instruction::LAX, AddressingMode::IndirectIndexed), /* B3 */
+    (Instruction::LDY, AddressingMode::ZeroPageX), /* B4 */
+    (Instruction::LDA, AddressingMode::ZeroPageX), /* B5 */
+    (Instruction::LDX, AddressingMode::ZeroPageY), /* B6 */
+    (Instruction::LAX, AddressingMode::ZeroPageY), /* B7 */
+    (Instruction::CLV, AddressingMode::Implied), /* B8 */
+    (Instruction::LDA, AddressingMode::AbsoluteY), /* B9 */
+    (Instruction::TSX, AddressingMode::Implied), /* BA */
+    (Instruction::LAS, AddressingMode::AbsoluteY), /* BB */
+    (Instruction::LDY, AddressingMode::AbsoluteX), /* BC */
+    (Instruction::LDA, AddressingMode::AbsoluteX), /* BD */
+    (Instruction::LDX, AddressingMode::AbsoluteY), /* BE */
+    (Instruction::LAX, AddressingMode::AbsoluteY), /* BF */
+    (Instruction::CPY, AddressingMode::Immediate), /* C0 */
+    (Instruction::CMP, AddressingMode::IndexedIndirect), /* C1 */
+    (Instruction::NOP, AddressingMode::Immediate), /* C2 */
+    (Instruction::DCP, AddressingMode::IndexedIndirect), /* C3 */
+    (Instruction::CPY, AddressingMode::ZeroPage), /* C4 */
+    (Instruction::CMP, AddressingMode::ZeroPage), /* C5 */
+    (Instruction::DEC, AddressingMode::ZeroPage), /* C6 */
+    (Instruction::DCP, AddressingMode::ZeroPage), /* C7 */
+    (Instruction::INY, AddressingMode::Implied), /* C8 */
+    (Instruction::CMP, AddressingMode::Immediate), /* C9 */
+    (Instruction::DEX, AddressingMode::Implied), /* CA */
+    (Instruction::AXS, AddressingMode::Immediate), /* CB */
+    (Instruction::CPY, AddressingMode::Absolute), /* CC */
+    (Instruction::CMP, AddressingMode::Absolute), /* CD */
+    (Instruction::DEC, AddressingMode::Absolute), /* CE */
+    (Instruction::DCP, AddressingMode::Absolute), /* CF */
+    (Instruction::BNE, AddressingMode::Relative), /* D0 */
+    (Instruction::CMP, AddressingMode::IndirectIndexed), /* D1 */
+    (Instruction::JAM, AddressingMode::Implied), /* D2 */
+    (Instruction::DCP, AddressingMode::IndirectIndexed), /* D3 */
+    (Instruction::NOP, AddressingMode::ZeroPageX), /* D4 */
+    (Instruction::CMP, AddressingMode::ZeroPageX), /* D5 */
+    (Instruction::DEC, AddressingMode::ZeroPageX), /* D6 */
+    (Instruction::DCP, AddressingMode::ZeroPageX), /* D7 */
+    (Instruction::CLD, AddressingMode::Implied), /* D8 */
+    (Instruction::CMP, AddressingMode::AbsoluteY), /* D9 */
+    (Instruction::NOP, AddressingMode::Implied), /* DA */
+    (Instruction::DCP, AddressingMode::AbsoluteY), /* DB */
+    (Instruction::NOP, AddressingMode::AbsoluteX), /* DC */
+    (Instruction::CMP, AddressingMode::AbsoluteX), /* DD */
+    (Instruction::DEC, AddressingMode::AbsoluteX), /* DE */
+    (Instruction::DCP, AddressingMode::AbsoluteX), /* DF */
+    (Instruction::CPX, AddressingMode::Immediate), /* E0 */
+    (Instruction::SBC, AddressingMode::IndexedIndirect), /* E1 */
+    (Instruction::NOP, AddressingMode::Immediate), /* E2 */
+    (Instruction::ISC, AddressingMode::IndexedIndirect), /* E3 */
+    (Instruction::CPX, AddressingMode::ZeroPage), /* E4 */
+    (Instruction::SBC, AddressingMode::ZeroPage), /* E5 */
+    (Instruction::INC, AddressingMode::ZeroPage), /* E6 */
+    (Instruction::ISC, AddressingMode::ZeroPage), /* E7 */
+    (Instruction::INX, AddressingMode::Implied), /* E8 */
+    (Instruction::SBC, AddressingMode::Immediate), /* E9 */
+    (Instruction::NOP, AddressingMode::Implied), /* EA */
+    (Instruction::SBC, AddressingMode::Immediate), /* EB */
+    (Instruction::CPX, AddressingMode::Absolute), /* EC */
+    (Instruction::SBC, AddressingMode::Absolute), /* ED */
+    (Instruction::INC, AddressingMode::Absolute), /* EE */
+    (Instruction::ISC, AddressingMode::Absolute), /* EF */
+    (Instruction::BEQ, AddressingMode::Relative), /* F0 */
+    (Instruction::SBC, AddressingMode::IndirectIndexed), /* F1 */
+    (Instruction::JAM, AddressingMode::Implied), /* F2 */
+    (Instruction::ISC, AddressingMode::IndirectIndexed), /* F3 */
+    (Instruction::NOP, AddressingMode::ZeroPageX), /* F4 */
+    (Instruction::SBC, AddressingMode::ZeroPageX), /* F5 */
+    (Instruction::INC, AddressingMode::ZeroPageX), /* F6 */
+    (Instruction::ISC, AddressingMode::ZeroPageX), /* F7 */
+    (Instruction::SED, AddressingMode::Implied), /* F8 */
+    (Instruction::SBC, AddressingMode::AbsoluteY), /* F9 */
+    (Instruction::NOP, AddressingMode::Implied), /* FA */
+    (Instruction::ISC, AddressingMode::AbsoluteY), /* FB */
+    (Instruction::NOP, AddressingMode::AbsoluteX), /* FC */
+    (Instruction::SBC, AddressingMode::AbsoluteX), /* FD */
+    (Instruction::INC, AddressingMode::AbsoluteX), /* FE */
+    (Instruction::ISC, AddressingMode::AbsoluteX), /* FF */
+];
 
 #[derive(Debug)]
 pub enum Instruction {
@@ -62,14 +391,33 @@ pub enum Instruction {
     TXS(u8, u16, u8, OperandType),
     TYA(u8, u16, u8, OperandType),
 
-    MyHalt(u8),
-    Unknown(u8),
+    /* Unofficial NMOS opcodes */
+    ALR(u8, u16, u8, OperandType),
+    ANC(u8, u16, u8, OperandType),
+    ARR(u8, u16, u8, OperandType),
+    AXS(u8, u16, u8, OperandType),
+    DCP(u8, u16, u8, OperandType),
+    ISC(u8, u16, u8, OperandType),
+    JAM(u8, u16, u8, OperandType),
+    LAS(u8, u16, u8, OperandType),
+    LAX(u8, u16, u8, OperandType),
+    LXA(u8, u16, u8, OperandType),
+    RLA(u8, u16, u8, OperandType),
+    RRA(u8, u16, u8, OperandType),
+    SAX(u8, u16, u8, OperandType),
+    SHA(u8, u16, u8, OperandType),
+    SHX(u8, u16, u8, OperandType),
+    SHY(u8, u16, u8, OperandType),
+    SLO(u8, u16, u8, OperandType),
+    SRE(u8, u16, u8, OperandType),
+    TAS(u8, u16, u8, OperandType),
+    XAA(u8, u16, u8, OperandType),
 }
 
 impl Instruction {
     pub fn get_contents(&self) -> (u8, u16, u8, &OperandType) {
         match self {
-              Instruction::ADC(opcode, operand, operand_size, operand_type)
+            Instruction::ADC(opcode, operand, operand_size, operand_type)
             | Instruction::AND(opcode, operand, operand_size, operand_type)
             | Instruction::ASL(opcode, operand, operand_size, operand_type)
             | Instruction::BCC(opcode, operand, operand_size, operand_type)
@@ -124,19 +472,150 @@ impl Instruction {
             | Instruction::TSX(opcode, operand, operand_size, operand_type)
             | Instruction::TXA(opcode, operand, operand_size, operand_type)
             | Instruction::TXS(opcode, operand, operand_size, operand_type)
-            | Instruction::TYA(opcode, operand, operand_size, operand_type) => (*opcode, *operand, *operand_size, operand_type),
-            Instruction::MyHalt(opcode) => (255, 0, 0, &OperandType::Imm),
-            _ => {
-                panic!("Unknown instruction {:?}", self);
+            | Instruction::TYA(opcode, operand, operand_size, operand_type)
+            | Instruction::ALR(opcode, operand, operand_size, operand_type)
+            | Instruction::ANC(opcode, operand, operand_size, operand_type)
+            | Instruction::ARR(opcode, operand, operand_size, operand_type)
+            | Instruction::AXS(opcode, operand, operand_size, operand_type)
+            | Instruction::DCP(opcode, operand, operand_size, operand_type)
+            | Instruction::ISC(opcode, operand, operand_size, operand_type)
+            | Instruction::JAM(opcode, operand, operand_size, operand_type)
+            | Instruction::LAS(opcode, operand, operand_size, operand_type)
+            | Instruction::LAX(opcode, operand, operand_size, operand_type)
+            | Instruction::LXA(opcode, operand, operand_size, operand_type)
+            | Instruction::RLA(opcode, operand, operand_size, operand_type)
+            | Instruction::RRA(opcode, operand, operand_size, operand_type)
+            | Instruction::SAX(opcode, operand, operand_size, operand_type)
+            | Instruction::SHA(opcode, operand, operand_size, operand_type)
+            | Instruction::SHX(opcode, operand, operand_size, operand_type)
+            | Instruction::SHY(opcode, operand, operand_size, operand_type)
+            | Instruction::SLO(opcode, operand, operand_size, operand_type)
+            | Instruction::SRE(opcode, operand, operand_size, operand_type)
+            | Instruction::TAS(opcode, operand, operand_size, operand_type)
+            | Instruction::XAA(opcode, operand, operand_size, operand_type) => {
+                (*opcode, *operand, *operand_size, operand_type)
             }
         }
     }
 
-    pub fn get_operand(&self, memory: &mut Memory) -> u16 {
+    pub fn get_operand(&self, bus: &mut dyn Bus) -> u16 {
         match self.get_contents().3 {
             OperandType::Imm => self.get_contents().1,
-            OperandType::Mem => memory.read(self.get_contents().1) as u16,
-            _ => 0
+            OperandType::Mem => bus.read(self.get_contents().1) as u16,
+            OperandType::Relative => self.get_contents().1,
+            _ => 0,
+        }
+    }
+
+    /* Effective address for memory operands, or jump target for JMP/JSR */
+    pub fn get_address(&self) -> u16 {
+        self.get_contents().1
+    }
+
+    /*
+     * Read-modify-write instructions (ASL, LSR, ROL, ROR) work on
+     * either the accumulator or a memory location.
+     */
+    pub fn read_rmw_operand(&self, cpu: &CPU, bus: &mut dyn Bus) -> u8 {
+        match self.get_contents().3 {
+            OperandType::Accumulator => cpu.a,
+            _ => self.get_operand(bus) as u8,
+        }
+    }
+
+    /*
+     * On memory the 6502 writes the unmodified value back while it works
+     * out the result, then writes the result on the next cycle. Mappers
+     * such as MMC1 see both writes.
+     */
+    pub fn write_rmw_operand(&self, cpu: &mut CPU, bus: &mut dyn Bus, operand: u8, result: u8) {
+        match self.get_contents().3 {
+            OperandType::Accumulator => cpu.a = result,
+            _ => {
+                bus.write(self.get_address(), operand);
+                bus.write(self.get_address(), result);
+            }
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::ADC(..) => "ADC",
+            Instruction::AND(..) => "AND",
+            Instruction::ASL(..) => "ASL",
+            Instruction::BCC(..) => "BCC",
+            Instruction::BCS(..) => "BCS",
+            Instruction::BEQ(..) => "BEQ",
+            Instruction::BIT(..) => "BIT",
+            Instruction::BMI(..) => "BMI",
+            Instruction::BNE(..) => "BNE",
+            Instruction::BPL(..) => "BPL",
+            Instruction::BRK(..) => "BRK",
+            Instruction::BVC(..) => "BVC",
+            Instruction::BVS(..) => "BVS",
+            Instruction::CLC(..) => "CLC",
+            Instruction::CLD(..) => "CLD",
+            Instruction::CLI(..) => "CLI",
+            Instruction::CLV(..) => "CLV",
+            Instruction::CMP(..) => "CMP",
+            Instruction::CPX(..) => "CPX",
+            Instruction::CPY(..) => "CPY",
+            Instruction::DEC(..) => "DEC",
+            Instruction::DEX(..) => "DEX",
+            Instruction::DEY(..) => "DEY",
+            Instruction::EOR(..) => "EOR",
+            Instruction::INC(..) => "INC",
+            Instruction::INX(..) => "INX",
+            Instruction::INY(..) => "INY",
+            Instruction::JMP(..) => "JMP",
+            Instruction::JSR(..) => "JSR",
+            Instruction::LDA(..) => "LDA",
+            Instruction::LDX(..) => "LDX",
+            Instruction::LDY(..) => "LDY",
+            Instruction::LSR(..) => "LSR",
+            Instruction::NOP(..) => "NOP",
+            Instruction::ORA(..) => "ORA",
+            Instruction::PHA(..) => "PHA",
+            Instruction::PHP(..) => "PHP",
+            Instruction::PLA(..) => "PLA",
+            Instruction::PLP(..) => "PLP",
+            Instruction::ROL(..) => "ROL",
+            Instruction::ROR(..) => "ROR",
+            Instruction::RTI(..) => "RTI",
+            Instruction::RTS(..) => "RTS",
+            Instruction::SBC(..) => "SBC",
+            Instruction::SEC(..) => "SEC",
+            Instruction::SED(..) => "SED",
+            Instruction::SEI(..) => "SEI",
+            Instruction::STA(..) => "STA",
+            Instruction::STX(..) => "STX",
+            Instruction::STY(..) => "STY",
+            Instruction::TAX(..) => "TAX",
+            Instruction::TAY(..) => "TAY",
+            Instruction::TSX(..) => "TSX",
+            Instruction::TXA(..) => "TXA",
+            Instruction::TXS(..) => "TXS",
+            Instruction::TYA(..) => "TYA",
+            Instruction::ALR(..) => "ALR",
+            Instruction::ANC(..) => "ANC",
+            Instruction::ARR(..) => "ARR",
+            Instruction::AXS(..) => "AXS",
+            Instruction::DCP(..) => "DCP",
+            Instruction::ISC(..) => "ISC",
+            Instruction::JAM(..) => "JAM",
+            Instruction::LAS(..) => "LAS",
+            Instruction::LAX(..) => "LAX",
+            Instruction::LXA(..) => "LXA",
+            Instruction::RLA(..) => "RLA",
+            Instruction::RRA(..) => "RRA",
+            Instruction::SAX(..) => "SAX",
+            Instruction::SHA(..) => "SHA",
+            Instruction::SHX(..) => "SHX",
+            Instruction::SHY(..) => "SHY",
+            Instruction::SLO(..) => "SLO",
+            Instruction::SRE(..) => "SRE",
+            Instruction::TAS(..) => "TAS",
+            Instruction::XAA(..) => "XAA",
         }
     }
 
@@ -147,27 +626,737 @@ impl Instruction {
     pub fn get_operand_size(&self) -> u8 {
         self.get_contents().2
     }
+
+    /* Undocumented opcodes, including the unofficial NOP and SBC encodings */
+    pub fn is_illegal(&self) -> bool {
+        match self {
+            Instruction::NOP(opcode, _, _, _) => *opcode != 0xEA,
+            Instruction::SBC(opcode, _, _, _) => *opcode == 0xEB,
+            Instruction::ALR(..)
+            | Instruction::ANC(..)
+            | Instruction::ARR(..)
+            | Instruction::AXS(..)
+            | Instruction::DCP(..)
+            | Instruction::ISC(..)
+            | Instruction::JAM(..)
+            | Instruction::LAS(..)
+            | Instruction::LAX(..)
+            | Instruction::LXA(..)
+            | Instruction::RLA(..)
+            | Instruction::RRA(..)
+            | Instruction::SAX(..)
+            | Instruction::SHA(..)
+            | Instruction::SHX(..)
+            | Instruction::SHY(..)
+            | Instruction::SLO(..)
+            | Instruction::SRE(..)
+            | Instruction::TAS(..)
+            | Instruction::XAA(..) => true,
+            _ => false,
+        }
+    }
+
+    pub fn get_cycles(&self) -> u8 {
+        CYCLE_TABLE[self.get_opcode() as usize]
+    }
+
+    /*
+     * Only instructions that merely read their operand can skip the
+     * fix-up cycle, stores and read-modify-write always spend it.
+     */
+    pub fn has_page_cross_penalty(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ADC(..)
+                | Instruction::AND(..)
+                | Instruction::CMP(..)
+                | Instruction::EOR(..)
+                | Instruction::LDA(..)
+                | Instruction::LDX(..)
+                | Instruction::LDY(..)
+                | Instruction::ORA(..)
+                | Instruction::SBC(..)
+                | Instruction::NOP(..)
+                | Instruction::LAX(..)
+                | Instruction::LAS(..)
+        )
+    }
 }
 
 pub trait InstEXE {
-    fn execute(cpu: &mut CPU, inst: &Instruction, memory: &mut Memory);
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus);
 }
 
 pub struct ADCInst;
 impl InstEXE for ADCInst {
-    fn execute(cpu: &mut CPU, inst: &Instruction, memory: &mut Memory) {
-        let operand = inst.get_operand(memory);
-        (_, cpu.status.overflow) = (cpu.a as i8).overflowing_add(operand as i8);
-        (cpu.a, cpu.status.carry) = cpu.a.overflowing_add(operand as u8);
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.add_with_carry(operand as u8);
+    }
+}
+
+pub struct SBCInst;
+impl InstEXE for SBCInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.subtract_with_borrow(operand as u8);
+    }
+}
+
+pub struct ANDInst;
+impl InstEXE for ANDInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.a &= operand as u8;
         cpu.set_nz(cpu.a);
     }
 }
 
+pub struct ORAInst;
+impl InstEXE for ORAInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.a |= operand as u8;
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct EORInst;
+impl InstEXE for EORInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.a ^= operand as u8;
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct BITInst;
+impl InstEXE for BITInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus) as u8;
+        cpu.status.zero = (cpu.a & operand) == 0;
+        cpu.status.negative = (operand & 0b1000_0000) != 0;
+        cpu.status.overflow = (operand & 0b0100_0000) != 0;
+    }
+}
+
+/* Shifts and rotates, shared with the unofficial read-modify-write combos */
+fn asl(cpu: &mut CPU, operand: u8) -> u8 {
+    cpu.status.carry = (operand & 0b1000_0000) != 0;
+    operand << 1
+}
+
+fn lsr(cpu: &mut CPU, operand: u8) -> u8 {
+    cpu.status.carry = (operand & 0b0000_0001) != 0;
+    operand >> 1
+}
+
+fn rol(cpu: &mut CPU, operand: u8) -> u8 {
+    let result = (operand << 1) | cpu.status.carry as u8;
+    cpu.status.carry = (operand & 0b1000_0000) != 0;
+    result
+}
+
+fn ror(cpu: &mut CPU, operand: u8) -> u8 {
+    let result = (operand >> 1) | ((cpu.status.carry as u8) << 7);
+    cpu.status.carry = (operand & 0b0000_0001) != 0;
+    result
+}
+
+fn read_modify_write(
+    cpu: &mut CPU,
+    inst: &Instruction,
+    bus: &mut dyn Bus,
+    op: fn(&mut CPU, u8) -> u8,
+) -> u8 {
+    let operand = inst.read_rmw_operand(cpu, bus);
+    let result = op(cpu, operand);
+    inst.write_rmw_operand(cpu, bus, operand, result);
+    cpu.set_nz(result);
+    result
+}
+
+pub struct ASLInst;
+impl InstEXE for ASLInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        read_modify_write(cpu, inst, bus, asl);
+    }
+}
+
+pub struct LSRInst;
+impl InstEXE for LSRInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        read_modify_write(cpu, inst, bus, lsr);
+    }
+}
+
+pub struct ROLInst;
+impl InstEXE for ROLInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        read_modify_write(cpu, inst, bus, rol);
+    }
+}
+
+pub struct RORInst;
+impl InstEXE for RORInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        read_modify_write(cpu, inst, bus, ror);
+    }
+}
+
+pub struct CMPInst;
+impl InstEXE for CMPInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.compare(cpu.a, operand as u8);
+    }
+}
+
+pub struct CPXInst;
+impl InstEXE for CPXInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.compare(cpu.x, operand as u8);
+    }
+}
+
+pub struct CPYInst;
+impl InstEXE for CPYInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.compare(cpu.y, operand as u8);
+    }
+}
+
+pub struct INCInst;
+impl InstEXE for INCInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus) as u8;
+        let result = operand.wrapping_add(1);
+        inst.write_rmw_operand(cpu, bus, operand, result);
+        cpu.set_nz(result);
+    }
+}
+
+pub struct DECInst;
+impl InstEXE for DECInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus) as u8;
+        let result = operand.wrapping_sub(1);
+        inst.write_rmw_operand(cpu, bus, operand, result);
+        cpu.set_nz(result);
+    }
+}
+
+pub struct INXInst;
+impl InstEXE for INXInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.x = cpu.x.wrapping_add(1);
+        cpu.set_nz(cpu.x);
+    }
+}
+
+pub struct INYInst;
+impl InstEXE for INYInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.y = cpu.y.wrapping_add(1);
+        cpu.set_nz(cpu.y);
+    }
+}
+
+pub struct DEXInst;
+impl InstEXE for DEXInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.x = cpu.x.wrapping_sub(1);
+        cpu.set_nz(cpu.x);
+    }
+}
+
+pub struct DEYInst;
+impl InstEXE for DEYInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.y = cpu.y.wrapping_sub(1);
+        cpu.set_nz(cpu.y);
+    }
+}
+
 pub struct LDAInst;
 impl InstEXE for LDAInst {
-    fn execute(cpu: &mut CPU, inst: &Instruction, memory: &mut Memory) {
-        let operand = inst.get_operand(memory);
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
         cpu.a = operand as u8;
         cpu.set_nz(cpu.a);
     }
 }
+
+pub struct LDXInst;
+impl InstEXE for LDXInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.x = operand as u8;
+        cpu.set_nz(cpu.x);
+    }
+}
+
+pub struct LDYInst;
+impl InstEXE for LDYInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus);
+        cpu.y = operand as u8;
+        cpu.set_nz(cpu.y);
+    }
+}
+
+pub struct STAInst;
+impl InstEXE for STAInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        bus.write(inst.get_address(), cpu.a);
+    }
+}
+
+pub struct STXInst;
+impl InstEXE for STXInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        bus.write(inst.get_address(), cpu.x);
+    }
+}
+
+pub struct STYInst;
+impl InstEXE for STYInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        bus.write(inst.get_address(), cpu.y);
+    }
+}
+
+pub struct TAXInst;
+impl InstEXE for TAXInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.x = cpu.a;
+        cpu.set_nz(cpu.x);
+    }
+}
+
+pub struct TAYInst;
+impl InstEXE for TAYInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.y = cpu.a;
+        cpu.set_nz(cpu.y);
+    }
+}
+
+pub struct TXAInst;
+impl InstEXE for TXAInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.a = cpu.x;
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct TYAInst;
+impl InstEXE for TYAInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.a = cpu.y;
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct CLCInst;
+impl InstEXE for CLCInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.set_status_bit(StatusRegBit::Carry, false);
+    }
+}
+
+pub struct CLDInst;
+impl InstEXE for CLDInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.set_status_bit(StatusRegBit::Decimal, false);
+    }
+}
+
+pub struct CLIInst;
+impl InstEXE for CLIInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.set_status_bit(StatusRegBit::InterruptDisable, false);
+    }
+}
+
+pub struct CLVInst;
+impl InstEXE for CLVInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.set_status_bit(StatusRegBit::Overflow, false);
+    }
+}
+
+pub struct SECInst;
+impl InstEXE for SECInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.set_status_bit(StatusRegBit::Carry, true);
+    }
+}
+
+pub struct SEDInst;
+impl InstEXE for SEDInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.set_status_bit(StatusRegBit::Decimal, true);
+    }
+}
+
+pub struct SEIInst;
+impl InstEXE for SEIInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.set_status_bit(StatusRegBit::InterruptDisable, true);
+    }
+}
+
+pub struct BCCInst;
+impl InstEXE for BCCInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.branch(!cpu.status.carry, inst.get_operand(bus) as u8);
+    }
+}
+
+pub struct BCSInst;
+impl InstEXE for BCSInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.branch(cpu.status.carry, inst.get_operand(bus) as u8);
+    }
+}
+
+pub struct BEQInst;
+impl InstEXE for BEQInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.branch(cpu.status.zero, inst.get_operand(bus) as u8);
+    }
+}
+
+pub struct BNEInst;
+impl InstEXE for BNEInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.branch(!cpu.status.zero, inst.get_operand(bus) as u8);
+    }
+}
+
+pub struct BMIInst;
+impl InstEXE for BMIInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.branch(cpu.status.negative, inst.get_operand(bus) as u8);
+    }
+}
+
+pub struct BPLInst;
+impl InstEXE for BPLInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.branch(!cpu.status.negative, inst.get_operand(bus) as u8);
+    }
+}
+
+pub struct BVCInst;
+impl InstEXE for BVCInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.branch(!cpu.status.overflow, inst.get_operand(bus) as u8);
+    }
+}
+
+pub struct BVSInst;
+impl InstEXE for BVSInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.branch(cpu.status.overflow, inst.get_operand(bus) as u8);
+    }
+}
+
+pub struct JSRInst;
+impl InstEXE for JSRInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        /* JSR pushes the address of its own last byte, RTS adds one back */
+        cpu.push_word(bus, cpu.pc.wrapping_sub(1));
+        cpu.pc = inst.get_address();
+    }
+}
+
+pub struct RTSInst;
+impl InstEXE for RTSInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.pc = cpu.pop_word(bus).wrapping_add(1);
+    }
+}
+
+pub struct BRKInst;
+impl InstEXE for BRKInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, bus: &mut dyn Bus) {
+        /* BRK is followed by a padding byte which the return address skips */
+        cpu.interrupt(bus, cpu.pc.wrapping_add(1), true, IRQ_VECTOR);
+    }
+}
+
+pub struct RTIInst;
+impl InstEXE for RTIInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.pop_status(bus);
+        cpu.pc = cpu.pop_word(bus);
+    }
+}
+
+pub struct PHAInst;
+impl InstEXE for PHAInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.push(bus, cpu.a);
+    }
+}
+
+pub struct PHPInst;
+impl InstEXE for PHPInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.push_status(bus, true);
+    }
+}
+
+pub struct PLAInst;
+impl InstEXE for PLAInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.a = cpu.pop(bus);
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct PLPInst;
+impl InstEXE for PLPInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.pop_status(bus);
+    }
+}
+
+pub struct TSXInst;
+impl InstEXE for TSXInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        cpu.x = cpu.sp;
+        cpu.set_nz(cpu.x);
+    }
+}
+
+pub struct TXSInst;
+impl InstEXE for TXSInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        /* The only transfer that leaves the flags alone */
+        cpu.sp = cpu.x;
+    }
+}
+
+pub struct JMPInst;
+impl InstEXE for JMPInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, _bus: &mut dyn Bus) {
+        /* The operand of both JMP forms is already the resolved target */
+        cpu.pc = inst.get_address();
+    }
+}
+
+pub struct NOPInst;
+impl InstEXE for NOPInst {
+    fn execute(_cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        /* Unofficial NOPs with a memory operand still perform the read */
+        inst.get_operand(bus);
+    }
+}
+
+/*
+ * Unofficial opcodes. Most of them are two official instructions glued
+ * together because both decoders fire for the same opcode.
+ */
+pub struct LAXInst;
+impl InstEXE for LAXInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus) as u8;
+        cpu.a = operand;
+        cpu.x = operand;
+        cpu.set_nz(operand);
+    }
+}
+
+pub struct SAXInst;
+impl InstEXE for SAXInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        bus.write(inst.get_address(), cpu.a & cpu.x);
+    }
+}
+
+pub struct DCPInst;
+impl InstEXE for DCPInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus) as u8;
+        let result = operand.wrapping_sub(1);
+        inst.write_rmw_operand(cpu, bus, operand, result);
+        cpu.compare(cpu.a, result);
+    }
+}
+
+pub struct ISCInst;
+impl InstEXE for ISCInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus) as u8;
+        let result = operand.wrapping_add(1);
+        inst.write_rmw_operand(cpu, bus, operand, result);
+        cpu.subtract_with_borrow(result);
+    }
+}
+
+pub struct SLOInst;
+impl InstEXE for SLOInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let result = read_modify_write(cpu, inst, bus, asl);
+        cpu.a |= result;
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct RLAInst;
+impl InstEXE for RLAInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let result = read_modify_write(cpu, inst, bus, rol);
+        cpu.a &= result;
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct SREInst;
+impl InstEXE for SREInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let result = read_modify_write(cpu, inst, bus, lsr);
+        cpu.a ^= result;
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct RRAInst;
+impl InstEXE for RRAInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let result = read_modify_write(cpu, inst, bus, ror);
+        cpu.add_with_carry(result);
+    }
+}
+
+pub struct ANCInst;
+impl InstEXE for ANCInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        ANDInst::execute(cpu, inst, bus);
+        cpu.status.carry = cpu.status.negative;
+    }
+}
+
+pub struct ALRInst;
+impl InstEXE for ALRInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = cpu.a & inst.get_operand(bus) as u8;
+        cpu.a = lsr(cpu, operand);
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct ARRInst;
+impl InstEXE for ARRInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        /* AND + ROR, but C and V come from bits 6 and 5 of the result */
+        let operand = cpu.a & inst.get_operand(bus) as u8;
+        cpu.a = (operand >> 1) | ((cpu.status.carry as u8) << 7);
+        cpu.set_nz(cpu.a);
+        cpu.status.carry = (cpu.a & 0b0100_0000) != 0;
+        cpu.status.overflow = ((cpu.a >> 6) ^ (cpu.a >> 5)) & 1 != 0;
+    }
+}
+
+pub struct AXSInst;
+impl InstEXE for AXSInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        /* X = (A & X) - M, a compare that keeps its result and ignores C */
+        let operand = inst.get_operand(bus) as u8;
+        let value = cpu.a & cpu.x;
+        cpu.compare(value, operand);
+        cpu.x = value.wrapping_sub(operand);
+    }
+}
+
+/*
+ * XAA and LXA OR A with a chip dependent "magic" constant first. The
+ * values here are the ones most often seen on 2A03s.
+ */
+pub struct XAAInst;
+impl InstEXE for XAAInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus) as u8;
+        cpu.a = (cpu.a | 0xEE) & cpu.x & operand;
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct LXAInst;
+impl InstEXE for LXAInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let operand = inst.get_operand(bus) as u8;
+        cpu.a = (cpu.a | 0xFF) & operand;
+        cpu.x = cpu.a;
+        cpu.set_nz(cpu.a);
+    }
+}
+
+pub struct LASInst;
+impl InstEXE for LASInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        let value = inst.get_operand(bus) as u8 & cpu.sp;
+        cpu.a = value;
+        cpu.x = value;
+        cpu.sp = value;
+        cpu.set_nz(value);
+    }
+}
+
+/*
+ * SHA, SHX, SHY and TAS store a register ANDed with the high byte of the
+ * base address plus one. When indexing crosses a page that value also
+ * replaces the high byte of the address that gets written.
+ */
+fn store_and_high(cpu: &CPU, inst: &Instruction, bus: &mut dyn Bus, data: u8) {
+    let addr = inst.get_address();
+    let high_byte = (addr >> 8) as u8;
+    if cpu.page_crossed() {
+        let data = data & high_byte;
+        bus.write((data as u16) << 8 | (addr & 0x00FF), data);
+    } else {
+        bus.write(addr, data & high_byte.wrapping_add(1));
+    }
+}
+
+pub struct SHAInst;
+impl InstEXE for SHAInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        store_and_high(cpu, inst, bus, cpu.a & cpu.x);
+    }
+}
+
+pub struct SHXInst;
+impl InstEXE for SHXInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        store_and_high(cpu, inst, bus, cpu.x);
+    }
+}
+
+pub struct SHYInst;
+impl InstEXE for SHYInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        store_and_high(cpu, inst, bus, cpu.y);
+    }
+}
+
+pub struct TASInst;
+impl InstEXE for TASInst {
+    fn execute(cpu: &mut CPU, inst: &Instruction, bus: &mut dyn Bus) {
+        cpu.sp = cpu.a & cpu.x;
+        store_and_high(cpu, inst, bus, cpu.sp);
+    }
+}
+
+pub struct JAMInst;
+impl InstEXE for JAMInst {
+    fn execute(cpu: &mut CPU, _inst: &Instruction, _bus: &mut dyn Bus) {
+        /* The CPU stops fetching, keep PC on the JAM so it spins here */
+        cpu.pc = cpu.pc.wrapping_sub(1);
+    }
+}
@@ -0,0 +1,174 @@
+pub mod cnrom;
+pub mod mmc1;
+pub mod mmc3;
+pub mod nrom;
+pub mod uxrom;
+
+use super::{
+    bus::Bus,
+    cartridge::{Cartridge, Mirroring},
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+/*
+ * The board inside the cartridge. Its Bus side is what the CPU sees in
+ * $4020-$FFFF, the ppu_* side is the pattern table space $0000-$1FFF.
+ * Its Snapshot covers the RAM and registers, never the ROM.
+ */
+pub trait Mapper: Bus + Snapshot {
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.ppu_peek(addr)
+    }
+    fn ppu_write(&mut self, addr: u16, data: u8);
+    fn ppu_peek(&self, addr: u16) -> u8;
+
+    /* How the 2 KiB of nametable RAM in the console is mirrored */
+    fn mirroring(&self) -> Mirroring;
+
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    /* Called once per CPU cycle, for boards that watch M2 */
+    fn cpu_cycle(&mut self) {}
+
+    /* The work RAM at $6000-$7FFF, which a battery keeps alive on some boards */
+    fn prg_ram(&self) -> &[u8];
+    fn prg_ram_mut(&mut self) -> &mut [u8];
+}
+
+/* PRG ROM the banking of each board needs at the least: one 16 KiB bank, or two 8 KiB ones */
+fn min_prg_rom_size(mapper: u16) -> usize {
+    match mapper {
+        1 | 2 | 4 => 0x4000,
+        _ => 1,
+    }
+}
+
+pub fn new_mapper(cartridge: Cartridge) -> Result<Box<dyn Mapper>, String> {
+    let min_prg = min_prg_rom_size(cartridge.header.mapper);
+    if cartridge.prg_rom.len() < min_prg {
+        return Err(format!(
+            "Mapper {} needs at least {} bytes of PRG ROM, the header declares {}",
+            cartridge.header.mapper,
+            min_prg,
+            cartridge.prg_rom.len()
+        ));
+    }
+
+    Ok(match cartridge.header.mapper {
+        0 => Box::new(nrom::Nrom::new(cartridge)),
+        1 => Box::new(mmc1::Mmc1::new(cartridge)),
+        2 => Box::new(uxrom::UxRom::new(cartridge)),
+        3 => Box::new(cnrom::CnRom::new(cartridge)),
+        4 => Box::new(mmc3::Mmc3::new(cartridge)),
+        mapper => return Err(format!("Unsupported mapper {}", mapper)),
+    })
+}
+
+/* Reads a byte from a bank, wrapping bank numbers past the end of the ROM */
+fn banked(data: &[u8], bank: usize, bank_size: usize, addr: u16) -> u8 {
+    data[(bank * bank_size + addr as usize % bank_size) % data.len()]
+}
+
+/* CHR ROM, or CHR RAM when the board has none */
+pub struct Chr {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl Chr {
+    pub fn new(cartridge: &Cartridge) -> Self {
+        if cartridge.chr_rom.is_empty() {
+            Chr {
+                data: vec![0; cartridge.header.chr_ram_size.max(0x2000)],
+                writable: true,
+            }
+        } else {
+            Chr {
+                data: cartridge.chr_rom.clone(),
+                writable: false,
+            }
+        }
+    }
+
+    pub fn read(&self, bank: usize, bank_size: usize, addr: u16) -> u8 {
+        banked(&self.data, bank, bank_size, addr)
+    }
+
+    pub fn write(&mut self, bank: usize, bank_size: usize, addr: u16, data: u8) {
+        if self.writable {
+            let index = (bank * bank_size + addr as usize % bank_size) % self.data.len();
+            self.data[index] = data;
+        }
+    }
+}
+
+/* Only CHR RAM is state, CHR ROM comes with the cartridge */
+impl Snapshot for Chr {
+    fn save_state(&self, out: &mut StateWriter) {
+        if self.writable {
+            out.bytes(&self.data);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        if self.writable {
+            input.bytes_into(&mut self.data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /*
+     * A cartridge whose every PRG bank of the given size starts with its
+     * own number, and whose every 1 KiB CHR bank is filled with its number.
+     */
+    pub fn cartridge(mapper: u16, prg_banks: usize, chr_banks: usize) -> Cartridge {
+        let mut data = b"NES\x1A\0\0\0\0\0\0\0\0\0\0\0\0".to_vec();
+        data[4] = prg_banks as u8;
+        data[5] = chr_banks as u8;
+        data[6] = ((mapper & 0x0F) << 4) as u8;
+        data[7] = (mapper & 0xF0) as u8;
+        for bank in 0..prg_banks * 2 {
+            let mut prg = vec![0; 0x2000];
+            prg[0] = bank as u8;
+            data.extend(prg);
+        }
+        for bank in 0..chr_banks * 8 {
+            data.extend(vec![bank as u8; 0x400]);
+        }
+        Cartridge::from_bytes(&data).unwrap()
+    }
+
+    /* A NES 2.0 cartridge with only 96 bytes of PRG ROM, smaller than any bank */
+    pub fn tiny_prg_cartridge(mapper: u16) -> Cartridge {
+        let mut data = b"NES\x1A\x15\x00\0\x08\0\x0F\0\0\0\0\0\0".to_vec();
+        data[6] = ((mapper & 0x0F) << 4) as u8;
+        data[7] |= (mapper & 0xF0) as u8;
+        data.resize(data.len() + 96, 0);
+        Cartridge::from_bytes(&data).unwrap()
+    }
+
+    #[test]
+    fn rejects_unknown_mappers() {
+        assert!(new_mapper(cartridge(0, 1, 1)).is_ok());
+        let err = new_mapper(cartridge(99, 1, 1)).err().unwrap();
+        assert_eq!(err, "Unsupported mapper 99");
+    }
+
+    #[test]
+    fn chr_ram_is_writable_and_chr_rom_is_not() {
+        let mut ram = Chr::new(&cartridge(0, 1, 0));
+        ram.write(0, 0x2000, 0x0123, 0x5A);
+        assert_eq!(ram.read(0, 0x2000, 0x0123), 0x5A);
+
+        let mut rom = Chr::new(&cartridge(0, 1, 1));
+        rom.write(0, 0x2000, 0x0000, 0x5A);
+        assert_eq!(rom.read(0, 0x2000, 0x0000), 0x00);
+    }
+}
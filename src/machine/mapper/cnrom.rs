@@ -0,0 +1,109 @@
+use super::{
+    super::{
+        bus::Bus,
+        cartridge::{Cartridge, Mirroring},
+        state::{Snapshot, StateReader, StateWriter},
+    },
+    banked, Chr, Mapper,
+};
+
+/* Mapper 3: fixed PRG like NROM, a switchable 8 KiB CHR bank */
+pub struct CnRom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    chr_bank: usize,
+}
+
+impl CnRom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        CnRom {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Bus for CnRom {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000] = data,
+            0x8000..=0xFFFF => self.chr_bank = data as usize,
+            _ => (),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => banked(&self.prg_rom, 0, 0x8000, addr),
+            _ => 0,
+        }
+    }
+}
+
+impl Mapper for CnRom {
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank, 0x2000, addr, data);
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank, 0x2000, addr)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for CnRom {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.prg_ram);
+        self.chr.save_state(out);
+        out.u8(self.chr_bank as u8);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(input)?;
+        self.chr_bank = input.u8()? as usize;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::tests::cartridge, *};
+
+    #[test]
+    fn switches_8k_chr_bank() {
+        let mut cnrom = CnRom::new(cartridge(3, 2, 4));
+        assert_eq!(cnrom.ppu_read(0x0000), 0);
+        assert_eq!(cnrom.ppu_read(0x1C00), 7);
+
+        cnrom.write(0x8000, 2);
+        assert_eq!(cnrom.ppu_read(0x0000), 16);
+        assert_eq!(cnrom.ppu_read(0x1FFF), 23);
+        assert_eq!(cnrom.read(0xC000), 2);
+
+        /* CHR ROM ignores writes */
+        cnrom.ppu_write(0x0000, 0xFF);
+        assert_eq!(cnrom.ppu_read(0x0000), 16);
+    }
+}
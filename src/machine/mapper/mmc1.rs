@@ -0,0 +1,313 @@
+use super::{
+    super::{
+        bus::Bus,
+        cartridge::{Cartridge, Mirroring},
+        state::{Snapshot, StateReader, StateWriter},
+    },
+    banked, Chr, Mapper,
+};
+
+/*
+ * Mapper 1 (SxROM). Registers are loaded one bit at a time through a
+ * serial port at $8000-$FFFF: five writes of bit 0, the last one picking
+ * the register from address bits 13-14. A write with bit 7 set clears the
+ * shift register and locks the last PRG bank at $C000.
+ */
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+    cycle: u64,
+    last_write_cycle: Option<u64>,
+}
+
+impl Mmc1 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Mmc1 {
+            chr: Chr::new(&cartridge),
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            shift: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write_cycle: None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        /*
+         * The serial port ignores a write on the cycle right after another
+         * one. cpu_cycle only runs once an instruction is done, so here
+         * that is any further write in the same instruction: the result a
+         * read-modify-write stores after writing back the unmodified value.
+         * INC on a byte with bit 7 set resets the shift register this way.
+         */
+        if self.last_write_cycle == Some(self.cycle) {
+            return;
+        }
+        self.last_write_cycle = Some(self.cycle);
+
+        if data & 0x80 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            return;
+        }
+
+        self.shift |= (data & 0x01) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count < 5 {
+            return;
+        }
+
+        let value = self.shift;
+        self.shift = 0;
+        self.shift_count = 0;
+        match addr {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0
+    }
+
+    /* SUROM and SXROM use CHR bank bit 4 to pick a 256 KiB half of PRG ROM */
+    fn prg_outer_bank(&self) -> usize {
+        if self.prg_rom.len() > 0x40000 {
+            (self.chr_bank_0 & 0x10) as usize
+        } else {
+            0
+        }
+    }
+
+    fn prg_bank_at(&self, addr: u16) -> usize {
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let last = (self.prg_rom.len() / 0x4000 - 1).min(0x0F);
+        let bank = match (self.control >> 2) & 0x03 {
+            0 | 1 => (bank & !1) | ((addr as usize >> 14) & 1),
+            2 if addr < 0xC000 => 0,
+            2 => bank,
+            _ if addr < 0xC000 => bank,
+            _ => last,
+        };
+        self.prg_outer_bank() | bank
+    }
+
+    fn chr_bank_at(&self, addr: u16) -> usize {
+        if self.control & 0x10 == 0 {
+            (self.chr_bank_0 & !1) as usize | (addr as usize >> 12)
+        } else if addr < 0x1000 {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        }
+    }
+}
+
+impl Bus for Mmc1 {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let index = (addr as usize - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0xFFFF => self.write_register(addr, data),
+            _ => (),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()]
+            }
+            0x8000..=0xFFFF => banked(&self.prg_rom, self.prg_bank_at(addr), 0x4000, addr),
+            _ => 0,
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(self.chr_bank_at(addr), 0x1000, addr, data);
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank_at(addr), 0x1000, addr)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for Mmc1 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.prg_ram);
+        self.chr.save_state(out);
+        out.u8(self.shift);
+        out.u8(self.shift_count);
+        out.u8(self.control);
+        out.u8(self.chr_bank_0);
+        out.u8(self.chr_bank_1);
+        out.u8(self.prg_bank);
+        out.u64(self.cycle);
+        out.bool(self.last_write_cycle.is_some());
+        out.u64(self.last_write_cycle.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(input)?;
+        self.shift = input.u8()?;
+        self.shift_count = input.u8()?;
+        self.control = input.u8()?;
+        self.chr_bank_0 = input.u8()?;
+        self.chr_bank_1 = input.u8()?;
+        self.prg_bank = input.u8()?;
+        self.cycle = input.u64()?;
+        let has_last_write = input.bool()?;
+        let last_write_cycle = input.u64()?;
+        self.last_write_cycle = has_last_write.then_some(last_write_cycle);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            new_mapper,
+            tests::{cartridge, tiny_prg_cartridge},
+        },
+        *,
+    };
+
+    fn load(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.write(addr, (value >> bit) & 0x01);
+            mmc1.cpu_cycle();
+        }
+    }
+
+    #[test]
+    fn powers_up_with_last_bank_fixed() {
+        let mmc1 = Mmc1::new(cartridge(1, 8, 2));
+        assert_eq!(mmc1.peek(0x8000), 0);
+        assert_eq!(mmc1.peek(0xC000), 14);
+    }
+
+    #[test]
+    fn switches_prg_in_every_mode() {
+        let mut mmc1 = Mmc1::new(cartridge(1, 8, 2));
+        load(&mut mmc1, 0xE000, 3);
+        assert_eq!(mmc1.peek(0x8000), 6);
+        assert_eq!(mmc1.peek(0xC000), 14);
+
+        /* First bank fixed at $8000 */
+        load(&mut mmc1, 0x8000, 0x08);
+        assert_eq!(mmc1.peek(0x8000), 0);
+        assert_eq!(mmc1.peek(0xC000), 6);
+
+        /* 32 KiB mode ignores the low bit */
+        load(&mut mmc1, 0x8000, 0x00);
+        assert_eq!(mmc1.peek(0x8000), 4);
+        assert_eq!(mmc1.peek(0xC000), 6);
+    }
+
+    #[test]
+    fn switches_chr_in_both_modes() {
+        let mut mmc1 = Mmc1::new(cartridge(1, 2, 4));
+        /* 8 KiB mode, bank 3 (4 KiB units) means 8 KiB bank 1 */
+        load(&mut mmc1, 0xA000, 3);
+        assert_eq!(mmc1.ppu_read(0x0000), 8);
+        assert_eq!(mmc1.ppu_read(0x1000), 12);
+
+        load(&mut mmc1, 0x8000, 0x1C);
+        load(&mut mmc1, 0xC000, 5);
+        assert_eq!(mmc1.ppu_read(0x0000), 12);
+        assert_eq!(mmc1.ppu_read(0x1000), 20);
+    }
+
+    #[test]
+    fn controls_mirroring_and_reset() {
+        let mut mmc1 = Mmc1::new(cartridge(1, 2, 2));
+        load(&mut mmc1, 0x8000, 0x02);
+        assert_eq!(mmc1.mirroring(), Mirroring::Vertical);
+        load(&mut mmc1, 0x8000, 0x01);
+        assert_eq!(mmc1.mirroring(), Mirroring::SingleScreenUpper);
+
+        /* A reset in the middle of a load drops the bits shifted so far */
+        mmc1.write(0x8000, 0x01);
+        mmc1.cpu_cycle();
+        mmc1.write(0x8000, 0x80);
+        mmc1.cpu_cycle();
+        load(&mut mmc1, 0x8000, 0x03);
+        assert_eq!(mmc1.mirroring(), Mirroring::Horizontal);
+        assert_eq!(mmc1.control, 0x03);
+    }
+
+    #[test]
+    fn ignores_back_to_back_writes() {
+        let mut mmc1 = Mmc1::new(cartridge(1, 2, 2));
+        for _ in 0..5 {
+            mmc1.write(0x8000, 0x00);
+            mmc1.write(0x8000, 0x01);
+            mmc1.cpu_cycle();
+        }
+        assert_eq!(mmc1.control, 0x00);
+    }
+
+    #[test]
+    fn prg_ram_can_be_disabled() {
+        let mut mmc1 = Mmc1::new(cartridge(1, 2, 2));
+        mmc1.write(0x6000, 0x42);
+        assert_eq!(mmc1.peek(0x6000), 0x42);
+        load(&mut mmc1, 0xE000, 0x10);
+        assert_eq!(mmc1.peek(0x6000), 0);
+    }
+
+    #[test]
+    fn refuses_prg_rom_smaller_than_a_bank() {
+        let err = new_mapper(tiny_prg_cartridge(1)).err().unwrap();
+        assert_eq!(
+            err,
+            "Mapper 1 needs at least 16384 bytes of PRG ROM, the header declares 96"
+        );
+    }
+}
@@ -0,0 +1,395 @@
+use super::{
+    super::{
+        bus::Bus,
+        cartridge::{Cartridge, Mirroring},
+        state::{Snapshot, StateReader, StateWriter},
+    },
+    banked, Chr, Mapper,
+};
+
+/*
+ * PPU A12 has to stay low for a few M2 cycles before a rise clocks the
+ * IRQ counter, so the short dips between sprite fetches are filtered out.
+ */
+const A12_LOW_CYCLES: u8 = 3;
+
+/*
+ * Mapper 4 (TxROM). Eight bank registers picked through $8000 and loaded
+ * through $8001, two 8 KiB PRG windows and six CHR windows whose halves
+ * can be swapped, and a scanline counter clocked by rising edges of PPU
+ * address line A12.
+ */
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    four_screen: bool,
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12_high: bool,
+    a12_low_cycles: u8,
+}
+
+impl Mmc3 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let four_screen = cartridge.header.mirroring == Mirroring::FourScreen;
+        Mmc3 {
+            chr: Chr::new(&cartridge),
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            four_screen,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: cartridge.header.mirroring,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_high: false,
+            a12_low_cycles: 0,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match (addr & 0xE000, addr & 0x0001) {
+            (0x8000, 0) => self.bank_select = data,
+            (0x8000, _) => self.banks[(self.bank_select & 0x07) as usize] = data,
+            (0xA000, 0) => {
+                if !self.four_screen {
+                    self.mirroring = if data & 0x01 == 0 {
+                        Mirroring::Vertical
+                    } else {
+                        Mirroring::Horizontal
+                    };
+                }
+            }
+            (0xA000, _) => {
+                self.prg_ram_enabled = data & 0x80 != 0;
+                self.prg_ram_write_protect = data & 0x40 != 0;
+            }
+            (0xC000, 0) => self.irq_latch = data,
+            (0xC000, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (_, 0) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (_, _) => self.irq_enabled = true,
+        }
+    }
+
+    fn prg_bank_at(&self, addr: u16) -> usize {
+        let second_last = self.prg_rom.len() / 0x2000 - 2;
+        let swapped = self.bank_select & 0x40 != 0;
+        match (addr - 0x8000) / 0x2000 {
+            0 if swapped => second_last,
+            0 => self.banks[6] as usize,
+            1 => self.banks[7] as usize,
+            2 if swapped => self.banks[6] as usize,
+            2 => second_last,
+            _ => second_last + 1,
+        }
+    }
+
+    /* Returns the bank in 1 KiB units */
+    fn chr_bank_at(&self, addr: u16) -> usize {
+        let addr = if self.bank_select & 0x80 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        match addr / 0x400 {
+            0 | 1 => (self.banks[0] & !1) as usize + (addr / 0x400) as usize,
+            2 | 3 => (self.banks[1] & !1) as usize + (addr / 0x400 - 2) as usize,
+            window => self.banks[window as usize - 2] as usize,
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn watch_a12(&mut self, addr: u16) {
+        let a12_high = addr & 0x1000 != 0;
+        if a12_high && !self.a12_high && self.a12_low_cycles >= A12_LOW_CYCLES {
+            self.clock_irq_counter();
+        }
+        if a12_high {
+            self.a12_low_cycles = 0;
+        }
+        self.a12_high = a12_high;
+    }
+}
+
+impl Bus for Mmc3 {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_write_protect => {
+                self.prg_ram[addr as usize - 0x6000] = data;
+            }
+            0x8000..=0xFFFF => self.write_register(addr, data),
+            _ => (),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => banked(&self.prg_rom, self.prg_bank_at(addr), 0x2000, addr),
+            _ => 0,
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.watch_a12(addr);
+        self.ppu_peek(addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.watch_a12(addr);
+        self.chr.write(self.chr_bank_at(addr), 0x400, addr, data);
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank_at(addr), 0x400, addr)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_cycle(&mut self) {
+        if !self.a12_high {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for Mmc3 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.prg_ram);
+        self.chr.save_state(out);
+        out.u8(self.bank_select);
+        for &bank in &self.banks {
+            out.u8(bank);
+        }
+        out.bool(self.mirroring == Mirroring::Horizontal);
+        out.bool(self.prg_ram_enabled);
+        out.bool(self.prg_ram_write_protect);
+        out.u8(self.irq_latch);
+        out.u8(self.irq_counter);
+        out.bool(self.irq_reload);
+        out.bool(self.irq_enabled);
+        out.bool(self.irq_pending);
+        out.bool(self.a12_high);
+        out.u8(self.a12_low_cycles);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(input)?;
+        self.bank_select = input.u8()?;
+        for bank in self.banks.iter_mut() {
+            *bank = input.u8()?;
+        }
+        let horizontal = input.bool()?;
+        if !self.four_screen {
+            self.mirroring = if horizontal {
+                Mirroring::Horizontal
+            } else {
+                Mirroring::Vertical
+            };
+        }
+        self.prg_ram_enabled = input.bool()?;
+        self.prg_ram_write_protect = input.bool()?;
+        self.irq_latch = input.u8()?;
+        self.irq_counter = input.u8()?;
+        self.irq_reload = input.bool()?;
+        self.irq_enabled = input.bool()?;
+        self.irq_pending = input.bool()?;
+        self.a12_high = input.bool()?;
+        self.a12_low_cycles = input.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            new_mapper,
+            tests::{cartridge, tiny_prg_cartridge},
+        },
+        *,
+    };
+
+    /* What the PPU does to A12 once per rendered scanline */
+    fn scanline(mmc3: &mut Mmc3) {
+        mmc3.ppu_read(0x0000);
+        for _ in 0..A12_LOW_CYCLES {
+            mmc3.cpu_cycle();
+        }
+        mmc3.ppu_read(0x1000);
+    }
+
+    #[test]
+    fn switches_prg_in_both_modes() {
+        let mut mmc3 = Mmc3::new(cartridge(4, 4, 2));
+        mmc3.write(0x8000, 6);
+        mmc3.write(0x8001, 2);
+        mmc3.write(0x8000, 7);
+        mmc3.write(0x8001, 3);
+        assert_eq!(mmc3.peek(0x8000), 2);
+        assert_eq!(mmc3.peek(0xA000), 3);
+        assert_eq!(mmc3.peek(0xC000), 6);
+        assert_eq!(mmc3.peek(0xE000), 7);
+
+        mmc3.write(0x8000, 0x40);
+        assert_eq!(mmc3.peek(0x8000), 6);
+        assert_eq!(mmc3.peek(0xA000), 3);
+        assert_eq!(mmc3.peek(0xC000), 2);
+        assert_eq!(mmc3.peek(0xE000), 7);
+    }
+
+    #[test]
+    fn switches_chr_with_inversion() {
+        let mut mmc3 = Mmc3::new(cartridge(4, 2, 2));
+        for (register, bank) in [(0, 3), (1, 8), (2, 10), (3, 11), (4, 12), (5, 13)] {
+            mmc3.write(0x8000, register);
+            mmc3.write(0x8001, bank);
+        }
+        let windows = |mmc3: &Mmc3| (0..8).map(|w| mmc3.ppu_peek(w * 0x400)).collect::<Vec<_>>();
+        assert_eq!(windows(&mmc3), vec![2, 3, 8, 9, 10, 11, 12, 13]);
+
+        mmc3.write(0x8000, 0x80);
+        assert_eq!(windows(&mmc3), vec![10, 11, 12, 13, 2, 3, 8, 9]);
+    }
+
+    #[test]
+    fn controls_mirroring_and_prg_ram() {
+        let mut mmc3 = Mmc3::new(cartridge(4, 2, 2));
+        mmc3.write(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), Mirroring::Horizontal);
+        mmc3.write(0xA000, 0);
+        assert_eq!(mmc3.mirroring(), Mirroring::Vertical);
+
+        mmc3.write(0x6000, 0x11);
+        mmc3.write(0xA001, 0xC0);
+        mmc3.write(0x6000, 0x22);
+        assert_eq!(mmc3.peek(0x6000), 0x11);
+        mmc3.write(0xA001, 0x00);
+        assert_eq!(mmc3.peek(0x6000), 0);
+    }
+
+    #[test]
+    fn counts_scanlines_and_raises_irq() {
+        let mut mmc3 = Mmc3::new(cartridge(4, 2, 2));
+        mmc3.write(0xC000, 2);
+        mmc3.write(0xC001, 0);
+        mmc3.write(0xE001, 0);
+
+        /* Reload to 2, then 1, then 0 */
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq_pending());
+        scanline(&mut mmc3);
+        assert!(mmc3.irq_pending());
+
+        /* $E000 acknowledges and disables */
+        mmc3.write(0xE000, 0);
+        assert!(!mmc3.irq_pending());
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        assert!(!mmc3.irq_pending());
+    }
+
+    #[test]
+    fn filters_short_a12_pulses() {
+        let mut mmc3 = Mmc3::new(cartridge(4, 2, 2));
+        mmc3.write(0xC000, 1);
+        mmc3.write(0xE001, 0);
+        scanline(&mut mmc3);
+
+        /* Sprite fetches toggle A12 without enough low time in between */
+        for _ in 0..8 {
+            mmc3.ppu_read(0x2000);
+            mmc3.ppu_read(0x1000);
+        }
+        assert!(!mmc3.irq_pending());
+        scanline(&mut mmc3);
+        assert!(mmc3.irq_pending());
+    }
+
+    #[test]
+    fn save_state_keeps_banks_and_irq_counter() {
+        let mut mmc3 = Mmc3::new(cartridge(4, 4, 2));
+        mmc3.write(0x8000, 6);
+        mmc3.write(0x8001, 3);
+        mmc3.write(0xA000, 1);
+        mmc3.write(0x6000, 0x99);
+        mmc3.write(0xC000, 5);
+        mmc3.write(0xC001, 0);
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+
+        let mut out = StateWriter::new();
+        mmc3.save_state(&mut out);
+        let state = out.into_bytes();
+
+        let mut restored = Mmc3::new(cartridge(4, 4, 2));
+        restored.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(restored.peek(0x8000), 3);
+        assert_eq!(restored.peek(0x6000), 0x99);
+        assert_eq!(restored.mirroring(), Mirroring::Horizontal);
+        assert_eq!(restored.irq_counter, mmc3.irq_counter);
+    }
+
+    #[test]
+    fn refuses_prg_rom_smaller_than_a_bank() {
+        let err = new_mapper(tiny_prg_cartridge(4)).err().unwrap();
+        assert_eq!(
+            err,
+            "Mapper 4 needs at least 16384 bytes of PRG ROM, the header declares 96"
+        );
+    }
+}
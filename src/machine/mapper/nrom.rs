@@ -0,0 +1,100 @@
+use super::{
+    super::{
+        bus::Bus,
+        cartridge::{Cartridge, Mirroring},
+        state::{Snapshot, StateReader, StateWriter},
+    },
+    banked, Chr, Mapper,
+};
+
+/* Mapper 0: no bank switching, 16 or 32 KiB of PRG ROM and 8 KiB of CHR */
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Nrom {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+        }
+    }
+}
+
+impl Bus for Nrom {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram[addr as usize - 0x6000] = data;
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => banked(&self.prg_rom, 0, 0x8000, addr),
+            _ => 0,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(0, 0x2000, addr, data);
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(0, 0x2000, addr)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for Nrom {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.prg_ram);
+        self.chr.save_state(out);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::tests::cartridge, *};
+
+    #[test]
+    fn mirrors_16k_prg_and_keeps_ram() {
+        let mut nrom = Nrom::new(cartridge(0, 1, 1));
+        assert_eq!(nrom.read(0x8000), 0);
+        assert_eq!(nrom.read(0xA000), 1);
+        assert_eq!(nrom.read(0xC000), 0);
+        assert_eq!(nrom.read(0xE000), 1);
+
+        nrom.write(0x8000, 0x77);
+        assert_eq!(nrom.read(0x8000), 0);
+        nrom.write(0x6123, 0x77);
+        assert_eq!(nrom.read(0x6123), 0x77);
+    }
+}
@@ -0,0 +1,129 @@
+use super::{
+    super::{
+        bus::Bus,
+        cartridge::{Cartridge, Mirroring},
+        state::{Snapshot, StateReader, StateWriter},
+    },
+    banked, Chr, Mapper,
+};
+
+/* Mapper 2: a switchable 16 KiB bank at $8000, the last bank fixed at $C000 */
+pub struct UxRom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    prg_bank: usize,
+}
+
+impl UxRom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        UxRom {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            prg_rom: cartridge.prg_rom,
+            prg_ram: cartridge.prg_ram,
+            prg_bank: 0,
+        }
+    }
+
+    fn last_bank(&self) -> usize {
+        self.prg_rom.len() / 0x4000 - 1
+    }
+}
+
+impl Bus for UxRom {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000] = data,
+            0x8000..=0xFFFF => self.prg_bank = data as usize,
+            _ => (),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xBFFF => banked(&self.prg_rom, self.prg_bank, 0x4000, addr),
+            0xC000..=0xFFFF => banked(&self.prg_rom, self.last_bank(), 0x4000, addr),
+            _ => 0,
+        }
+    }
+}
+
+impl Mapper for UxRom {
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr.write(0, 0x2000, addr, data);
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(0, 0x2000, addr)
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+impl Snapshot for UxRom {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.prg_ram);
+        self.chr.save_state(out);
+        out.u8(self.prg_bank as u8);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        input.bytes_into(&mut self.prg_ram)?;
+        self.chr.load_state(input)?;
+        self.prg_bank = input.u8()? as usize;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            new_mapper,
+            tests::{cartridge, tiny_prg_cartridge},
+        },
+        *,
+    };
+
+    #[test]
+    fn switches_low_bank_and_fixes_last() {
+        let mut uxrom = UxRom::new(cartridge(2, 8, 0));
+        assert_eq!(uxrom.read(0x8000), 0);
+        assert_eq!(uxrom.read(0xC000), 14);
+
+        uxrom.write(0xFFFF, 3);
+        assert_eq!(uxrom.read(0x8000), 6);
+        assert_eq!(uxrom.read(0xA000), 7);
+        assert_eq!(uxrom.read(0xC000), 14);
+
+        /* Bank numbers past the end wrap around */
+        uxrom.write(0x8000, 9);
+        assert_eq!(uxrom.read(0x8000), 2);
+    }
+
+    #[test]
+    fn refuses_prg_rom_smaller_than_a_bank() {
+        let err = new_mapper(tiny_prg_cartridge(2)).err().unwrap();
+        assert_eq!(
+            err,
+            "Mapper 2 needs at least 16384 bytes of PRG ROM, the header declares 96"
+        );
+    }
+}
@@ -1,23 +1,41 @@
+use super::bus::Bus;
+
+/*
+ * A flat 64 KiB RAM. Works as the whole bus of a bare 6502 system, or as
+ * a RAM-backed device on a bigger one.
+ */
 pub struct Memory {
     pub blocks: Vec<u8>,
 }
 
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Memory {
     pub fn new() -> Self {
         Memory {
-            blocks: vec![0; 0xFFFF],
+            blocks: vec![0; 0x10000],
         }
     }
 
     pub fn reset(&mut self) {
-        self.blocks = vec![0; 0xFFFF];
+        self.blocks = vec![0; 0x10000];
     }
+}
 
-    pub fn read(&self, addr: u16) -> u8 {
+impl Bus for Memory {
+    fn read(&mut self, addr: u16) -> u8 {
         self.blocks[addr as usize]
     }
 
-    pub fn write(&mut self, addr: u16, data: u8) {
+    fn write(&mut self, addr: u16, data: u8) {
         self.blocks[addr as usize] = data;
     }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.blocks[addr as usize]
+    }
 }
@@ -0,0 +1,236 @@
+use std::fs;
+
+use super::controller::ButtonState;
+
+/* Button letters of an FM2 gamepad field, most significant bit first */
+const BUTTON_LETTERS: &[u8; 8] = b"RLDUTSBA";
+
+/* What happened on the console during one frame of a movie */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub buttons: [ButtonState; 2],
+}
+
+impl MovieFrame {
+    pub const RESET: u8 = 0x01;
+    pub const POWER: u8 = 0x02;
+}
+
+/*
+ * An input movie in the FCEUX FM2 text format: "key value" header lines
+ * followed by one "|commands|port0|port1|port2|" line per frame. Only
+ * standard gamepads in the first two ports are supported.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub header: Vec<(String, String)>,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(rom_name: &str) -> Self {
+        let header = [
+            ("version", "3"),
+            ("emuVersion", "22020"),
+            ("rerecordCount", "0"),
+            ("palFlag", "0"),
+            ("romFilename", rom_name),
+            ("fourscore", "0"),
+            ("microphone", "0"),
+            ("port0", "1"),
+            ("port1", "1"),
+            ("port2", "0"),
+            ("FDS", "0"),
+            ("NewPPU", "0"),
+        ];
+        Movie {
+            header: header
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.header
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /* Which of the two ports have a gamepad field on every input line */
+    fn gamepads(&self) -> Result<[bool; 2], String> {
+        if self.get("binary").is_some_and(|value| value != "0") {
+            return Err("Binary FM2 movies are not supported".to_string());
+        }
+        if self.get("fourscore").is_some_and(|value| value != "0") {
+            return Err("Four Score movies are not supported".to_string());
+        }
+        let mut gamepads = [false; 2];
+        for (port, gamepad) in gamepads.iter_mut().enumerate() {
+            *gamepad = match self.get(&format!("port{}", port)).unwrap_or("1") {
+                "0" => false,
+                "1" => true,
+                device => return Err(format!("Unsupported device {} in port {}", device, port)),
+            };
+        }
+        Ok(gamepads)
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie {
+            header: Vec::new(),
+            frames: Vec::new(),
+        };
+        let mut gamepads = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            if !line.starts_with('|') {
+                let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+                movie
+                    .header
+                    .push((key.to_string(), value.trim().to_string()));
+                continue;
+            }
+
+            /* The header is complete once the input starts */
+            let gamepads = match gamepads {
+                Some(gamepads) => gamepads,
+                None => *gamepads.insert(movie.gamepads()?),
+            };
+            let bad_line = || format!("Bad input on line {}: {}", number + 1, line);
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < 5 {
+                return Err(bad_line());
+            }
+
+            let mut frame = MovieFrame {
+                commands: fields[1].trim().parse().map_err(|_| bad_line())?,
+                ..MovieFrame::default()
+            };
+            for (port, field) in fields[2..4].iter().enumerate() {
+                if !gamepads[port] {
+                    continue;
+                }
+                if field.len() != BUTTON_LETTERS.len() {
+                    return Err(bad_line());
+                }
+                for (bit, button) in field.bytes().enumerate() {
+                    if button != b'.' && button != b' ' {
+                        frame.buttons[port].0 |= 0x80 >> bit;
+                    }
+                }
+            }
+            movie.frames.push(frame);
+        }
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> Result<String, String> {
+        let gamepads = self.gamepads()?;
+        let mut text = String::new();
+        for (key, value) in &self.header {
+            text += &format!("{} {}\n", key, value);
+        }
+        for frame in &self.frames {
+            text += &format!("|{}|", frame.commands);
+            for (&gamepad, buttons) in gamepads.iter().zip(frame.buttons) {
+                if gamepad {
+                    text.extend(BUTTON_LETTERS.iter().enumerate().map(|(bit, &letter)| {
+                        if buttons.0 & (0x80 >> bit) != 0 {
+                            letter as char
+                        } else {
+                            '.'
+                        }
+                    }));
+                }
+                text.push('|');
+            }
+            text += "|\n";
+        }
+        Ok(text)
+    }
+
+    pub fn load(path: &str) -> Result<Movie, String> {
+        let text =
+            fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {}", path, err))?;
+        Movie::parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_fm2()?).map_err(|err| format!("Failed to write {}: {}", path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FM2: &str = "version 3\n\
+                       emuVersion 22020\n\
+                       romFilename smb\n\
+                       port0 1\n\
+                       port1 0\n\
+                       port2 0\n\
+                       comment author someone\n\
+                       |2|........|||\n\
+                       |0|R..U...A|||\n\
+                       |1|.L..TS.A|||\n";
+
+    #[test]
+    fn parses_fm2() {
+        let movie = Movie::parse(FM2).unwrap();
+        assert_eq!(movie.get("romFilename"), Some("smb"));
+        assert_eq!(movie.get("comment"), Some("author someone"));
+        assert_eq!(movie.frames.len(), 3);
+        assert_eq!(movie.frames[0].commands, MovieFrame::POWER);
+        assert_eq!(
+            movie.frames[1].buttons[0],
+            ButtonState::RIGHT | ButtonState::UP | ButtonState::A
+        );
+        assert_eq!(movie.frames[2].commands, MovieFrame::RESET);
+        assert_eq!(
+            movie.frames[2].buttons[0],
+            ButtonState::LEFT | ButtonState::START | ButtonState::SELECT | ButtonState::A
+        );
+        assert_eq!(movie.frames[2].buttons[1], ButtonState::empty());
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let movie = Movie::parse(FM2).unwrap();
+        assert_eq!(movie.to_fm2().unwrap(), FM2);
+
+        let mut movie = Movie::new("game");
+        movie.frames.push(MovieFrame {
+            commands: 0,
+            buttons: [ButtonState::B, ButtonState::DOWN],
+        });
+        let text = movie.to_fm2().unwrap();
+        assert!(text.ends_with("port2 0\nFDS 0\nNewPPU 0\n|0|......B.|..D.....||\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_play() {
+        let err = |text: &str| Movie::parse(text).err().unwrap();
+        assert_eq!(
+            err("binary 1\n|0|||\n"),
+            "Binary FM2 movies are not supported"
+        );
+        assert_eq!(
+            err("port1 2\n|0|........||\n"),
+            "Unsupported device 2 in port 1"
+        );
+        assert_eq!(
+            err("port1 0\n|x|........|||\n"),
+            "Bad input on line 2: |x|........|||"
+        );
+    }
+}
@@ -0,0 +1,973 @@
+use super::{
+    cartridge::Mirroring,
+    mapper::Mapper,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+const DOTS_PER_SCANLINE: u16 = 341;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+
+const CTRL_NAMETABLE: u8 = 0b0000_0011;
+const CTRL_INCREMENT_32: u8 = 0b0000_0100;
+const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+const CTRL_BACKGROUND_TABLE: u8 = 0b0001_0000;
+const CTRL_SPRITE_8X16: u8 = 0b0010_0000;
+const CTRL_NMI_ENABLE: u8 = 0b1000_0000;
+
+const MASK_GRAYSCALE: u8 = 0b0000_0001;
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+const MASK_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SPRITES: u8 = 0b0001_0000;
+
+const STATUS_OVERFLOW: u8 = 0b0010_0000;
+const STATUS_SPRITE_ZERO_HIT: u8 = 0b0100_0000;
+const STATUS_VBLANK: u8 = 0b1000_0000;
+
+const SPRITE_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+const SPRITE_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+const SPRITE_FLIP_VERTICAL: u8 = 0b1000_0000;
+
+/* The 2C02 colors as RGB */
+#[rustfmt::skip]
+const PALETTE: [(u8, u8, u8); 64] = [
+    (84, 84, 84), (0, 30, 116), (8, 16, 144), (48, 0, 136), (68, 0, 100), (92, 0, 48), (84, 4, 0), (60, 24, 0),
+    (32, 42, 0), (8, 58, 0), (0, 64, 0), (0, 60, 0), (0, 50, 60), (0, 0, 0), (0, 0, 0), (0, 0, 0),
+    (152, 150, 152), (8, 76, 196), (48, 50, 236), (92, 30, 228), (136, 20, 176), (160, 20, 100), (152, 34, 32), (120, 60, 0),
+    (84, 90, 0), (40, 114, 0), (8, 124, 0), (0, 118, 40), (0, 102, 120), (0, 0, 0), (0, 0, 0), (0, 0, 0),
+    (236, 238, 236), (76, 154, 236), (120, 124, 236), (176, 98, 236), (228, 84, 236), (236, 88, 180), (236, 106, 100), (212, 136, 32),
+    (160, 170, 0), (116, 196, 0), (76, 208, 32), (56, 204, 108), (56, 180, 204), (60, 60, 60), (0, 0, 0), (0, 0, 0),
+    (236, 238, 236), (168, 204, 236), (188, 188, 236), (212, 178, 236), (236, 174, 236), (236, 174, 212), (236, 180, 176), (228, 196, 144),
+    (204, 210, 120), (180, 222, 120), (168, 226, 144), (152, 226, 180), (160, 214, 228), (160, 162, 160), (0, 0, 0), (0, 0, 0),
+];
+
+/* A sprite picked for the next scanline, its pattern already flipped */
+#[derive(Clone, Copy, Default)]
+struct LineSprite {
+    x: u8,
+    attributes: u8,
+    pattern_low: u8,
+    pattern_high: u8,
+}
+
+/*
+ * The 2C02. Scrolling follows the "loopy" model: v is the current VRAM
+ * address, t the temporary one the CPU writes into, x the fine X scroll
+ * and w the shared $2005/$2006 write toggle. Rendering runs one dot per
+ * step, 341 dots on each of 262 scanlines.
+ */
+pub struct Ppu {
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    oam: [u8; 256],
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+    read_buffer: u8,
+    io_latch: u8,
+    vram: [u8; 0x1000],
+    palette: [u8; 32],
+
+    scanline: u16,
+    dot: u16,
+    odd_frame: bool,
+    frame_count: u64,
+
+    next_tile: u8,
+    next_attribute: u8,
+    next_pattern_low: u8,
+    next_pattern_high: u8,
+    pattern_shift_low: u16,
+    pattern_shift_high: u16,
+    attribute_shift_low: u16,
+    attribute_shift_high: u16,
+
+    line_oam: [usize; 8],
+    line_oam_count: usize,
+    sprites: [LineSprite; 8],
+    sprite_count: usize,
+    sprite_zero_in_line: bool,
+    next_sprite_zero_in_line: bool,
+
+    frame: Vec<u8>,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; 256],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+            vram: [0; 0x1000],
+            palette: [0; 32],
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            frame_count: 0,
+            next_tile: 0,
+            next_attribute: 0,
+            next_pattern_low: 0,
+            next_pattern_high: 0,
+            pattern_shift_low: 0,
+            pattern_shift_high: 0,
+            attribute_shift_low: 0,
+            attribute_shift_high: 0,
+            line_oam: [0; 8],
+            line_oam_count: 0,
+            sprites: [LineSprite::default(); 8],
+            sprite_count: 0,
+            sprite_zero_in_line: false,
+            next_sprite_zero_in_line: false,
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+        }
+    }
+
+    /* Back to power-up, but still counting frames from where it was */
+    pub fn power_cycle(&mut self) {
+        *self = Ppu {
+            frame_count: self.frame_count,
+            ..Ppu::new()
+        };
+    }
+
+    /* The last completed picture, 8-bit RGB, row by row */
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /* Counts the frames that reached vblank */
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /* The PPU pulls /NMI low for as long as vblank and NMI enable are both set */
+    pub fn nmi_line(&self) -> bool {
+        self.status & STATUS_VBLANK != 0 && self.ctrl & CTRL_NMI_ENABLE != 0
+    }
+
+    /* Used by OAM DMA, which goes through $2004 one byte at a time */
+    pub fn write_oam(&mut self, data: u8) {
+        self.oam[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    fn vram_increment(&self) -> u16 {
+        if self.ctrl & CTRL_INCREMENT_32 != 0 {
+            32
+        } else {
+            1
+        }
+    }
+
+    /*
+     * The CPU side, $2000-$2007. The bus folds the mirrors down before
+     * calling in, so only the low three bits matter.
+     */
+    pub fn read_register(&mut self, addr: u16, mapper: Option<&mut (dyn Mapper + 'static)>) -> u8 {
+        let data = match addr & 0x0007 {
+            2 => {
+                let data = (self.status & 0xE0) | (self.io_latch & 0x1F);
+                self.status &= !STATUS_VBLANK;
+                self.w = false;
+                data
+            }
+            4 => self.oam[self.oam_addr as usize],
+            7 => {
+                let addr = self.v & 0x3FFF;
+                let data = if addr >= 0x3F00 {
+                    /* Palette reads skip the buffer but still refill it from the nametable below */
+                    self.read_buffer = self.read(addr - 0x1000, mapper);
+                    (self.palette_entry(addr) & 0x3F) | (self.io_latch & 0xC0)
+                } else {
+                    let data = self.read_buffer;
+                    self.read_buffer = self.read(addr, mapper);
+                    data
+                };
+                self.v = self.v.wrapping_add(self.vram_increment()) & 0x7FFF;
+                data
+            }
+            _ => self.io_latch,
+        };
+        self.io_latch = data;
+        data
+    }
+
+    pub fn write_register(
+        &mut self,
+        addr: u16,
+        data: u8,
+        mapper: Option<&mut (dyn Mapper + 'static)>,
+    ) {
+        self.io_latch = data;
+        match addr & 0x0007 {
+            0 => {
+                self.ctrl = data;
+                self.t = (self.t & !0x0C00) | ((data & CTRL_NAMETABLE) as u16) << 10;
+            }
+            1 => self.mask = data,
+            3 => self.oam_addr = data,
+            4 => self.write_oam(data),
+            5 => {
+                if !self.w {
+                    self.t = (self.t & !0x001F) | (data >> 3) as u16;
+                    self.x = data & 0x07;
+                } else {
+                    self.t = (self.t & !0x73E0)
+                        | ((data & 0x07) as u16) << 12
+                        | ((data & 0xF8) as u16) << 2;
+                }
+                self.w = !self.w;
+            }
+            6 => {
+                if !self.w {
+                    self.t = (self.t & 0x00FF) | ((data & 0x3F) as u16) << 8;
+                } else {
+                    self.t = (self.t & 0xFF00) | data as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            }
+            7 => {
+                self.write(self.v & 0x3FFF, data, mapper);
+                self.v = self.v.wrapping_add(self.vram_increment()) & 0x7FFF;
+            }
+            _ => (),
+        }
+    }
+
+    /* Register reads without side effects, for the monitor */
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        match addr & 0x0007 {
+            2 => (self.status & 0xE0) | (self.io_latch & 0x1F),
+            4 => self.oam[self.oam_addr as usize],
+            7 if self.v & 0x3FFF >= 0x3F00 => self.palette_entry(self.v & 0x3FFF),
+            7 => self.read_buffer,
+            _ => self.io_latch,
+        }
+    }
+
+    fn nametable_index(addr: u16, mirroring: Mirroring) -> usize {
+        let addr = addr & 0x0FFF;
+        (match mirroring {
+            Mirroring::Vertical => addr & 0x07FF,
+            Mirroring::Horizontal => (addr >> 1) & 0x0400 | (addr & 0x03FF),
+            Mirroring::SingleScreenLower => addr & 0x03FF,
+            Mirroring::SingleScreenUpper => 0x0400 | (addr & 0x03FF),
+            Mirroring::FourScreen => addr,
+        }) as usize
+    }
+
+    fn palette_index(addr: u16) -> usize {
+        let index = addr as usize & 0x1F;
+        /* The backdrop entries of the sprite palettes mirror the background ones */
+        if index & 0x13 == 0x10 {
+            index & !0x10
+        } else {
+            index
+        }
+    }
+
+    fn palette_entry(&self, addr: u16) -> u8 {
+        let entry = self.palette[Self::palette_index(addr)];
+        if self.mask & MASK_GRAYSCALE != 0 {
+            entry & 0x30
+        } else {
+            entry
+        }
+    }
+
+    fn read(&mut self, addr: u16, mapper: Option<&mut (dyn Mapper + 'static)>) -> u8 {
+        match addr & 0x3FFF {
+            0x0000..=0x1FFF => mapper.map_or(0, |mapper| mapper.ppu_read(addr)),
+            0x2000..=0x3EFF => {
+                let mirroring = mapper.map_or(Mirroring::Horizontal, |mapper| mapper.mirroring());
+                self.vram[Self::nametable_index(addr, mirroring)]
+            }
+            _ => self.palette[Self::palette_index(addr)],
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8, mapper: Option<&mut (dyn Mapper + 'static)>) {
+        match addr & 0x3FFF {
+            0x0000..=0x1FFF => {
+                if let Some(mapper) = mapper {
+                    mapper.ppu_write(addr, data);
+                }
+            }
+            0x2000..=0x3EFF => {
+                let mirroring = mapper.map_or(Mirroring::Horizontal, |mapper| mapper.mirroring());
+                self.vram[Self::nametable_index(addr, mirroring)] = data;
+            }
+            _ => self.palette[Self::palette_index(addr)] = data & 0x3F,
+        }
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            /* Rows 30 and 31 hold attributes, scrolling into them wraps without switching */
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | coarse_y << 5;
+    }
+
+    fn copy_horizontal(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    fn copy_vertical(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.pattern_shift_low = (self.pattern_shift_low & 0xFF00) | self.next_pattern_low as u16;
+        self.pattern_shift_high =
+            (self.pattern_shift_high & 0xFF00) | self.next_pattern_high as u16;
+        let fill = |bit: u8| if bit != 0 { 0x00FF } else { 0x0000 };
+        self.attribute_shift_low =
+            (self.attribute_shift_low & 0xFF00) | fill(self.next_attribute & 0x01);
+        self.attribute_shift_high =
+            (self.attribute_shift_high & 0xFF00) | fill(self.next_attribute & 0x02);
+    }
+
+    fn shift_background(&mut self) {
+        self.pattern_shift_low <<= 1;
+        self.pattern_shift_high <<= 1;
+        self.attribute_shift_low <<= 1;
+        self.attribute_shift_high <<= 1;
+    }
+
+    fn background_pattern_addr(&self, plane: u16) -> u16 {
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0x0000
+        };
+        table + (self.next_tile as u16) * 16 + ((self.v >> 12) & 0x07) + plane
+    }
+
+    /* One step of the eight-dot nametable/attribute/pattern fetch cycle */
+    fn fetch_background(&mut self, mut mapper: Option<&mut (dyn Mapper + 'static)>) {
+        match (self.dot - 1) % 8 {
+            0 => {
+                self.load_background_shifters();
+                self.next_tile = self.read(0x2000 | (self.v & 0x0FFF), mapper);
+            }
+            2 => {
+                let v = self.v;
+                let mut attribute = self.read(
+                    0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07),
+                    mapper,
+                );
+                if v & 0x0040 != 0 {
+                    attribute >>= 4;
+                }
+                if v & 0x0002 != 0 {
+                    attribute >>= 2;
+                }
+                self.next_attribute = attribute & 0x03;
+            }
+            4 => {
+                let addr = self.background_pattern_addr(0);
+                self.next_pattern_low = self.read(addr, mapper.as_deref_mut());
+            }
+            6 => {
+                let addr = self.background_pattern_addr(8);
+                self.next_pattern_high = self.read(addr, mapper);
+            }
+            7 => self.increment_coarse_x(),
+            _ => (),
+        }
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & CTRL_SPRITE_8X16 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /* Picks the first eight sprites that cover the next scanline */
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        self.line_oam_count = 0;
+        self.next_sprite_zero_in_line = false;
+        for index in 0..64 {
+            let row = self.scanline.wrapping_sub(self.oam[index * 4] as u16);
+            if row >= height {
+                continue;
+            }
+            if self.line_oam_count == 8 {
+                self.status |= STATUS_OVERFLOW;
+                break;
+            }
+            if index == 0 {
+                self.next_sprite_zero_in_line = true;
+            }
+            self.line_oam[self.line_oam_count] = index;
+            self.line_oam_count += 1;
+        }
+    }
+
+    fn sprite_pattern_addr(&self, slot: usize) -> u16 {
+        if slot >= self.line_oam_count {
+            /* Empty slots still fetch tile $FF, which mappers watching A12 can see */
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 && self.sprite_height() == 8 {
+                0x1000
+            } else {
+                0x0000
+            };
+            return table + 0xFF * 16;
+        }
+
+        let sprite = &self.oam[self.line_oam[slot] * 4..self.line_oam[slot] * 4 + 4];
+        let height = self.sprite_height();
+        let mut row = self.scanline.wrapping_sub(sprite[0] as u16) & 0x0F;
+        if sprite[2] & SPRITE_FLIP_VERTICAL != 0 {
+            row = height - 1 - row;
+        }
+        if height == 16 {
+            let table = (sprite[1] as u16 & 0x01) * 0x1000;
+            let tile = (sprite[1] & 0xFE) as u16 + row / 8;
+            table + tile * 16 + row % 8
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+                0x1000
+            } else {
+                0x0000
+            };
+            table + sprite[1] as u16 * 16 + row
+        }
+    }
+
+    /* Dots 257-320 fetch the patterns of the eight sprite slots, eight dots each */
+    fn fetch_sprite(&mut self, mapper: Option<&mut (dyn Mapper + 'static)>) {
+        let slot = (self.dot - 257) as usize / 8;
+        let plane = match (self.dot - 257) % 8 {
+            5 => 0,
+            7 => 8,
+            _ => return,
+        };
+        let addr = self.sprite_pattern_addr(slot) + plane;
+        let mut data = self.read(addr, mapper);
+        if slot >= self.line_oam_count {
+            return;
+        }
+        let index = self.line_oam[slot] * 4;
+        if self.oam[index + 2] & SPRITE_FLIP_HORIZONTAL != 0 {
+            data = data.reverse_bits();
+        }
+        let sprite = &mut self.sprites[slot];
+        sprite.attributes = self.oam[index + 2];
+        sprite.x = self.oam[index + 3];
+        if plane == 0 {
+            sprite.pattern_low = data;
+        } else {
+            sprite.pattern_high = data;
+        }
+    }
+
+    fn background_pixel(&self, x: usize) -> u8 {
+        if self.mask & MASK_BACKGROUND == 0 || (x < 8 && self.mask & MASK_BACKGROUND_LEFT == 0) {
+            return 0;
+        }
+        let bit = 0x8000 >> self.x;
+        let pixel = (self.pattern_shift_low & bit != 0) as u8
+            | ((self.pattern_shift_high & bit != 0) as u8) << 1;
+        let palette = (self.attribute_shift_low & bit != 0) as u8
+            | ((self.attribute_shift_high & bit != 0) as u8) << 1;
+        if pixel == 0 {
+            0
+        } else {
+            palette << 2 | pixel
+        }
+    }
+
+    /* Returns the palette index, priority and whether it came from sprite 0 */
+    fn sprite_pixel(&self, x: usize) -> Option<(u8, bool, bool)> {
+        if self.mask & MASK_SPRITES == 0 || (x < 8 && self.mask & MASK_SPRITES_LEFT == 0) {
+            return None;
+        }
+        for (slot, sprite) in self.sprites[..self.sprite_count].iter().enumerate() {
+            let column = x.wrapping_sub(sprite.x as usize);
+            if column >= 8 {
+                continue;
+            }
+            let bit = 7 - column;
+            let pixel =
+                (sprite.pattern_low >> bit) & 0x01 | ((sprite.pattern_high >> bit) & 0x01) << 1;
+            if pixel == 0 {
+                continue;
+            }
+            let palette = 0x10 | (sprite.attributes & 0x03) << 2 | pixel;
+            let behind = sprite.attributes & SPRITE_BEHIND_BACKGROUND != 0;
+            return Some((palette, behind, slot == 0 && self.sprite_zero_in_line));
+        }
+        None
+    }
+
+    fn render_pixel(&mut self) {
+        let x = self.dot as usize - 1;
+        let y = self.scanline as usize;
+
+        let color = if self.rendering_enabled() {
+            let background = self.background_pixel(x);
+            let index = match self.sprite_pixel(x) {
+                Some((sprite, behind, sprite_zero)) => {
+                    if sprite_zero && background != 0 && x != 255 {
+                        self.status |= STATUS_SPRITE_ZERO_HIT;
+                    }
+                    if behind && background != 0 {
+                        background
+                    } else {
+                        sprite
+                    }
+                }
+                None => background,
+            };
+            self.palette_entry(0x3F00 | index as u16)
+        } else {
+            self.palette_entry(0x3F00)
+        };
+
+        let (r, g, b) = PALETTE[color as usize & 0x3F];
+        let offset = (y * SCREEN_WIDTH + x) * 3;
+        self.frame[offset..offset + 3].copy_from_slice(&[r, g, b]);
+    }
+
+    fn step_render_line(&mut self, mut mapper: Option<&mut (dyn Mapper + 'static)>) {
+        let pre_render = self.scanline == PRE_RENDER_SCANLINE;
+
+        if (2..=257).contains(&self.dot) || (321..=337).contains(&self.dot) {
+            if self.mask & MASK_BACKGROUND != 0 {
+                self.shift_background();
+            }
+            self.fetch_background(mapper.as_deref_mut());
+        }
+
+        match self.dot {
+            256 => self.increment_y(),
+            257 => {
+                self.load_background_shifters();
+                self.copy_horizontal();
+                self.oam_addr = 0;
+                self.sprite_count = 0;
+                if pre_render {
+                    self.line_oam_count = 0;
+                    self.next_sprite_zero_in_line = false;
+                } else {
+                    self.evaluate_sprites();
+                }
+            }
+            280..=304 if pre_render => self.copy_vertical(),
+            338 | 340 => {
+                self.next_tile = self.read(0x2000 | (self.v & 0x0FFF), mapper.as_deref_mut());
+            }
+            _ => (),
+        }
+
+        if (257..=320).contains(&self.dot) {
+            self.fetch_sprite(mapper);
+            if self.dot == 320 {
+                self.sprite_count = self.line_oam_count;
+                self.sprite_zero_in_line = self.next_sprite_zero_in_line;
+            }
+        }
+    }
+
+    /* Advances the PPU by one dot */
+    pub fn step(&mut self, mapper: Option<&mut (dyn Mapper + 'static)>) {
+        let visible = self.scanline < SCREEN_HEIGHT as u16;
+
+        if self.scanline == PRE_RENDER_SCANLINE && self.dot == 1 {
+            self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO_HIT | STATUS_OVERFLOW);
+        }
+
+        if (visible || self.scanline == PRE_RENDER_SCANLINE) && self.rendering_enabled() {
+            self.step_render_line(mapper);
+        }
+
+        if visible && (1..=256).contains(&self.dot) {
+            self.render_pixel();
+        }
+
+        if self.scanline == VBLANK_SCANLINE && self.dot == 1 {
+            self.status |= STATUS_VBLANK;
+            self.frame_count += 1;
+        }
+
+        self.dot += 1;
+        /* With rendering on, odd frames skip the last dot of the pre-render line */
+        let skip_dot = self.scanline == PRE_RENDER_SCANLINE
+            && self.dot == DOTS_PER_SCANLINE - 1
+            && self.odd_frame
+            && self.rendering_enabled();
+        if self.dot == DOTS_PER_SCANLINE || skip_dot {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > PRE_RENDER_SCANLINE {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+}
+
+impl Snapshot for Ppu {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.u8(self.ctrl);
+        out.u8(self.mask);
+        out.u8(self.status);
+        out.u8(self.oam_addr);
+        out.bytes(&self.oam);
+        out.u16(self.v);
+        out.u16(self.t);
+        out.u8(self.x);
+        out.bool(self.w);
+        out.u8(self.read_buffer);
+        out.u8(self.io_latch);
+        out.bytes(&self.vram);
+        out.bytes(&self.palette);
+
+        out.u16(self.scanline);
+        out.u16(self.dot);
+        out.bool(self.odd_frame);
+        out.u64(self.frame_count);
+
+        out.u8(self.next_tile);
+        out.u8(self.next_attribute);
+        out.u8(self.next_pattern_low);
+        out.u8(self.next_pattern_high);
+        out.u16(self.pattern_shift_low);
+        out.u16(self.pattern_shift_high);
+        out.u16(self.attribute_shift_low);
+        out.u16(self.attribute_shift_high);
+
+        for &index in &self.line_oam {
+            out.u8(index as u8);
+        }
+        out.u8(self.line_oam_count as u8);
+        for sprite in &self.sprites {
+            out.u8(sprite.x);
+            out.u8(sprite.attributes);
+            out.u8(sprite.pattern_low);
+            out.u8(sprite.pattern_high);
+        }
+        out.u8(self.sprite_count as u8);
+        out.bool(self.sprite_zero_in_line);
+        out.bool(self.next_sprite_zero_in_line);
+
+        out.bytes(&self.frame);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String> {
+        self.ctrl = input.u8()?;
+        self.mask = input.u8()?;
+        self.status = input.u8()?;
+        self.oam_addr = input.u8()?;
+        input.bytes_into(&mut self.oam)?;
+        self.v = input.u16()?;
+        self.t = input.u16()?;
+        self.x = input.u8()?;
+        self.w = input.bool()?;
+        self.read_buffer = input.u8()?;
+        self.io_latch = input.u8()?;
+        input.bytes_into(&mut self.vram)?;
+        input.bytes_into(&mut self.palette)?;
+
+        self.scanline = input.u16()?;
+        self.dot = input.u16()?;
+        if self.scanline > PRE_RENDER_SCANLINE || self.dot >= DOTS_PER_SCANLINE {
+            return Err(format!(
+                "Save state has the PPU at scanline {}, dot {}",
+                self.scanline, self.dot
+            ));
+        }
+        self.odd_frame = input.bool()?;
+        self.frame_count = input.u64()?;
+
+        self.next_tile = input.u8()?;
+        self.next_attribute = input.u8()?;
+        self.next_pattern_low = input.u8()?;
+        self.next_pattern_high = input.u8()?;
+        self.pattern_shift_low = input.u16()?;
+        self.pattern_shift_high = input.u16()?;
+        self.attribute_shift_low = input.u16()?;
+        self.attribute_shift_high = input.u16()?;
+
+        for index in self.line_oam.iter_mut() {
+            *index = input.u8()? as usize;
+            if *index >= 64 {
+                return Err(format!("Save state has sprite {} on the line", index));
+            }
+        }
+        self.line_oam_count = (input.u8()? as usize).min(8);
+        for sprite in self.sprites.iter_mut() {
+            sprite.x = input.u8()?;
+            sprite.attributes = input.u8()?;
+            sprite.pattern_low = input.u8()?;
+            sprite.pattern_high = input.u8()?;
+        }
+        self.sprite_count = (input.u8()? as usize).min(8);
+        self.sprite_zero_in_line = input.bool()?;
+        self.next_sprite_zero_in_line = input.bool()?;
+
+        input.bytes_into(&mut self.frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::mapper::{nrom::Nrom, tests::cartridge};
+
+    fn write(ppu: &mut Ppu, mapper: &mut Nrom, addr: u16, data: u8) {
+        ppu.write_register(addr, data, Some(mapper));
+    }
+
+    fn read(ppu: &mut Ppu, mapper: &mut Nrom, addr: u16) -> u8 {
+        ppu.read_register(addr, Some(mapper))
+    }
+
+    fn set_vram_addr(ppu: &mut Ppu, mapper: &mut Nrom, addr: u16) {
+        write(ppu, mapper, 0x2006, (addr >> 8) as u8);
+        write(ppu, mapper, 0x2006, addr as u8);
+    }
+
+    fn run_frame(ppu: &mut Ppu, mapper: &mut Nrom) {
+        let frame = ppu.frame_count();
+        while ppu.frame_count() == frame {
+            ppu.step(Some(mapper));
+        }
+    }
+
+    #[test]
+    fn data_reads_are_buffered_except_palette() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(cartridge(0, 1, 0));
+        set_vram_addr(&mut ppu, &mut nrom, 0x2400);
+        write(&mut ppu, &mut nrom, 0x2007, 0x11);
+        write(&mut ppu, &mut nrom, 0x2007, 0x22);
+
+        set_vram_addr(&mut ppu, &mut nrom, 0x2400);
+        read(&mut ppu, &mut nrom, 0x2007);
+        assert_eq!(read(&mut ppu, &mut nrom, 0x2007), 0x11);
+        assert_eq!(read(&mut ppu, &mut nrom, 0x2007), 0x22);
+
+        /* $3F10 is a mirror of $3F00 */
+        set_vram_addr(&mut ppu, &mut nrom, 0x3F10);
+        write(&mut ppu, &mut nrom, 0x2007, 0x2A);
+        set_vram_addr(&mut ppu, &mut nrom, 0x3F00);
+        assert_eq!(read(&mut ppu, &mut nrom, 0x2007), 0x2A);
+    }
+
+    #[test]
+    fn nametables_follow_mirroring() {
+        assert_eq!(Ppu::nametable_index(0x2C05, Mirroring::Vertical), 0x405);
+        assert_eq!(Ppu::nametable_index(0x2805, Mirroring::Vertical), 0x005);
+        assert_eq!(Ppu::nametable_index(0x2405, Mirroring::Horizontal), 0x005);
+        assert_eq!(Ppu::nametable_index(0x2805, Mirroring::Horizontal), 0x405);
+        assert_eq!(
+            Ppu::nametable_index(0x2C05, Mirroring::SingleScreenLower),
+            0x005
+        );
+        assert_eq!(
+            Ppu::nametable_index(0x2005, Mirroring::SingleScreenUpper),
+            0x405
+        );
+        assert_eq!(Ppu::nametable_index(0x3C05, Mirroring::FourScreen), 0xC05);
+    }
+
+    #[test]
+    fn scroll_writes_fill_t_and_x() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(cartridge(0, 1, 0));
+        write(&mut ppu, &mut nrom, 0x2000, 0x02);
+        write(&mut ppu, &mut nrom, 0x2005, 0x7D);
+        assert_eq!((ppu.t, ppu.x, ppu.w), (0x080F, 5, true));
+        write(&mut ppu, &mut nrom, 0x2005, 0x5E);
+        assert_eq!((ppu.t, ppu.w), (0x696F, false));
+        write(&mut ppu, &mut nrom, 0x2006, 0x3D);
+        write(&mut ppu, &mut nrom, 0x2006, 0xF0);
+        assert_eq!((ppu.t, ppu.v), (0x3DF0, 0x3DF0));
+    }
+
+    #[test]
+    fn vblank_raises_nmi_until_status_read() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(cartridge(0, 1, 0));
+        write(&mut ppu, &mut nrom, 0x2000, 0x80);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!((ppu.scanline(), ppu.dot()), (241, 2));
+        assert!(ppu.nmi_line());
+        assert_eq!(read(&mut ppu, &mut nrom, 0x2002) & 0x80, 0x80);
+        assert!(!ppu.nmi_line());
+        assert_eq!(read(&mut ppu, &mut nrom, 0x2002) & 0x80, 0x00);
+    }
+
+    #[test]
+    fn odd_frames_are_one_dot_shorter_when_rendering() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(cartridge(0, 1, 0));
+        write(&mut ppu, &mut nrom, 0x2001, 0x08);
+        let mut dots = Vec::new();
+        for _ in 0..3 {
+            let mut count = 0;
+            let frame = ppu.frame_count();
+            while ppu.frame_count() == frame {
+                ppu.step(Some(&mut nrom));
+                count += 1;
+            }
+            dots.push(count);
+        }
+        assert_eq!(&dots[1..], &[341 * 262, 341 * 262 - 1]);
+    }
+
+    /* A solid tile 1 everywhere, and sprite 0 made of the same tile at (x, y) */
+    fn solid_scene(ppu: &mut Ppu, nrom: &mut Nrom, x: u8, y: u8) {
+        for row in 0..8 {
+            nrom.ppu_write(0x0010 + row, 0xFF);
+        }
+        set_vram_addr(ppu, nrom, 0x2000);
+        for _ in 0..960 {
+            write(ppu, nrom, 0x2007, 0x01);
+        }
+        set_vram_addr(ppu, nrom, 0x3F00);
+        for color in [0x0F, 0x16, 0x27, 0x30] {
+            write(ppu, nrom, 0x2007, color);
+        }
+        set_vram_addr(ppu, nrom, 0x0000);
+        write(ppu, nrom, 0x2003, 0x00);
+        for data in [y, 0x01, 0x00, x] {
+            write(ppu, nrom, 0x2004, data);
+        }
+    }
+
+    #[test]
+    fn renders_background_into_frame_buffer() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(cartridge(0, 1, 0));
+        solid_scene(&mut ppu, &mut nrom, 0, 0xF0);
+        write(&mut ppu, &mut nrom, 0x2001, 0x0A);
+        run_frame(&mut ppu, &mut nrom);
+        run_frame(&mut ppu, &mut nrom);
+
+        /* Palette entry 1 is $16 */
+        let (r, g, b) = PALETTE[0x16];
+        let frame = ppu.frame_buffer();
+        assert_eq!(frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        assert_eq!(&frame[0..3], &[r, g, b]);
+        let last = frame.len() - 3;
+        assert_eq!(&frame[last..], &[r, g, b]);
+    }
+
+    #[test]
+    fn sprite_zero_hits_only_over_background() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(cartridge(0, 1, 0));
+        solid_scene(&mut ppu, &mut nrom, 40, 30);
+        write(&mut ppu, &mut nrom, 0x2001, 0x1E);
+        run_frame(&mut ppu, &mut nrom);
+        assert_ne!(read(&mut ppu, &mut nrom, 0x2002) & 0x40, 0);
+
+        /* Background off: nothing to collide with */
+        write(&mut ppu, &mut nrom, 0x2001, 0x14);
+        run_frame(&mut ppu, &mut nrom);
+        assert_eq!(read(&mut ppu, &mut nrom, 0x2002) & 0x40, 0);
+    }
+
+    #[test]
+    fn more_than_eight_sprites_set_overflow() {
+        let mut ppu = Ppu::new();
+        let mut nrom = Nrom::new(cartridge(0, 1, 0));
+        write(&mut ppu, &mut nrom, 0x2003, 0x00);
+        for sprite in 0..64 {
+            let y = if sprite < 9 { 50 } else { 0xF0 };
+            for data in [y, 0x00, 0x00, sprite * 4] {
+                write(&mut ppu, &mut nrom, 0x2004, data);
+            }
+        }
+        write(&mut ppu, &mut nrom, 0x2001, 0x10);
+        run_frame(&mut ppu, &mut nrom);
+        assert_ne!(read(&mut ppu, &mut nrom, 0x2002) & 0x20, 0);
+    }
+
+    #[test]
+    fn refuses_states_outside_the_frame() {
+        let reload = |ppu: &Ppu| {
+            let mut out = StateWriter::new();
+            ppu.save_state(&mut out);
+            let data = out.into_bytes();
+            Ppu::new().load_state(&mut StateReader::new(&data))
+        };
+        let mut ppu = Ppu::new();
+        ppu.scanline = PRE_RENDER_SCANLINE;
+        ppu.dot = DOTS_PER_SCANLINE - 1;
+        assert!(reload(&ppu).is_ok());
+
+        ppu.dot = DOTS_PER_SCANLINE;
+        assert_eq!(
+            reload(&ppu),
+            Err("Save state has the PPU at scanline 261, dot 341".to_string())
+        );
+        ppu.dot = 0;
+        ppu.scanline = PRE_RENDER_SCANLINE + 1;
+        assert!(reload(&ppu).is_err());
+        ppu.scanline = 0;
+        ppu.line_oam[7] = 64;
+        assert!(reload(&ppu).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::VecDeque;
+
+pub const DEFAULT_REWIND_BUDGET: usize = 32 << 20;
+
+/* When a snapshot was taken */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewindMark {
+    pub frame: u64,
+    pub cycles: u64,
+    /* Movie frames played back or recorded by then */
+    pub movie_frame: usize,
+}
+
+struct Delta {
+    mark: RewindMark,
+    data: Vec<u8>,
+}
+
+/*
+ * A ring of save states taken every few frames. Only the newest one is
+ * kept whole; each older one is stored as the run-length coded XOR
+ * against its successor, which is mostly zeros from one frame to the
+ * next. Going back peels deltas off the newest end, and staying inside
+ * the memory budget drops them from the oldest.
+ */
+pub struct RewindBuffer {
+    interval: u64,
+    budget: usize,
+    latest: Option<(RewindMark, Vec<u8>)>,
+    older: VecDeque<Delta>,
+    used: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(interval: u64, budget: usize) -> Self {
+        RewindBuffer {
+            interval: interval.max(1),
+            budget,
+            latest: None,
+            older: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    pub fn wants(&self, frame: u64) -> bool {
+        frame.is_multiple_of(self.interval)
+    }
+
+    pub fn len(&self) -> usize {
+        self.older.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /* Bytes held, which stays within the budget except for the newest state */
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn oldest(&self) -> Option<RewindMark> {
+        self.older
+            .front()
+            .map(|delta| delta.mark)
+            .or(self.latest.as_ref().map(|(mark, _)| *mark))
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.older.clear();
+        self.used = 0;
+    }
+
+    pub fn push(&mut self, mark: RewindMark, state: Vec<u8>) {
+        if let Some((previous_mark, previous)) = self.latest.take() {
+            self.used -= previous.len();
+            let data = encode_delta(&previous, &state);
+            self.used += data.len();
+            self.older.push_back(Delta {
+                mark: previous_mark,
+                data,
+            });
+        }
+        self.used += state.len();
+        self.latest = Some((mark, state));
+
+        while self.used > self.budget {
+            match self.older.pop_front() {
+                Some(delta) => self.used -= delta.data.len(),
+                None => break,
+            }
+        }
+    }
+
+    /*
+     * Drops every snapshot newer than the last one `keep` accepts and
+     * returns that one, or leaves the buffer alone if none qualifies.
+     * Marks only grow from oldest to newest, so the search runs from the
+     * newest end.
+     */
+    pub fn seek(&mut self, keep: impl Fn(&RewindMark) -> bool) -> Option<(RewindMark, &[u8])> {
+        let depth = match &self.latest {
+            Some((mark, _)) if keep(mark) => 0,
+            Some(_) => {
+                1 + self
+                    .older
+                    .iter()
+                    .rev()
+                    .position(|delta| keep(&delta.mark))?
+            }
+            None => return None,
+        };
+
+        for _ in 0..depth {
+            let delta = self.older.pop_back().unwrap();
+            let (mark, state) = self.latest.as_mut().unwrap();
+            self.used -= state.len() + delta.data.len();
+            apply_delta(state, &delta.data);
+            self.used += state.len();
+            *mark = delta.mark;
+        }
+        self.latest
+            .as_ref()
+            .map(|(mark, state)| (*mark, state.as_slice()))
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/*
+ * What turns `newer` back into `older`: the length of `older`, then
+ * alternating runs of unchanged bytes (a count) and changed bytes (a
+ * count and the XOR of each).
+ */
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let len = older.len().max(newer.len());
+    let xor = |i: usize| older.get(i).unwrap_or(&0) ^ newer.get(i).unwrap_or(&0);
+    let mut out = Vec::new();
+    write_varint(&mut out, older.len());
+
+    let mut i = 0;
+    while i < len {
+        let same = (i..len).take_while(|&j| xor(j) == 0).count();
+        i += same;
+        let changed = (i..len).take_while(|&j| xor(j) != 0).count();
+        write_varint(&mut out, same);
+        write_varint(&mut out, changed);
+        out.extend((i..i + changed).map(xor));
+        i += changed;
+    }
+    out
+}
+
+fn apply_delta(state: &mut Vec<u8>, delta: &[u8]) {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+    state.resize(len.max(state.len()), 0);
+
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+        for byte in &mut state[i..i + changed] {
+            *byte ^= delta[pos];
+            pos += 1;
+        }
+        i += changed;
+    }
+    state.truncate(len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mark(frame: u64) -> RewindMark {
+        RewindMark {
+            frame,
+            cycles: frame * 100,
+            movie_frame: frame as usize,
+        }
+    }
+
+    fn state(frame: u64) -> Vec<u8> {
+        let mut state = vec![0xAA; 1000];
+        state[0] = frame as u8;
+        state[500..508].copy_from_slice(&frame.to_le_bytes());
+        state
+    }
+
+    #[test]
+    fn deltas_round_trip() {
+        let older = vec![1, 2, 3, 0, 0, 0, 7, 8];
+        for newer in [
+            vec![1, 2, 3, 0, 0, 0, 7, 8],
+            vec![9; 3],
+            vec![1, 5, 3, 0, 4, 0, 7, 8, 1, 1],
+        ] {
+            let mut restored = newer.clone();
+            apply_delta(&mut restored, &encode_delta(&older, &newer));
+            assert_eq!(restored, older);
+        }
+        assert_eq!(encode_delta(&older, &older), vec![8, 8, 0]);
+    }
+
+    #[test]
+    fn seeks_back_through_deltas() {
+        let mut buffer = RewindBuffer::new(1, usize::MAX);
+        for frame in 0..10 {
+            buffer.push(mark(frame), state(frame));
+        }
+        assert_eq!(buffer.len(), 10);
+        assert!(buffer.used() < 2000 + 9 * 30);
+
+        let (found, data) = buffer.seek(|mark| mark.cycles <= 650).unwrap();
+        assert_eq!(found, mark(6));
+        assert_eq!(data, &state(6)[..]);
+        assert_eq!(buffer.len(), 7);
+
+        assert!(buffer.seek(|mark| mark.frame > 100).is_none());
+        assert_eq!(buffer.len(), 7);
+        let (found, data) = buffer.seek(|mark| mark.frame == 0).unwrap();
+        assert_eq!((found, data), (mark(0), &state(0)[..]));
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[test]
+    fn stays_within_budget() {
+        let mut buffer = RewindBuffer::new(2, 1500);
+        assert!(buffer.wants(4) && !buffer.wants(5));
+        for frame in 0..100 {
+            buffer.push(mark(frame), state(frame));
+        }
+        assert!(buffer.used() <= 1500);
+        assert!(buffer.len() > 2);
+        let oldest = buffer.oldest().unwrap();
+        assert!(oldest.frame > 0);
+        let (found, data) = buffer.seek(|mark| *mark == oldest).unwrap();
+        assert_eq!(data, &state(found.frame)[..]);
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ * Save states are a flat little-endian byte stream. Every component
+ * writes its fields in a fixed order and reads them back in the same
+ * order, so any change to what gets saved has to bump STATE_VERSION.
+ */
+pub const STATE_MAGIC: &[u8; 4] = b"NESS";
+pub const STATE_VERSION: u16 = 1;
+
+pub trait Snapshot {
+    fn save_state(&self, out: &mut StateWriter);
+    fn load_state(&mut self, input: &mut StateReader) -> Result<(), String>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, value: f64) {
+        self.data.extend(value.to_le_bytes());
+    }
+
+    /* A length-prefixed block, for RAM and other buffers */
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.data.extend_from_slice(data);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or("Save state is truncated")?;
+        self.pos += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or("Save state is truncated")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /* A block that has to fill the buffer exactly, such as a RAM chip */
+    pub fn bytes_into(&mut self, out: &mut [u8]) -> Result<(), String> {
+        let bytes = self.bytes()?;
+        if bytes.len() != out.len() {
+            return Err(format!(
+                "Save state has {} bytes where {} were expected",
+                bytes.len(),
+                out.len()
+            ));
+        }
+        out.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), String> {
+        if self.pos != self.data.len() {
+            return Err("Save state has trailing data".to_string());
+        }
+        Ok(())
+    }
+}
+
+/* FNV-1a, to tell whether a state was saved with the same ROM loaded */
+pub fn fingerprint(blocks: &[&[u8]]) -> u64 {
+    let mut hash = 0xCBF2_9CE4_8422_2325u64;
+    for &byte in blocks.iter().flat_map(|block| block.iter()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let mut out = StateWriter::new();
+        out.u8(0x12);
+        out.bool(true);
+        out.u16(0x3456);
+        out.u32(0x789A_BCDE);
+        out.u64(u64::MAX - 1);
+        out.f32(-0.5);
+        out.f64(1e-9);
+        out.bytes(&[1, 2, 3]);
+        let data = out.into_bytes();
+
+        let mut input = StateReader::new(&data);
+        assert_eq!(input.u8(), Ok(0x12));
+        assert_eq!(input.bool(), Ok(true));
+        assert_eq!(input.u16(), Ok(0x3456));
+        assert_eq!(input.u32(), Ok(0x789A_BCDE));
+        assert_eq!(input.u64(), Ok(u64::MAX - 1));
+        assert_eq!(input.f32(), Ok(-0.5));
+        assert_eq!(input.f64(), Ok(1e-9));
+        let mut ram = [0; 3];
+        input.bytes_into(&mut ram).unwrap();
+        assert_eq!(ram, [1, 2, 3]);
+        assert_eq!(input.finish(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_short_and_mismatched_data() {
+        let mut input = StateReader::new(&[1, 0]);
+        assert_eq!(input.u32(), Err("Save state is truncated".to_string()));
+
+        let mut out = StateWriter::new();
+        out.bytes(&[0; 4]);
+        let data = out.into_bytes();
+        let mut ram = [0; 8];
+        assert_eq!(
+            StateReader::new(&data).bytes_into(&mut ram),
+            Err("Save state has 4 bytes where 8 were expected".to_string())
+        );
+        assert!(StateReader::new(&data).finish().is_err());
+    }
+}
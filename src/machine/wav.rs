@@ -0,0 +1,166 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleEncoding {
+    Pcm16,
+    Float32,
+}
+
+impl SampleEncoding {
+    pub fn parse(name: &str) -> Result<SampleEncoding, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "s16" | "pcm16" => Ok(SampleEncoding::Pcm16),
+            "f32" | "float" => Ok(SampleEncoding::Float32),
+            _ => Err(format!("Unknown sample encoding: {}", name)),
+        }
+    }
+
+    fn format_tag(&self) -> u16 {
+        match self {
+            SampleEncoding::Pcm16 => 1,
+            SampleEncoding::Float32 => 3,
+        }
+    }
+
+    fn bytes_per_sample(&self) -> u32 {
+        match self {
+            SampleEncoding::Pcm16 => 2,
+            SampleEncoding::Float32 => 4,
+        }
+    }
+}
+
+/*
+ * A mono RIFF WAVE file. Float files get the extended fmt chunk and the
+ * fact chunk that non-PCM formats are supposed to carry.
+ */
+pub fn write_wav(
+    out: &mut dyn Write,
+    samples: &[f32],
+    sample_rate: u32,
+    encoding: SampleEncoding,
+) -> std::io::Result<()> {
+    let bytes_per_sample = encoding.bytes_per_sample();
+    let data_size = samples.len() as u32 * bytes_per_sample;
+    let (fmt_size, fact_size) = match encoding {
+        SampleEncoding::Pcm16 => (16, 0),
+        SampleEncoding::Float32 => (18, 12),
+    };
+    let riff_size = 4 + (8 + fmt_size) + fact_size + (8 + data_size);
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&riff_size.to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&fmt_size.to_le_bytes())?;
+    out.write_all(&encoding.format_tag().to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * bytes_per_sample).to_le_bytes())?;
+    out.write_all(&(bytes_per_sample as u16).to_le_bytes())?;
+    out.write_all(&(bytes_per_sample as u16 * 8).to_le_bytes())?;
+    if encoding == SampleEncoding::Float32 {
+        out.write_all(&0u16.to_le_bytes())?;
+        out.write_all(b"fact")?;
+        out.write_all(&4u32.to_le_bytes())?;
+        out.write_all(&(samples.len() as u32).to_le_bytes())?;
+    }
+
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())?;
+    for &sample in samples {
+        match encoding {
+            SampleEncoding::Pcm16 => {
+                let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                out.write_all(&value.to_le_bytes())?;
+            }
+            SampleEncoding::Float32 => out.write_all(&sample.to_le_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+pub fn save_wav(
+    path: &str,
+    samples: &[f32],
+    sample_rate: u32,
+    encoding: SampleEncoding,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| format!("Failed to create {}: {}", path, err))?;
+    let mut out = BufWriter::new(file);
+    write_wav(&mut out, samples, sample_rate, encoding)
+        .and_then(|_| out.flush())
+        .map_err(|err| format!("Failed to write {}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_16_bit_pcm() {
+        let mut out = Vec::new();
+        write_wav(
+            &mut out,
+            &[0.0, 1.0, -1.0, 2.0, 0.5],
+            44_100,
+            SampleEncoding::Pcm16,
+        )
+        .unwrap();
+
+        assert_eq!(out.len(), 44 + 10);
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(u32_at(&out, 4), out.len() as u32 - 8);
+        assert_eq!(&out[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&out, 16), 16);
+        assert_eq!(u16_at(&out, 20), 1);
+        assert_eq!(u16_at(&out, 22), 1);
+        assert_eq!(u32_at(&out, 24), 44_100);
+        assert_eq!(u32_at(&out, 28), 88_200);
+        assert_eq!((u16_at(&out, 32), u16_at(&out, 34)), (2, 16));
+        assert_eq!(&out[36..40], b"data");
+        assert_eq!(u32_at(&out, 40), 10);
+
+        let samples: Vec<i16> = out[44..]
+            .chunks(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(samples, vec![0, 32767, -32767, 32767, 16384]);
+    }
+
+    #[test]
+    fn writes_float() {
+        let mut out = Vec::new();
+        write_wav(&mut out, &[0.25, -0.75], 48_000, SampleEncoding::Float32).unwrap();
+
+        assert_eq!(out.len(), 12 + 26 + 12 + 8 + 8);
+        assert_eq!(u32_at(&out, 4), out.len() as u32 - 8);
+        assert_eq!(u32_at(&out, 16), 18);
+        assert_eq!(u16_at(&out, 20), 3);
+        assert_eq!((u16_at(&out, 32), u16_at(&out, 34)), (4, 32));
+        assert_eq!(&out[38..42], b"fact");
+        assert_eq!(u32_at(&out, 46), 2);
+        assert_eq!(&out[50..54], b"data");
+        assert_eq!(f32::from_le_bytes(out[58..62].try_into().unwrap()), 0.25);
+        assert_eq!(f32::from_le_bytes(out[62..66].try_into().unwrap()), -0.75);
+    }
+
+    #[test]
+    fn parses_encodings() {
+        assert_eq!(SampleEncoding::parse("S16"), Ok(SampleEncoding::Pcm16));
+        assert_eq!(SampleEncoding::parse("f32"), Ok(SampleEncoding::Float32));
+        assert!(SampleEncoding::parse("u8").is_err());
+    }
+}
@@ -1,13 +1,9 @@
-// use nesemu::disassemble;
-mod machine;
-
-use machine::Machine;
+use nesemu::machine::Machine;
 
 fn main() {
     /* TODO: Refactor args to use advanced rust crates */
     let args: Vec<String> = std::env::args().collect();
-    let mut machine = Machine::new_from_args(&args);
-    if let Err(msg) = machine.run() {
+    if let Err(msg) = Machine::new_from_args(&args).and_then(|mut machine| machine.run()) {
         println!("Error: {}", msg);
     }
 